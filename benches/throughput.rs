@@ -0,0 +1,134 @@
+// UDP throughput benchmark.
+//
+// Run using: cargo bench --bench throughput
+//
+// For each UDP batch size, this starts the multiplexer (in a separate
+// process, as the multiplexer state is global) with a single server, sends
+// PUSH_DATA packets from a number of gateways and reports the number of
+// packets per second that are forwarded to the server.
+use std::env;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use tokio::net::UdpSocket;
+use tokio::time::timeout;
+
+use chirpstack_packet_multiplexer::config;
+use chirpstack_packet_multiplexer::packets::{GatewayId, PacketType};
+use chirpstack_packet_multiplexer::testing;
+
+const BATCH_SIZES: [usize; 3] = [1, 8, 32];
+const GATEWAYS: u64 = 50;
+const PACKETS_PER_GATEWAY: usize = 2000;
+const WINDOW: usize = 8;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    if let Some(pos) = args.iter().position(|v| v == "--batch-size") {
+        let batch_size: usize = args[pos + 1].parse().expect("Invalid batch size");
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(run(batch_size));
+        return;
+    }
+
+    for batch_size in BATCH_SIZES {
+        let out = Command::new(env::current_exe().unwrap())
+            .args(["--batch-size", &batch_size.to_string()])
+            .output()
+            .unwrap();
+
+        println!(
+            "udp_batch_size={:<3} {}",
+            batch_size,
+            String::from_utf8_lossy(&out.stdout).trim()
+        );
+    }
+}
+
+async fn run(batch_size: usize) {
+    // A plain socket is used for the server, such that receiving does not
+    // affect the measured throughput.
+    let server_sock = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+    let conf = config::Configuration {
+        multiplexer: config::Multiplexer {
+            bind: "127.0.0.1:0".into(),
+            udp_batch_size: batch_size,
+            servers: vec![config::Server {
+                server: server_sock.local_addr().unwrap().to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let addrs = testing::start(&conf).await.unwrap();
+    let start = Instant::now();
+
+    for gateway_id in 0..GATEWAYS {
+        let target = addrs[0];
+        tokio::spawn(async move {
+            let gw_sock = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            gw_sock.connect(target).await.unwrap();
+
+            let mut b = testing::packet(
+                PacketType::PushData,
+                0,
+                Some(GatewayId::from_bytes_be(gateway_id.to_be_bytes())),
+                br#"{"rxpk":[{"tmst":1000,"freq":868.1,"chan":0,"rfch":0,"stat":1,"modu":"LORA","datr":"SF7BW125","codr":"4/5","rssi":-82,"lsnr":6.3,"size":23,"data":"QAQDAgGAAQABsnS9Ug8k5xNjd0zIRA=="}]}"#,
+            );
+
+            // Keep at most WINDOW PUSH_DATA packets without PUSH_ACK in flight,
+            // so that the multiplexer capacity is measured (not socket buffer
+            // overflows).
+            let mut ack: [u8; 4] = [0; 4];
+            let mut sent = 0;
+            let mut in_flight = 0;
+
+            while sent < PACKETS_PER_GATEWAY || in_flight > 0 {
+                while in_flight < WINDOW && sent < PACKETS_PER_GATEWAY {
+                    b[1..3].copy_from_slice(&(sent as u16).to_be_bytes());
+                    gw_sock.send(&b).await.unwrap();
+                    sent += 1;
+                    in_flight += 1;
+                }
+
+                match timeout(Duration::from_millis(100), gw_sock.recv(&mut ack)).await {
+                    Ok(Ok(_)) => in_flight -= 1,
+                    _ => in_flight = 0,
+                }
+            }
+        });
+    }
+
+    let mut buffer: [u8; 65535] = [0; 65535];
+    let mut received = 0;
+    let mut last_received = start;
+
+    let sent = GATEWAYS as usize * PACKETS_PER_GATEWAY;
+
+    while received < sent {
+        match timeout(Duration::from_secs(1), server_sock.recv(&mut buffer)).await {
+            Ok(Ok(_)) => {
+                received += 1;
+                last_received = Instant::now();
+            }
+            _ => break,
+        }
+    }
+
+    let elapsed = last_received.duration_since(start);
+
+    println!(
+        "sent={} forwarded={} elapsed={:?} forwarded_per_second={:.0}",
+        sent,
+        received,
+        elapsed,
+        received as f64 / elapsed.as_secs_f64()
+    );
+}
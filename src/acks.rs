@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use tokio::net::UdpSocket;
+use tokio::sync::{OnceCell, RwLock};
+use tracing::{error, info, warn};
+
+use crate::capture;
+use crate::config::{self, AckMode};
+use crate::monitoring::{inc_gateway_ack_count, inc_gateway_udp_sent_count};
+use crate::packets::{GatewayId, PacketType};
+use crate::traits::PrintFullError;
+
+static TIMEOUTS: OnceCell<()> = OnceCell::const_new();
+static PASS_THROUGH: AtomicBool = AtomicBool::new(false);
+static PENDING: OnceCell<RwLock<HashMap<AckKey, PendingAck>>> = OnceCell::const_new();
+
+// Interval in which pending ACKs are checked for the timeout.
+const TIMEOUT_TICK: Duration = Duration::from_millis(10);
+
+// Gateway ID, ACK packet-type and token.
+type AckKey = (GatewayId, PacketType, u16);
+
+// PUSH_ACK or PULL_ACK which is sent to the gateway once the server(s) have
+// acknowledged the packet.
+struct PendingAck {
+    conf: config::Ack,
+    addr: SocketAddr,
+    socket: Arc<UdpSocket>,
+    data: [u8; 4],
+    created_at: Instant,
+    // Servers from which an ACK is required, None until the packet has been
+    // forwarded.
+    required: Option<Vec<String>>,
+    // Servers from which an ACK has been received. This can happen before the
+    // forwarder has registered the required servers.
+    acked: Vec<String>,
+}
+
+pub async fn setup(conf: &config::Ack, servers: &[config::Server]) -> Result<()> {
+    info!(mode = ?conf.mode, timeout_ms = conf.timeout_ms, send_on_timeout = conf.send_on_timeout, "Setting up gateway ACK mode");
+
+    if conf.mode == AckMode::Primary && !servers.iter().any(|v| v.primary) {
+        return Err(anyhow!(
+            "Gateway ACK mode primary requires at least one server with primary=true"
+        ));
+    }
+
+    PASS_THROUGH.store(is_pass_through(conf), Ordering::Relaxed);
+
+    if conf.mode != AckMode::Local {
+        TIMEOUTS
+            .get_or_init(|| async {
+                tokio::spawn(handle_timeouts());
+            })
+            .await;
+    }
+
+    Ok(())
+}
+
+/// Returns true if the ACKs to the gateway are sent after the server(s)
+/// acknowledged the packet, instead of directly by the multiplexer.
+pub fn is_pass_through(conf: &config::Ack) -> bool {
+    conf.mode != AckMode::Local
+}
+
+/// Adds the ACK (PUSH_ACK or PULL_ACK) for the received packet, it is sent
+/// once the server(s) have acknowledged the packet or on timeout.
+pub async fn add(
+    conf: &config::Ack,
+    gateway_id: GatewayId,
+    packet_type: PacketType,
+    addr: SocketAddr,
+    socket: Arc<UdpSocket>,
+    data: [u8; 4],
+) {
+    let token = u16::from_be_bytes([data[1], data[2]]);
+    let pending = PENDING
+        .get_or_init(|| async { RwLock::new(HashMap::new()) })
+        .await;
+
+    pending.write().await.insert(
+        (gateway_id, packet_type, token),
+        PendingAck {
+            conf: conf.clone(),
+            addr,
+            socket,
+            data,
+            created_at: Instant::now(),
+            required: None,
+            acked: Vec::new(),
+        },
+    );
+}
+
+/// Returns true if the ACK for the given packet is waiting for the server(s).
+pub async fn is_pending(gateway_id: GatewayId, packet_type: PacketType, token: u16) -> bool {
+    let pending = PENDING
+        .get_or_init(|| async { RwLock::new(HashMap::new()) })
+        .await;
+
+    pending
+        .read()
+        .await
+        .contains_key(&(gateway_id, packet_type, token))
+}
+
+/// Registers the servers to which the packet was forwarded, with for each
+/// server if it is a primary server. In case there is no server to wait for,
+/// the ACK is sent directly.
+pub async fn forwarded(
+    gateway_id: GatewayId,
+    packet_type: PacketType,
+    token: u16,
+    servers: Vec<(String, bool)>,
+) {
+    update(gateway_id, packet_type, token, |v| {
+        v.required = Some(match v.conf.mode {
+            AckMode::Primary => servers
+                .into_iter()
+                .filter(|(_, primary)| *primary)
+                .map(|(server, _)| server)
+                .collect(),
+            _ => servers.into_iter().map(|(server, _)| server).collect(),
+        })
+    })
+    .await;
+}
+
+/// Registers the ACK received from the server. The ACK is sent to the gateway
+/// in case this completes the required server ACKs.
+pub async fn acked(server: &str, gateway_id: GatewayId, packet_type: PacketType, token: u16) {
+    update(gateway_id, packet_type, token, |v| {
+        v.acked.push(server.to_string())
+    })
+    .await;
+}
+
+async fn update<F>(gateway_id: GatewayId, packet_type: PacketType, token: u16, f: F)
+where
+    F: FnOnce(&mut PendingAck),
+{
+    // In local mode there are no pending ACKs, avoid taking the write lock
+    // for every forwarded and acknowledged packet.
+    if !PASS_THROUGH.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let pending = PENDING
+        .get_or_init(|| async { RwLock::new(HashMap::new()) })
+        .await;
+    let mut pending = pending.write().await;
+
+    let key = (gateway_id, packet_type, token);
+    let Some(ack) = pending.get_mut(&key) else {
+        return;
+    };
+    f(ack);
+
+    if !is_complete(ack) {
+        return;
+    }
+
+    if let Some(ack) = pending.remove(&key) {
+        let result = if ack.required.as_ref().is_some_and(|v| v.is_empty()) {
+            "no_server"
+        } else {
+            "server_acked"
+        };
+
+        send(gateway_id, packet_type, &ack, result).await;
+    }
+}
+
+fn is_complete(ack: &PendingAck) -> bool {
+    let Some(required) = &ack.required else {
+        return false;
+    };
+
+    if required.is_empty() {
+        return true;
+    }
+
+    match ack.conf.mode {
+        AckMode::Any => required.iter().any(|v| ack.acked.contains(v)),
+        _ => required.iter().all(|v| ack.acked.contains(v)),
+    }
+}
+
+async fn send(gateway_id: GatewayId, packet_type: PacketType, ack: &PendingAck, result: &str) {
+    info!(packet_type = %packet_type, gateway_id = %gateway_id, "Sending UDP packet");
+
+    if let Err(e) = ack
+        .socket
+        .send_to(&ack.data, ack.addr)
+        .await
+        .context("Socket send")
+    {
+        error!(error = %e.full(), "Send ACK error");
+        return;
+    }
+
+    if let Ok(local_addr) = ack.socket.local_addr() {
+        capture::capture(Some(gateway_id), None, local_addr, ack.addr, &ack.data);
+    }
+
+    inc_gateway_udp_sent_count(gateway_id, packet_type).await;
+    inc_gateway_ack_count(gateway_id, packet_type, result).await;
+}
+
+async fn handle_timeouts() {
+    loop {
+        tokio::time::sleep(TIMEOUT_TICK).await;
+
+        let pending = PENDING
+            .get_or_init(|| async { RwLock::new(HashMap::new()) })
+            .await;
+
+        let timed_out: Vec<_> = {
+            let mut pending = pending.write().await;
+            let keys: Vec<_> = pending
+                .iter()
+                .filter(|(_, v)| v.created_at.elapsed() >= Duration::from_millis(v.conf.timeout_ms))
+                .map(|(k, _)| *k)
+                .collect();
+            keys.into_iter()
+                .filter_map(|k| pending.remove(&k).map(|v| (k, v)))
+                .collect()
+        };
+
+        for ((gateway_id, packet_type, token), ack) in timed_out {
+            if ack.conf.send_on_timeout {
+                warn!(gateway_id = %gateway_id, packet_type = %packet_type, token = token, "Server ACK timeout, sending ACK to gateway");
+                send(gateway_id, packet_type, &ack, "timeout_sent").await;
+            } else {
+                warn!(gateway_id = %gateway_id, packet_type = %packet_type, token = token, "Server ACK timeout, not sending ACK to gateway");
+                inc_gateway_ack_count(gateway_id, packet_type, "timeout_dropped").await;
+            }
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use std::fmt;
+use std::net::SocketAddr;
+
+use crate::config::GatewayAllow;
+use crate::packets::GatewayId;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RejectReason {
+    GatewayIdNotAllowed,
+    SourceNotAllowed,
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Validates the Gateway ID and source address against the allowlist.
+///
+/// If the allowlist is empty, all gateways are allowed. Otherwise the gateway
+/// is allowed if any of the rules matches both its Gateway ID and source
+/// address. Empty Gateway ID prefixes or source CIDRs of a rule match any
+/// Gateway ID or source address.
+pub fn check(
+    rules: &[GatewayAllow],
+    gateway_id: GatewayId,
+    addr: SocketAddr,
+) -> Result<(), RejectReason> {
+    if rules.is_empty() {
+        return Ok(());
+    }
+
+    let gw_id_le = gateway_id.as_bytes_le();
+    // Gateways connecting over IPv4 to a dual-stack socket have an
+    // IPv4-mapped IPv6 address.
+    let ip = addr.ip().to_canonical();
+    let mut reason = RejectReason::GatewayIdNotAllowed;
+
+    for rule in rules {
+        if !rule.gateway_id_prefixes.is_empty()
+            && !rule
+                .gateway_id_prefixes
+                .iter()
+                .any(|v| v.is_match(gw_id_le))
+        {
+            continue;
+        }
+
+        if rule.source_cidrs.is_empty() || rule.source_cidrs.iter().any(|v| v.contains(&ip)) {
+            return Ok(());
+        }
+
+        reason = RejectReason::SourceNotAllowed;
+    }
+
+    Err(reason)
+}
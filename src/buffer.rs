@@ -0,0 +1,204 @@
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Context, Result};
+use bytes::Bytes;
+
+use crate::config;
+use crate::packets::GatewayId;
+
+// Max. size of a buffered packet (max. UDP packet size).
+const MAX_ENTRY_SIZE: u32 = 65535;
+
+/// Buffered PUSH_DATA packet.
+pub struct Entry {
+    pub gateway_id: GatewayId,
+    pub stored_at: SystemTime,
+    pub data: Bytes,
+}
+
+/// Store-and-forward buffer of PUSH_DATA packets.
+///
+/// Packets are kept in memory up to max_memory_packets, additional packets
+/// are appended to the spill file (if configured). Packets are returned in
+/// the order in which they were stored.
+pub struct Buffer {
+    memory: VecDeque<Entry>,
+    max_packets: usize,
+    max_memory_packets: usize,
+    spill_file: String,
+    // Number of entries in the spill file which have not been read yet and
+    // the offset of the first of these entries.
+    spill_count: usize,
+    spill_offset: u64,
+}
+
+impl Buffer {
+    /// Returns a new buffer, or None if buffering is not configured. In case
+    /// the spill file contains packets of a previous run, these are loaded.
+    pub fn new(conf: &config::Buffer) -> Result<Option<Self>> {
+        if conf.max_packets == 0 {
+            return Ok(None);
+        }
+
+        let mut b = Buffer {
+            memory: VecDeque::new(),
+            max_packets: conf.max_packets,
+            max_memory_packets: if conf.spill_file.is_empty() {
+                conf.max_packets
+            } else {
+                conf.max_memory_packets.max(1)
+            },
+            spill_file: conf.spill_file.clone(),
+            spill_count: 0,
+            spill_offset: 0,
+        };
+
+        if !b.spill_file.is_empty() && Path::new(&b.spill_file).exists() {
+            let f = File::open(&b.spill_file).context("Open spill file")?;
+            let mut r = BufReader::new(f);
+            while read_entry(&mut r)?.is_some() {
+                b.spill_count += 1;
+            }
+        }
+
+        Ok(Some(b))
+    }
+
+    pub fn len(&self) -> usize {
+        self.memory.len() + self.spill_count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Stores the given entry. Returns false if the buffer is full.
+    pub fn push(&mut self, entry: Entry) -> Result<bool> {
+        if self.len() >= self.max_packets {
+            return Ok(false);
+        }
+
+        // Once packets have been spilled to disk, new packets must be spilled
+        // as well to keep the packets in order.
+        if self.spill_count == 0 && self.memory.len() < self.max_memory_packets {
+            self.memory.push_back(entry);
+            return Ok(true);
+        }
+
+        let f = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.spill_file)
+            .context("Open spill file")?;
+        let mut w = BufWriter::new(f);
+        write_entry(&mut w, &entry)?;
+        w.flush().context("Write spill file")?;
+        self.spill_count += 1;
+
+        Ok(true)
+    }
+
+    /// Re-queues the given entries (in order) in front of the oldest entry,
+    /// e.g. when these could not be replayed. These are kept in memory.
+    pub fn push_front(&mut self, entries: Vec<Entry>) {
+        for entry in entries.into_iter().rev() {
+            self.memory.push_front(entry);
+        }
+    }
+
+    /// Returns the oldest entry.
+    pub fn pop(&mut self) -> Result<Option<Entry>> {
+        if self.memory.is_empty() && self.spill_count != 0 {
+            self.load_spill_file()?;
+        }
+
+        Ok(self.memory.pop_front())
+    }
+
+    // Reads the next entries from the spill file into memory. Once all entries
+    // have been read, the spill file is truncated.
+    fn load_spill_file(&mut self) -> Result<()> {
+        let mut f = File::open(&self.spill_file).context("Open spill file")?;
+        f.seek(SeekFrom::Start(self.spill_offset))
+            .context("Seek spill file")?;
+        let mut r = BufReader::new(f);
+
+        while self.memory.len() < self.max_memory_packets && self.spill_count != 0 {
+            match read_entry(&mut r)? {
+                Some(entry) => {
+                    self.spill_offset += 20 + entry.data.len() as u64;
+                    self.spill_count -= 1;
+                    self.memory.push_back(entry);
+                }
+                None => {
+                    self.spill_count = 0;
+                }
+            }
+        }
+
+        if self.spill_count == 0 {
+            OpenOptions::new()
+                .write(true)
+                .open(&self.spill_file)
+                .and_then(|f| f.set_len(0))
+                .context("Truncate spill file")?;
+            self.spill_offset = 0;
+        }
+
+        Ok(())
+    }
+}
+
+// Entries are stored as Gateway ID (8 bytes), stored at (UNIX timestamp in
+// ms, 8 bytes), data length (4 bytes) and data, all big-endian.
+fn write_entry<W: Write>(w: &mut W, entry: &Entry) -> Result<()> {
+    let stored_at = entry
+        .stored_at
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+
+    w.write_all(&entry.gateway_id.as_bytes_be())?;
+    w.write_all(&stored_at.to_be_bytes())?;
+    w.write_all(&(entry.data.len() as u32).to_be_bytes())?;
+    w.write_all(&entry.data)?;
+
+    Ok(())
+}
+
+// Returns None at the end of the file, or in case of an incomplete entry.
+fn read_entry<R: Read>(r: &mut R) -> Result<Option<Entry>> {
+    let mut header = [0; 20];
+    match r.read_exact(&mut header) {
+        Ok(_) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e).context("Read spill file"),
+    }
+
+    let gateway_id = GatewayId::from_bytes_be(header[..8].try_into()?);
+    let stored_at = u64::from_be_bytes(header[8..16].try_into()?);
+    let size = u32::from_be_bytes(header[16..20].try_into()?);
+
+    // A PUSH_DATA never exceeds the max. UDP packet size, a larger size means
+    // the spill file is corrupt.
+    if size > MAX_ENTRY_SIZE {
+        return Err(anyhow!("Corrupt spill file entry, size: {}", size));
+    }
+
+    let mut data = vec![0; size as usize];
+    match r.read_exact(&mut data) {
+        Ok(_) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e).context("Read spill file"),
+    }
+
+    Ok(Some(Entry {
+        gateway_id,
+        stored_at: UNIX_EPOCH + Duration::from_millis(stored_at),
+        data: data.into(),
+    }))
+}
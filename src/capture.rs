@@ -0,0 +1,317 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Context, Result};
+use prometheus_client::metrics::counter::Counter;
+use tokio::sync::{mpsc, OnceCell};
+use tracing::{error, info, warn};
+
+use crate::config;
+use crate::monitoring;
+use crate::packets::GatewayId;
+use crate::traits::PrintFullError;
+
+static CAPTURE: OnceCell<Capture> = OnceCell::const_new();
+
+// pcap magic for nanosecond resolution timestamps.
+const PCAP_MAGIC: u32 = 0xa1b23c4d;
+// Raw IPv4 / IPv6 packets, without link-layer header.
+const LINKTYPE_RAW: u32 = 101;
+const SNAPLEN: u32 = 65535;
+// Max. number of records waiting to be written, records are dropped in case
+// the writer can not keep up.
+const QUEUE_SIZE: usize = 10000;
+// Min. interval between logging that records are dropped.
+const DROPPED_LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+struct Capture {
+    tx: mpsc::Sender<Record>,
+    gateway_id_prefixes: Vec<lrwn_filters::EuiPrefix>,
+    servers: Vec<String>,
+    dropped: Counter,
+    dropped_logged_at: Mutex<Option<Instant>>,
+}
+
+struct Record {
+    time: SystemTime,
+    src: SocketAddr,
+    dst: SocketAddr,
+    data: Vec<u8>,
+}
+
+pub async fn setup(conf: &config::Capture) -> Result<()> {
+    if conf.file.is_empty() {
+        return Ok(());
+    }
+
+    info!(file = %conf.file, max_file_size = conf.max_file_size, max_files = conf.max_files, "Setting up traffic capture");
+
+    let writer = Writer::new(conf)?;
+    let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+    // The writer runs for the lifetime of the process, a dedicated thread is
+    // used as a blocking task would prevent the runtime from shutting down.
+    std::thread::Builder::new()
+        .name("capture".into())
+        .spawn(move || writer.run(rx))
+        .context("Spawn capture thread")?;
+
+    let dropped = Counter::default();
+    monitoring::register(
+        "capture_dropped_count",
+        "Number of capture records dropped because the capture queue was full",
+        dropped.clone(),
+    )
+    .await;
+
+    CAPTURE
+        .set(Capture {
+            tx,
+            gateway_id_prefixes: conf.gateway_id_prefixes.clone(),
+            servers: conf.servers.clone(),
+            dropped,
+            dropped_logged_at: Mutex::new(None),
+        })
+        .map_err(|_| anyhow!("Traffic capture is already configured"))
+}
+
+pub fn is_enabled() -> bool {
+    CAPTURE.get().is_some()
+}
+
+/// Captures the datagram in case capturing is enabled and the Gateway ID and
+/// server (None for gateway-side traffic) match the capture filters.
+pub fn capture(
+    gateway_id: Option<GatewayId>,
+    server: Option<&str>,
+    src: SocketAddr,
+    dst: SocketAddr,
+    data: &[u8],
+) {
+    let Some(capture) = CAPTURE.get() else {
+        return;
+    };
+
+    if !capture.gateway_id_prefixes.is_empty() {
+        let Some(gateway_id) = gateway_id else {
+            return;
+        };
+        let gw_id_le = gateway_id.as_bytes_le();
+        if !capture
+            .gateway_id_prefixes
+            .iter()
+            .any(|v| v.is_match(gw_id_le))
+        {
+            return;
+        }
+    }
+
+    if let Some(server) = server {
+        if !capture.servers.is_empty() && !capture.servers.iter().any(|v| v == server) {
+            return;
+        }
+    }
+
+    let record = Record {
+        time: SystemTime::now(),
+        src,
+        dst,
+        data: data.to_vec(),
+    };
+
+    if capture.tx.try_send(record).is_err() {
+        capture.dropped.inc();
+
+        let mut logged_at = capture
+            .dropped_logged_at
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if logged_at
+            .map(|v| v.elapsed() >= DROPPED_LOG_INTERVAL)
+            .unwrap_or(true)
+        {
+            warn!(
+                dropped = capture.dropped.get(),
+                "Capture queue is full, dropping records"
+            );
+            *logged_at = Some(Instant::now());
+        }
+    }
+}
+
+struct Writer {
+    file: String,
+    max_file_size: u64,
+    max_files: usize,
+    w: BufWriter<File>,
+    size: u64,
+}
+
+impl Writer {
+    fn new(conf: &config::Capture) -> Result<Self> {
+        let mut w = Writer {
+            file: conf.file.clone(),
+            max_file_size: conf.max_file_size,
+            max_files: conf.max_files.max(1),
+            w: BufWriter::new(File::create(&conf.file).context("Create capture file")?),
+            size: 0,
+        };
+
+        w.write_header()?;
+        Ok(w)
+    }
+
+    fn run(mut self, mut rx: mpsc::Receiver<Record>) {
+        while let Some(record) = rx.blocking_recv() {
+            if let Err(e) = self.write_record(&record) {
+                error!(error = %e.full(), "Write capture record error");
+            }
+
+            // Flush once there are no more records waiting.
+            if rx.is_empty() {
+                if let Err(e) = self.w.flush() {
+                    error!(error = %e, "Flush capture file error");
+                }
+            }
+        }
+    }
+
+    fn write_header(&mut self) -> Result<()> {
+        let mut b = Vec::with_capacity(24);
+        b.extend_from_slice(&PCAP_MAGIC.to_le_bytes());
+        b.extend_from_slice(&2u16.to_le_bytes());
+        b.extend_from_slice(&4u16.to_le_bytes());
+        b.extend_from_slice(&0i32.to_le_bytes());
+        b.extend_from_slice(&0u32.to_le_bytes());
+        b.extend_from_slice(&(SNAPLEN + 48).to_le_bytes());
+        b.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+
+        self.w.write_all(&b).context("Write capture file")?;
+        self.size = b.len() as u64;
+
+        Ok(())
+    }
+
+    fn write_record(&mut self, record: &Record) -> Result<()> {
+        if self.max_file_size != 0 && self.size >= self.max_file_size {
+            self.rotate()?;
+        }
+
+        let packet = ip_udp_packet(record.src, record.dst, &record.data);
+        let time = record.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        let mut b = Vec::with_capacity(16 + packet.len());
+        b.extend_from_slice(&(time.as_secs() as u32).to_le_bytes());
+        b.extend_from_slice(&time.subsec_nanos().to_le_bytes());
+        b.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        b.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        b.extend_from_slice(&packet);
+
+        self.w.write_all(&b).context("Write capture file")?;
+        self.size += b.len() as u64;
+
+        Ok(())
+    }
+
+    // Renames file.N to file.N+1 (removing the oldest) and file to file.1,
+    // then starts a new file.
+    fn rotate(&mut self) -> Result<()> {
+        self.w.flush().context("Flush capture file")?;
+
+        let rotated = |i: usize| format!("{}.{}", self.file, i);
+        let _ = fs::remove_file(rotated(self.max_files - 1));
+        for i in (1..self.max_files - 1).rev() {
+            let _ = fs::rename(rotated(i), rotated(i + 1));
+        }
+        if self.max_files > 1 {
+            fs::rename(&self.file, rotated(1)).context("Rotate capture file")?;
+        }
+
+        self.w = BufWriter::new(File::create(&self.file).context("Create capture file")?);
+        self.write_header()
+    }
+}
+
+// Returns the datagram with synthesized IP and UDP headers. In case the
+// address families of src and dst differ, IPv4-mapped IPv6 addresses are
+// used.
+fn ip_udp_packet(src: SocketAddr, dst: SocketAddr, data: &[u8]) -> Vec<u8> {
+    let data = &data[..data.len().min(SNAPLEN as usize)];
+    let udp_len = (8 + data.len()) as u16;
+
+    let mut udp = Vec::with_capacity(udp_len as usize);
+    udp.extend_from_slice(&src.port().to_be_bytes());
+    udp.extend_from_slice(&dst.port().to_be_bytes());
+    udp.extend_from_slice(&udp_len.to_be_bytes());
+    udp.extend_from_slice(&[0, 0]);
+    udp.extend_from_slice(data);
+
+    match (src.ip(), dst.ip()) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            // The UDP checksum is optional for IPv4 and left 0.
+            let mut b = Vec::with_capacity(20 + udp.len());
+            b.extend_from_slice(&[0x45, 0x00]);
+            b.extend_from_slice(&(20 + udp_len).to_be_bytes());
+            b.extend_from_slice(&[0x00, 0x00, 0x40, 0x00, 64, 17, 0x00, 0x00]);
+            b.extend_from_slice(&src.octets());
+            b.extend_from_slice(&dst.octets());
+
+            let checksum = checksum(&b, 0);
+            b[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+            b.extend_from_slice(&udp);
+            b
+        }
+        (src, dst) => {
+            let src = to_ipv6(src).octets();
+            let dst = to_ipv6(dst).octets();
+
+            // The UDP checksum is mandatory for IPv6 and covers a
+            // pseudo-header.
+            let mut pseudo = Vec::with_capacity(40);
+            pseudo.extend_from_slice(&src);
+            pseudo.extend_from_slice(&dst);
+            pseudo.extend_from_slice(&u32::from(udp_len).to_be_bytes());
+            pseudo.extend_from_slice(&[0, 0, 0, 17]);
+            let checksum = match checksum(&udp, sum(&pseudo)) {
+                0 => 0xffff,
+                v => v,
+            };
+            udp[6..8].copy_from_slice(&checksum.to_be_bytes());
+
+            let mut b = Vec::with_capacity(40 + udp.len());
+            b.extend_from_slice(&[0x60, 0x00, 0x00, 0x00]);
+            b.extend_from_slice(&udp_len.to_be_bytes());
+            b.extend_from_slice(&[17, 64]);
+            b.extend_from_slice(&src);
+            b.extend_from_slice(&dst);
+            b.extend_from_slice(&udp);
+            b
+        }
+    }
+}
+
+fn to_ipv6(ip: IpAddr) -> std::net::Ipv6Addr {
+    match ip {
+        IpAddr::V4(v) => v.to_ipv6_mapped(),
+        IpAddr::V6(v) => v,
+    }
+}
+
+// Returns the (not folded) 16-bit one's complement sum of the data.
+fn sum(b: &[u8]) -> u32 {
+    b.chunks(2)
+        .map(|v| u32::from(u16::from_be_bytes([v[0], *v.get(1).unwrap_or(&0)])))
+        .sum()
+}
+
+// Returns the internet checksum of the data, with the initial sum added.
+fn checksum(b: &[u8], initial: u32) -> u16 {
+    let mut sum = initial + sum(b);
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
@@ -27,6 +27,52 @@ pub fn run(config: &Configuration) {
   # data from the gateways.
   bind="{{ multiplexer.bind }}"
 
+  # Additional interface:port UDP binds.
+  #
+  # This can be used to receive data from the gateways on multiple ports or
+  # on both IPv4 and IPv6, e.g. ["0.0.0.0:1680", "[::]:1700"]. Downlinks are
+  # sent from the interface:port to which the gateway sent its last
+  # PULL_DATA.
+  binds=[
+    {{#each multiplexer.binds}}
+    "{{this}}",
+    {{/each}}
+  ]
+
+  # Interface:port of UDP bind for downlink.
+  #
+  # If set, gateways configured with a separate downlink port (serv_port_down)
+  # send their PULL_DATA and TX_ACK packets to this interface:port and
+  # downlinks are sent from this interface:port. If not set, the above bind
+  # is used for both uplink and downlink.
+  bind_down="{{ multiplexer.bind_down }}"
+
+  # Number of sockets per bind.
+  #
+  # If set to a value greater than 1, the given number of sockets are opened
+  # for each of the above binds using SO_REUSEPORT, each handled by its own
+  # task. The kernel distributes the received packets over these sockets,
+  # packets from the same gateway address are always received on the same
+  # socket.
+  sockets_per_bind={{ multiplexer.sockets_per_bind }}
+
+  # UDP batch size.
+  #
+  # On Linux, up to the given number of UDP datagrams are received from the
+  # gateways using a single syscall (recvmmsg) and the PUSH_ACK / PULL_ACK
+  # responses to these are sent using a single syscall (sendmmsg). Set this to
+  # 1 to disable batching.
+  udp_batch_size={{ multiplexer.udp_batch_size }}
+
+  # Socket receive and send buffer sizes (bytes).
+  #
+  # If set, SO_RCVBUF and SO_SNDBUF are set to the given size for the above
+  # binds, e.g. to handle uplink bursts of many gateways. If set to 0, the OS
+  # default is used. Note that the OS might limit the size (see
+  # net.core.rmem_max and net.core.wmem_max).
+  recv_buffer_size={{ multiplexer.recv_buffer_size }}
+  send_buffer_size={{ multiplexer.send_buffer_size }}
+
   # Servers to forward gateway data to.
   #
   # Example configuration:
@@ -35,11 +81,80 @@ pub fn run(config: &Configuration) {
   #   # Hostname:port of the server.
   #   server="example.com:1700"
 
+  #   # Uplink and downlink port.
+  #   #
+  #   # If set, the port of the server hostname:port is replaced by the given
+  #   # port for uplink (PUSH_DATA) and / or downlink (PULL_DATA and TX_ACK),
+  #   # for servers that use a different port for each. In that case a
+  #   # separate socket is used for uplink and downlink. If set to 0, the
+  #   # port of the server hostname:port is used.
+  #   port_up=0
+  #   port_down=0
+
+  #   # Max. number of sockets.
+  #   #
+  #   # The Multiplexer uses a separate UDP socket per gateway for forwarding
+  #   # data to this server (two sockets per gateway in case port_up and
+  #   # port_down are different). If set, no new sockets are opened for this
+  #   # server once this limit is reached, data of gateways without socket is
+  #   # then not forwarded to this server. This can be used to stay within
+  #   # the file-descriptor limit (ulimit -n). Failed socket creations are
+  #   # counted by the server_socket_error_count metric and the number of
+  #   # open sockets is exposed by the server_sockets metric. If set to 0,
+  #   # the number of sockets is not limited.
+  #   max_sockets=0
+
+  #   # Local port range.
+  #   #
+  #   # If set, the sockets for this server are bound to a port within the
+  #   # given range (inclusive), e.g. to configure upstream firewalls. Note
+  #   # that this limits the number of sockets to the size of the range.
+  #   local_port_range=[20000, 20999]
+
+  #   # Local address.
+  #   #
+  #   # If set, the sockets for this server are bound to the given IP address,
+  #   # e.g. on multi-homed hosts. If not set, 0.0.0.0 is used.
+  #   local_address="10.8.0.2"
+
+  #   # Interface.
+  #   #
+  #   # If set, the sockets for this server are bound to the given network
+  #   # interface (SO_BINDTODEVICE), e.g. to send the traffic to this server
+  #   # over a VPN interface.
+  #   interface="wg0"
+
+  #   # DSCP value (0 - 63).
+  #   #
+  #   # If set, the packets sent to this server are marked with the given DSCP
+  #   # value, e.g. 46 for Expedited Forwarding.
+  #   dscp=0
+
+  #   # Socket receive and send buffer sizes (bytes).
+  #   #
+  #   # If set to 0, the OS default is used.
+  #   recv_buffer_size=0
+  #   send_buffer_size=0
+
   #   # Only allow uplink.
   #   #
   #   # If set to true, any downlink will be discarded.
   #   uplink_only=false
 
+  #   # Primary server.
+  #   #
+  #   # In the primary ACK mode (see [gateways.ack]), the ACKs to the gateway
+  #   # are sent once the primary server(s) acknowledged the packet.
+  #   primary=false
+
+  #   # Server unreachable timeout (seconds).
+  #   #
+  #   # If set, the server is considered unreachable in case PUSH_DATA or
+  #   # PULL_DATA was sent, but no PUSH_ACK or PULL_ACK was received within
+  #   # this time. This emits the server_unreachable and server_recovered
+  #   # events (see [[webhook]]). If set to 0, this is disabled.
+  #   unreachable_after_secs=0
+
   #   # Gateway ID prefix filters.
   #   #
   #   # If not set, data of all gateways will be forwarded. If set, only data
@@ -49,16 +164,465 @@ pub fn run(config: &Configuration) {
   #   # * "0102030405060708/32": Exact match (all 32 bits of the filter must match)
   #   # * "0102030400000000/16": All gateway IDs starting with "01020304" (filter on 16 most significant bits)
   #   gateway_id_prefixes=[]
-  {{#each multiplexer.servers}}
+
+  #   # NetID filters.
+  #   #
+  #   # If not set, all data uplinks will be forwarded. If set, only data uplinks
+  #   # of which the DevAddr matches one of the given NetIDs will be forwarded.
+  #   # Non-matching rxpk objects are removed from the PUSH_DATA before it is
+  #   # sent to this server.
+  #   #
+  #   # Example:
+  #   # * "000013": Forward data uplinks with DevAddr prefix 26000000/7
+  #   net_ids=[]
+
+  #   # JoinEUI prefix filters.
+  #   #
+  #   # If not set, all join-requests will be forwarded. If set, only
+  #   # join-requests with a matching JoinEUI will be forwarded.
+  #   #
+  #   # Example:
+  #   # * "0102030400000000/32": All JoinEUIs starting with "01020304"
+  #   join_eui_prefixes=[]
+
+  #   # Gateway ID prefix rewrite.
+  #   #
+  #   # If set, the most significant bits of the Gateway ID are replaced by the
+  #   # given prefix before the data is forwarded to this server. Downlinks
+  #   # received from this server are sent to the gateway with the original
+  #   # Gateway ID.
+  #   #
+  #   # In the example below, Gateway ID 0102030405060708 is presented to the
+  #   # server as aabbccdd05060708.
+  #   gateway_id_prefix_rewrite="aabbccdd00000000/32"
+
+  #   # Gateway ID mapping.
+  #   #
+  #   # This maps the Gateway ID (key) to the Gateway ID presented to this
+  #   # server (value). The mapping has priority over the prefix rewrite.
+  #   [multiplexer.server.gateway_id_mapping]
+  #     "0102030405060708"="1112131415161718"
+
+  #   # Inject gateway location.
+  #   #
+  #   # If set, the location from the gateway metadata file is added to the
+  #   # gateway statistics (stat) in case the gateway does not report its
+  #   # location itself, e.g. for gateways without GPS.
+  #   inject_location=false
+
+  #   # Rate limit.
+  #   #
+  #   # If set, the PUSH_DATA forwarded to this server (for all gateways) is
+  #   # limited to the given number of packets and / or bytes per second. The
+  #   # burst is the maximum number of packets / bytes that can be sent at once
+  #   # (defaults to the per second value, for bytes at least 65535, the max.
+  #   # UDP packet size). A configured byte burst must be at least 65535.
+  #   # PULL_DATA and TX_ACK packets are not limited. Dropped packets are counted by the
+  #   # server_udp_throttled_count metric.
+  #   [multiplexer.server.rate_limit]
+  #     packets_per_second=100
+  #     packet_burst=200
+  #     bytes_per_second=0
+  #     byte_burst=0
+
+  #   # PUSH_DATA retransmission.
+  #   #
+  #   # If enabled, PUSH_DATA which is not acknowledged by the server (PUSH_ACK)
+  #   # within the timeout is sent again with the same token, until it is
+  #   # acknowledged or the max. number of retries is reached. Retransmissions
+  #   # are exposed by the server_retransmit_count metric. In case the
+  #   # store-and-forward buffer is enabled, PUSH_DATA which is still not
+  #   # acknowledged after the last retry is buffered.
+  #   [multiplexer.server.retransmit]
+
+  #     # Max. number of retries (0 = retransmission disabled).
+  #     max_retries=0
+
+  #     # Timeout (milliseconds) to wait for the PUSH_ACK before retrying.
+  #     timeout_ms=500
+
+  #   # Store-and-forward buffer.
+  #   #
+  #   # If enabled, PUSH_DATA which is not acknowledged by the server (PUSH_ACK)
+  #   # within the ACK timeout is stored in the buffer and the server is
+  #   # considered offline. Once the server acknowledges PUSH_DATA again, the
+  #   # buffered PUSH_DATA is replayed in order, limited to the given number of
+  #   # packets per second. This is intended for uplink-only servers (e.g.
+  #   # analytics), as replayed uplinks can no longer be answered by a
+  #   # downlink. The buffer is exposed by the server_buffer_packets and
+  #   # server_buffer_count metrics.
+  #   [multiplexer.server.buffer]
+
+  #     # Max. number of buffered PUSH_DATA (0 = buffering disabled).
+  #     #
+  #     # In case the buffer is full, the PUSH_DATA is dropped.
+  #     max_packets=0
+
+  #     # Max. number of PUSH_DATA kept in memory.
+  #     #
+  #     # PUSH_DATA exceeding this number are appended to the spill file. This
+  #     # is ignored in case no spill file is configured.
+  #     max_memory_packets=1000
+
+  #     # Spill file.
+  #     #
+  #     # If set, PUSH_DATA exceeding max_memory_packets are stored in this
+  #     # file. PUSH_DATA stored in this file are replayed after a restart.
+  #     spill_file="/var/lib/chirpstack-packet-multiplexer/server1.buffer"
+
+  #     # Max. age (seconds) of buffered PUSH_DATA.
+  #     #
+  #     # Older PUSH_DATA is discarded instead of replayed.
+  #     max_age_secs=3600
+
+  #     # ACK timeout (milliseconds).
+  #     ack_timeout_ms=1000
+
+  #     # Max. number of replayed PUSH_DATA per second.
+  #     replay_packets_per_second=10
+
+  #   # Rxpk filters.
+  #   #
+  #   # These filters are applied to each rxpk object (received uplink) within
+  #   # the PUSH_DATA. Rxpk objects that do not match are not forwarded to this
+  #   # server. Filters that are not set are ignored. If a filter is set but the
+  #   # rxpk object does not contain the field, it does not match.
+  #   [multiplexer.server.rxpk_filters]
+
+  #     # CRC status.
+  #     #
+  #     # Valid values: 1 (CRC OK), -1 (CRC error), 0 (no CRC).
+  #     crc_status=[1]
+
+  #     # Minimum RSSI (dBm).
+  #     min_rssi=-120
+
+  #     # Minimum LoRa SNR (dB).
+  #     min_snr=-10.0
+
+  #     # Frequency ranges (Hz, inclusive).
+  #     #
+  #     # Example for US915 sub-band 2:
+  #     frequency_ranges=[[902300000, 903700000]]
+
+  #     # Modulations.
+  #     #
+  #     # Valid values: "LORA", "FSK".
+  #     modulations=[]
+
+  #     # Data-rates (as reported in the datr field).
+  #     #
+  #     # Example: ["SF7BW125", "SF8BW125"]
+  #     data_rates=[]
+
+  #     # Spreading-factors (LoRa only).
+  #     #
+  #     # Example: [7, 8, 9]
+  #     spreading_factors=[]
+
+  #     # Drop PUSH_DATA without rxpk objects.
+  #     #
+  #     # If set, PUSH_DATA packets containing only gateway statistics (or of
+  #     # which all rxpk objects were filtered) are not forwarded.
+  #     drop_stat_only=false
+
+  #   # Privacy settings.
+  #   #
+  #   # These transformations are applied to the PUSH_DATA before it is sent to
+  #   # this server, e.g. to limit the gateway metadata shared with third-party
+  #   # servers.
+  #   [multiplexer.server.privacy]
+
+  #     # Remove the gateway location (lati, long and alti) from stat.
+  #     remove_location=false
+
+  #     # Round the gateway latitude and longitude to the given number of
+  #     # decimals (ignored when remove_location is set).
+  #     location_decimals=2
+
+  #     # Remove the GPS time (tmms) from rxpk.
+  #     remove_tmms=false
+
+  #     # Remove the fine-timestamp (ftime and etime) from rxpk and rsig.
+  #     remove_ftime=false
+
+  #     # Remove the per-antenna signal information (rsig) from rxpk.
+  #     remove_rsig=false
+
+  #     # Round the RSSI values (dBm) to a multiple of the given step.
+  #     rssi_step=5
+
+  #     # Round the SNR values (dB) to a multiple of the given step.
+  #     snr_step=2.5
+  {{#each multiplexer.server}}
   [[multiplexer.server]]
     server="{{this.server}}"
+    port_up={{this.port_up}}
+    port_down={{this.port_down}}
+    max_sockets={{this.max_sockets}}
+    {{#if this.local_port_range}}
+    local_port_range=[{{this.local_port_range.[0]}}, {{this.local_port_range.[1]}}]
+    {{/if}}
+    {{#if this.local_address}}
+    local_address="{{this.local_address}}"
+    {{/if}}
+    interface="{{this.interface}}"
+    dscp={{this.dscp}}
+    recv_buffer_size={{this.recv_buffer_size}}
+    send_buffer_size={{this.send_buffer_size}}
     uplink_only={{this.uplink_only}}
+    primary={{this.primary}}
+    unreachable_after_secs={{this.unreachable_after_secs}}
     gateway_id_prefixes=[
       {{#each this.gateway_id_prefixes}}
       "{{this}}",
       {{/each}}
     ]
+    net_ids=[
+      {{#each this.net_ids}}
+      "{{this}}",
+      {{/each}}
+    ]
+    join_eui_prefixes=[
+      {{#each this.join_eui_prefixes}}
+      "{{this}}",
+      {{/each}}
+    ]
+    {{#if this.gateway_id_prefix_rewrite}}
+    gateway_id_prefix_rewrite="{{this.gateway_id_prefix_rewrite}}"
+    {{/if}}
+
+    inject_location={{this.inject_location}}
+
+    [multiplexer.server.gateway_id_mapping]
+      {{#each this.gateway_id_mapping}}
+      "{{@key}}"="{{this}}"
+      {{/each}}
+
+    [multiplexer.server.rate_limit]
+      packets_per_second={{this.rate_limit.packets_per_second}}
+      packet_burst={{this.rate_limit.packet_burst}}
+      bytes_per_second={{this.rate_limit.bytes_per_second}}
+      byte_burst={{this.rate_limit.byte_burst}}
+
+    [multiplexer.server.retransmit]
+      max_retries={{this.retransmit.max_retries}}
+      timeout_ms={{this.retransmit.timeout_ms}}
+
+    [multiplexer.server.buffer]
+      max_packets={{this.buffer.max_packets}}
+      max_memory_packets={{this.buffer.max_memory_packets}}
+      spill_file="{{this.buffer.spill_file}}"
+      max_age_secs={{this.buffer.max_age_secs}}
+      ack_timeout_ms={{this.buffer.ack_timeout_ms}}
+      replay_packets_per_second={{this.buffer.replay_packets_per_second}}
+
+    [multiplexer.server.rxpk_filters]
+      crc_status=[{{#each this.rxpk_filters.crc_status}}{{this}}, {{/each}}]
+      {{#if this.rxpk_filters.min_rssi includeZero=true}}
+      min_rssi={{this.rxpk_filters.min_rssi}}
+      {{/if}}
+      {{#if this.rxpk_filters.min_snr includeZero=true}}
+      min_snr={{this.rxpk_filters.min_snr}}
+      {{/if}}
+      frequency_ranges=[
+        {{#each this.rxpk_filters.frequency_ranges}}
+        [{{this.[0]}}, {{this.[1]}}],
+        {{/each}}
+      ]
+      modulations=[
+        {{#each this.rxpk_filters.modulations}}
+        "{{this}}",
+        {{/each}}
+      ]
+      data_rates=[
+        {{#each this.rxpk_filters.data_rates}}
+        "{{this}}",
+        {{/each}}
+      ]
+      spreading_factors=[{{#each this.rxpk_filters.spreading_factors}}{{this}}, {{/each}}]
+      drop_stat_only={{this.rxpk_filters.drop_stat_only}}
+
+    [multiplexer.server.privacy]
+      remove_location={{this.privacy.remove_location}}
+      {{#if this.privacy.location_decimals includeZero=true}}
+      location_decimals={{this.privacy.location_decimals}}
+      {{/if}}
+      remove_tmms={{this.privacy.remove_tmms}}
+      remove_ftime={{this.privacy.remove_ftime}}
+      remove_rsig={{this.privacy.remove_rsig}}
+      {{#if this.privacy.rssi_step}}
+      rssi_step={{this.privacy.rssi_step}}
+      {{/if}}
+      {{#if this.privacy.snr_step}}
+      snr_step={{this.privacy.snr_step}}
+      {{/if}}
+
+  {{/each}}
+
+
+# Gateway configuration.
+[gateways]
+
+  # Gateway metadata file.
+  #
+  # If set, this file is used to provide gateway metadata (name, location and
+  # tags). The name is added to the logs and the name and tags are exposed
+  # using the gateway_info metric. The location can be injected into the
+  # gateway statistics per server (see inject_location).
+  #
+  # Example file content:
+  #
+  # [[gateway]]
+  #   gateway_id="0102030405060708"
+  #   name="office-rooftop"
+  #   latitude=52.3740
+  #   longitude=4.8897
+  #   altitude=12
+  #
+  #   [gateway.tags]
+  #     site="amsterdam"
+  metadata_file="{{ gateways.metadata_file }}"
+
+  # Gateway address change policy.
+  #
+  # This defines what happens when a PULL_DATA is received for a known
+  # Gateway ID from a different address, e.g. because of two gateways
+  # configured with the same Gateway ID or because of a spoofed Gateway ID.
+  # The address is used for sending downlinks to the gateway. Valid options:
+  #   * accept_latest: The new address is used immediately.
+  #   * keep_first: The new address is ignored until the current mapping
+  #     expires (no PULL_DATA received from the current address for 60s).
+  #   * require_consecutive: The new address is used after receiving
+  #     address_change_keepalives consecutive PULL_DATA from it.
+  #
+  # Ignored PULL_DATA packets are not acknowledged and not forwarded. Address
+  # conflicts are counted by the gateway_address_conflict_count metric and
+  # are exposed by the /gateways/conflicts monitoring endpoint.
+  address_change_policy="{{ gateways.address_change_policy }}"
+
+  # Number of consecutive PULL_DATA (require_consecutive policy).
+  address_change_keepalives={{ gateways.address_change_keepalives }}
+
+  # Gateway deduplication window (milliseconds).
+  #
+  # If set, a PUSH_DATA with the same token and payload as a PUSH_DATA
+  # received from the same gateway within this window is considered a
+  # duplicate (e.g. a retransmission by the packet forwarder or caused by a
+  # NAT re-binding). Duplicates are acknowledged, but not forwarded and are
+  # counted by the gateway_udp_duplicate_count metric (0 = disabled).
+  dedup_window_ms={{ gateways.dedup_window_ms }}
+
+  # Gateway keepalive interval (seconds).
+  #
+  # A gateway is online when PUSH_DATA or PULL_DATA is received and is
+  # considered offline when nothing is received for offline_after_keepalives
+  # intervals. This should match the keepalive_interval of the packet
+  # forwarder. The number of online gateways is exposed by the
+  # gateways_online metric, the last seen timestamp per gateway by the
+  # gateway_last_seen metric. Transitions are logged as events
+  # (gateway_first_seen, gateway_online and gateway_offline).
+  keepalive_interval_secs={{ gateways.keepalive_interval_secs }}
+
+  # Number of missed keepalive intervals before a gateway is offline
+  # (0 = offline detection disabled).
+  offline_after_keepalives={{ gateways.offline_after_keepalives }}
+
+  # Gateway rate limit.
+  #
+  # If set, the PUSH_DATA packets received from each gateway are limited to
+  # the given number of packets and / or bytes per second (0 = no limit). The
+  # burst is the maximum number of packets / bytes that can be received at
+  # once (defaults to the per second value, for bytes at least 65535, the
+  # max. UDP packet size). A configured byte burst must be at least 65535.
+  # PULL_DATA and TX_ACK packets are not limited. Packets exceeding the limit are dropped (not acknowledged and not
+  # forwarded) and are counted by the gateway_udp_throttled_count metric.
+  [gateways.rate_limit]
+    packets_per_second={{ gateways.rate_limit.packets_per_second }}
+    packet_burst={{ gateways.rate_limit.packet_burst }}
+    bytes_per_second={{ gateways.rate_limit.bytes_per_second }}
+    byte_burst={{ gateways.rate_limit.byte_burst }}
+
+  # Gateway downlink queue.
+  #
+  # Downlinks (PULL_RESP) for gateways of which the address is not (yet)
+  # known, e.g. directly after a restart of the multiplexer or after the
+  # mapping expired because of a delayed PULL_DATA, are queued and sent on
+  # the next PULL_DATA of the gateway. Queued downlinks exceeding the max.
+  # age are dropped, as a delayed downlink most likely misses its RX window.
+  # The queue is disabled by default. Queued downlinks are counted by the
+  # gateway_downlink_queue_count metric.
+  [gateways.downlink_queue]
+
+    # Max. number of queued downlinks per gateway (0 = queue disabled).
+    max_packets={{ gateways.downlink_queue.max_packets }}
+
+    # Max. age (milliseconds) of queued downlinks.
+    max_age_ms={{ gateways.downlink_queue.max_age_ms }}
+
+  # Gateway ACKs.
+  #
+  # By default, the PUSH_ACK and PULL_ACK are sent to the gateway directly by
+  # the multiplexer. As a result, the gateway statistics (e.g. ackr) do not
+  # reflect the reachability of the servers. In ACK pass-through mode, the ACK
+  # is only sent to the gateway once the server(s) have acknowledged the
+  # packet. Valid options:
+  #   * local: The ACK is sent directly by the multiplexer.
+  #   * primary: The ACK is sent once all primary servers (see primary)
+  #     acknowledged the packet. At least one server must be configured as
+  #     primary server.
+  #   * any: The ACK is sent once any of the servers acknowledged the packet.
+  #   * all: The ACK is sent once all servers acknowledged the packet.
+  #
+  # In case the packet was not forwarded to any (primary) server, e.g.
+  # because of the Gateway ID prefix filters, the ACK is sent directly. The
+  # results are counted by the gateway_ack_count metric.
+  [gateways.ack]
+    mode="{{ gateways.ack.mode }}"
 
+    # Timeout (milliseconds) to wait for the server ACK(s).
+    #
+    # Note that the gateway only waits a limited time for the ACK (e.g. the
+    # Semtech UDP Packet Forwarder waits 100ms for the PUSH_ACK). ACKs which
+    # are sent after this time are not taken into account by the gateway.
+    timeout_ms={{ gateways.ack.timeout_ms }}
+
+    # Send the ACK to the gateway on timeout.
+    #
+    # If set to true, the ACK is sent after the timeout even if the server(s)
+    # did not acknowledge the packet. If set to false, no ACK is sent.
+    send_on_timeout={{ gateways.ack.send_on_timeout }}
+
+  # Gateway allowlist.
+  #
+  # If not set, data from all gateways will be accepted. If set, only data
+  # from gateways matching one of the allow rules is accepted. A rule matches
+  # if both the Gateway ID matches one of its Gateway ID prefixes and the
+  # source IP address matches one of its source CIDRs. Empty Gateway ID
+  # prefixes or source CIDRs match any Gateway ID or source address.
+  # Rejected packets are logged and counted by the gateway_udp_rejected_count
+  # metric.
+  #
+  # Example configuration:
+  # [[gateways.allow]]
+
+  #   # Gateway ID prefixes.
+  #   gateway_id_prefixes=["0102030400000000/32"]
+
+  #   # Source CIDRs.
+  #   source_cidrs=["192.168.1.0/24", "2001:db8::/32"]
+  {{#each gateways.allow}}
+  [[gateways.allow]]
+    gateway_id_prefixes=[
+      {{#each this.gateway_id_prefixes}}
+      "{{this}}",
+      {{/each}}
+    ]
+    source_cidrs=[
+      {{#each this.source_cidrs}}
+      "{{this}}",
+      {{/each}}
+    ]
   {{/each}}
 
 
@@ -71,7 +635,131 @@ pub fn run(config: &Configuration) {
   # will be disabled. Endpoints:
   #
   # * /metrics: Exposes Prometheus metrics.
+  # * /gateways/conflicts: Exposes the gateways with address conflicts (JSON).
   bind="{{ monitoring.bind }}"
+
+
+# Traffic capture configuration.
+#
+# If enabled, every UDP datagram that is received and sent (gateway side and
+# server side) is written to a pcap file, with synthesized IP and UDP headers,
+# such that it can be inspected using for example Wireshark. For sockets
+# bound to an unspecified address (e.g. 0.0.0.0:1700), the multiplexer side
+# address in the capture is the unspecified address, as the actual local
+# address of the datagram is not known. Records which can not be written fast
+# enough are dropped and counted by the capture_dropped_count metric.
+[capture]
+
+  # Capture file.
+  #
+  # If not set, traffic capture is disabled. On rotation, the file is renamed
+  # to file.1 (file.1 to file.2, and so on).
+  file="{{ capture.file }}"
+
+  # Max. file size (bytes).
+  #
+  # Once the capture file reaches this size, it is rotated. If set to 0, the
+  # capture file is never rotated.
+  max_file_size={{ capture.max_file_size }}
+
+  # Max. number of capture files to keep (including the current file).
+  max_files={{ capture.max_files }}
+
+  # Gateway ID prefixes.
+  #
+  # If set, only the traffic of gateways matching one of the prefixes is
+  # captured.
+  #
+  # Example:
+  # gateway_id_prefixes=["0102030400000000/32"]
+  gateway_id_prefixes=[
+    {{#each capture.gateway_id_prefixes}}
+    "{{this}}",
+    {{/each}}
+  ]
+
+  # Servers.
+  #
+  # If set, only the server side traffic of the given servers (matching the
+  # server setting of [[multiplexer.server]]) is captured. This does not
+  # filter the gateway side traffic.
+  #
+  # Example:
+  # servers=["localhost:1700"]
+  servers=[
+    {{#each capture.servers}}
+    "{{this}}",
+    {{/each}}
+  ]
+
+
+# Webhooks.
+#
+# Events are sent as HTTP POST requests with a JSON body to the configured
+# webhooks. Events:
+#
+# * gateway_first_seen: The Gateway ID was seen for the first time since the
+#   start of the multiplexer.
+# * gateway_online: The gateway is online (see keepalive_interval_secs).
+# * gateway_offline: The gateway is offline (see offline_after_keepalives).
+# * server_unreachable: The server is unreachable (see
+#   unreachable_after_secs).
+# * server_recovered: The server is reachable again.
+# * downlink_rejected: The gateway rejected a downlink (TX_ACK with error).
+#
+# Example body:
+#
+# {"event":"gateway_online","gateway_id":"0102030405060708","addr":"192.168.1.10:1700","time":1700000000}
+#
+# Note: only http:// urls are supported, use a (local) reverse proxy for
+# sending the webhooks over https.
+#
+# Example configuration:
+#
+# [[webhook]]
+
+#   # URL.
+#   url="http://localhost:8080/events"
+
+#   # Events to send (if empty, all events are sent).
+#   events=["gateway_offline", "server_unreachable"]
+
+#   # Secret.
+#   #
+#   # If set, the X-Signature-256 header contains the HMAC-SHA256 signature of
+#   # the body, using this secret, in the format sha256=<hex encoded signature>.
+#   secret=""
+
+#   # Request timeout (milliseconds).
+#   timeout_ms=5000
+
+#   # Max. number of retries in case of an error or a non 2xx response.
+#   max_retries=3
+
+#   # Interval between retries (milliseconds).
+#   retry_interval_ms=1000
+
+#   # Additional HTTP headers.
+#   [webhook.headers]
+#     Authorization="Bearer secret-token"
+{{#each webhook}}
+[[webhook]]
+  url="{{this.url}}"
+  events=[
+    {{#each this.events}}
+    "{{this}}",
+    {{/each}}
+  ]
+  secret="{{this.secret}}"
+  timeout_ms={{this.timeout_ms}}
+  max_retries={{this.max_retries}}
+  retry_interval_ms={{this.retry_interval_ms}}
+
+  [webhook.headers]
+    {{#each this.headers}}
+    "{{@key}}"="{{this}}"
+    {{/each}}
+{{/each}}
 "#;
 
     let reg = Handlebars::new();
@@ -1 +1,3 @@
 pub mod configfile;
+pub mod replay;
+pub mod simulate;
@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use base64::{engine::general_purpose, Engine as _};
+use serde::Deserialize;
+use tokio::net::UdpSocket;
+use tokio::time::{sleep, sleep_until, Instant};
+use tracing::{debug, info, warn};
+
+use crate::packets::{GatewayId, GatewayIdPrefix, PacketType};
+
+// Time to wait for responses after the last packet has been sent.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+
+pub struct Options {
+    /// Capture file, pcap or NDJSON.
+    pub file: String,
+    /// Target (hostname:port) to which the packets are sent.
+    pub target: String,
+    /// Speed-up factor, e.g. 2.0 replays the traffic twice as fast.
+    pub speed: f64,
+    /// Only replay datagrams sent to this UDP port (required for pcap).
+    pub port: Option<u16>,
+    /// Only replay datagrams sent to this IP address (pcap only).
+    pub dst_ip: Option<IpAddr>,
+    /// Rewrite the Gateway IDs using this prefix.
+    pub gateway_id_prefix_rewrite: Option<GatewayIdPrefix>,
+}
+
+/// Summary of the replay.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub gateways: usize,
+    pub sent: usize,
+    pub received: usize,
+}
+
+// Captured datagram.
+struct Record {
+    // Time since the UNIX epoch.
+    time: Duration,
+    dst: Option<SocketAddr>,
+    data: Vec<u8>,
+}
+
+// NDJSON line, e.g.:
+// {"time":1700000000.123,"data":"AgECAAECAwQFBgcIe30="}
+#[derive(Deserialize)]
+struct JsonRecord {
+    time: f64,
+    data: String,
+}
+
+/// Replays the gateway-side traffic (PUSH_DATA, PULL_DATA and TX_ACK) of the
+/// capture file against the target, preserving the relative timing. Each
+/// gateway uses its own socket, such that the target sees a separate address
+/// per gateway.
+pub async fn run(opts: &Options) -> Result<Summary> {
+    if opts.speed <= 0.0 {
+        return Err(anyhow!("Speed must be > 0"));
+    }
+
+    let b = fs::read(&opts.file).context("Read capture file")?;
+    let records = if is_pcap(&b) {
+        // A capture contains both the gateway and the server side traffic,
+        // without port filter the PUSH_DATA would be replayed twice.
+        if opts.port.is_none() {
+            return Err(anyhow!("Port must be set for pcap input"));
+        }
+        read_pcap(&b).context("Read pcap")?
+    } else {
+        read_ndjson(&b).context("Read NDJSON")?
+    };
+
+    let records: Vec<Record> = records
+        .into_iter()
+        .filter(|v| opts.port.is_none() || v.dst.map(|v| v.port()) == opts.port)
+        .filter(|v| opts.dst_ip.is_none() || v.dst.map(|v| v.ip()) == opts.dst_ip)
+        .filter(|v| {
+            matches!(
+                PacketType::try_from(&v.data[..]),
+                Ok(PacketType::PushData | PacketType::PullData | PacketType::TxAck)
+            ) && GatewayId::try_from(&v.data[..]).is_ok()
+        })
+        .collect();
+
+    info!(file = %opts.file, target = %opts.target, packets = records.len(), speed = opts.speed, "Starting replay");
+
+    let received = Arc::new(AtomicUsize::new(0));
+    let mut sockets: HashMap<GatewayId, Arc<UdpSocket>> = HashMap::new();
+    let mut receivers = Vec::new();
+    let mut summary = Summary::default();
+
+    let start = Instant::now();
+    let t0 = records.first().map(|v| v.time).unwrap_or_default();
+
+    for record in records {
+        let offset = record.time.saturating_sub(t0).div_f64(opts.speed);
+        sleep_until(start + offset).await;
+
+        let mut data = record.data;
+        let mut gateway_id = GatewayId::try_from(&data[..])?;
+        if let Some(prefix) = &opts.gateway_id_prefix_rewrite {
+            gateway_id = prefix.rewrite(gateway_id);
+            data[4..12].copy_from_slice(&gateway_id.as_bytes_be());
+        }
+
+        let socket = match sockets.get(&gateway_id) {
+            Some(v) => v.clone(),
+            None => {
+                let socket = Arc::new(UdpSocket::bind("0.0.0.0:0").await.context("Bind socket")?);
+                socket
+                    .connect(&opts.target)
+                    .await
+                    .context("Connect socket")?;
+                receivers.push(tokio::spawn(handle_responses(
+                    gateway_id,
+                    socket.clone(),
+                    received.clone(),
+                )));
+                sockets.insert(gateway_id, socket.clone());
+                socket
+            }
+        };
+
+        let packet_type = PacketType::try_from(&data[..])?;
+        debug!(gateway_id = %gateway_id, packet_type = %packet_type, "Sending UDP packet");
+
+        if let Err(e) = socket.send(&data).await {
+            warn!(gateway_id = %gateway_id, error = %e, "Send UDP packet error");
+            continue;
+        }
+        summary.sent += 1;
+    }
+
+    sleep(RESPONSE_TIMEOUT).await;
+    for receiver in receivers {
+        receiver.abort();
+    }
+
+    summary.gateways = sockets.len();
+    summary.received = received.load(Ordering::Relaxed);
+
+    info!(
+        gateways = summary.gateways,
+        sent = summary.sent,
+        received = summary.received,
+        "Replay completed"
+    );
+
+    Ok(summary)
+}
+
+async fn handle_responses(
+    gateway_id: GatewayId,
+    socket: Arc<UdpSocket>,
+    received: Arc<AtomicUsize>,
+) {
+    let mut buffer = vec![0; 65535];
+
+    loop {
+        match socket.recv(&mut buffer).await {
+            Ok(size) => {
+                let packet_type = PacketType::try_from(&buffer[..size]);
+                debug!(gateway_id = %gateway_id, packet_type = ?packet_type, "UDP packet received");
+                received.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => {
+                debug!(gateway_id = %gateway_id, error = %e, "Receive error");
+                sleep(Duration::from_millis(100)).await;
+            }
+        }
+    }
+}
+
+fn is_pcap(b: &[u8]) -> bool {
+    b.len() >= 4
+        && matches!(
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            0xa1b2c3d4 | 0xa1b23c4d | 0xd4c3b2a1 | 0x4d3cb2a1
+        )
+}
+
+fn read_ndjson(b: &[u8]) -> Result<Vec<Record>> {
+    let s = std::str::from_utf8(b).context("Capture file is not pcap or NDJSON")?;
+    let mut out = Vec::new();
+
+    for (i, line) in s.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let rec: JsonRecord =
+            serde_json::from_str(line).with_context(|| format!("Parse line {}", i + 1))?;
+        out.push(Record {
+            time: Duration::try_from_secs_f64(rec.time)
+                .with_context(|| format!("Parse time on line {}", i + 1))?,
+            dst: None,
+            data: general_purpose::STANDARD
+                .decode(&rec.data)
+                .with_context(|| format!("Decode data on line {}", i + 1))?,
+        });
+    }
+
+    Ok(out)
+}
+
+// Reads the UDP datagrams from a (classic) pcap file, both microsecond and
+// nanosecond resolution in either byte order are supported.
+fn read_pcap(b: &[u8]) -> Result<Vec<Record>> {
+    if b.len() < 24 {
+        return Err(anyhow!("pcap header is too short"));
+    }
+
+    let (le, nanos) = match u32::from_le_bytes([b[0], b[1], b[2], b[3]]) {
+        0xa1b2c3d4 => (true, false),
+        0xa1b23c4d => (true, true),
+        0xd4c3b2a1 => (false, false),
+        _ => (false, true),
+    };
+    let u32_at = |b: &[u8], i: usize| {
+        let v = [b[i], b[i + 1], b[i + 2], b[i + 3]];
+        if le {
+            u32::from_le_bytes(v)
+        } else {
+            u32::from_be_bytes(v)
+        }
+    };
+
+    let link_type = u32_at(b, 20) & 0xffff;
+    let mut out = Vec::new();
+    let mut b = &b[24..];
+
+    while b.len() >= 16 {
+        let secs = u32_at(b, 0);
+        let frac = u32_at(b, 4);
+        let incl_len = u32_at(b, 8) as usize;
+        if b.len() < 16 + incl_len {
+            warn!("Truncated pcap record");
+            break;
+        }
+
+        let frame = &b[16..16 + incl_len];
+        b = &b[16 + incl_len..];
+
+        let packet = match link_type {
+            LINKTYPE_RAW => Some(frame),
+            LINKTYPE_ETHERNET => strip_ethernet(frame),
+            LINKTYPE_LINUX_SLL if frame.len() >= 16 => Some(&frame[16..]),
+            _ => return Err(anyhow!("Unsupported pcap link-type: {}", link_type)),
+        };
+
+        if let Some((dst, data)) = packet.and_then(parse_ip_udp) {
+            let time = Duration::from_secs(secs.into())
+                + if nanos {
+                    Duration::from_nanos(frac.into())
+                } else {
+                    Duration::from_micros(frac.into())
+                };
+
+            out.push(Record {
+                time,
+                dst: Some(dst),
+                data: data.to_vec(),
+            });
+        }
+    }
+
+    Ok(out)
+}
+
+// Returns the payload of the Ethernet frame, skipping VLAN tags.
+fn strip_ethernet(frame: &[u8]) -> Option<&[u8]> {
+    let mut offset = 12;
+    loop {
+        let ether_type = u16::from_be_bytes([*frame.get(offset)?, *frame.get(offset + 1)?]);
+        if ether_type == 0x8100 || ether_type == 0x88a8 {
+            offset += 4;
+        } else {
+            return frame.get(offset + 2..);
+        }
+    }
+}
+
+// Returns the UDP destination address and payload of the IPv4 or IPv6
+// packet. Non-UDP packets and IPv4 fragments are ignored.
+fn parse_ip_udp(b: &[u8]) -> Option<(SocketAddr, &[u8])> {
+    let (dst_ip, udp) = match b.first()? >> 4 {
+        4 => {
+            let ihl = usize::from(b[0] & 0x0f) * 4;
+            let fragmented = (u16::from_be_bytes([*b.get(6)?, *b.get(7)?]) & 0x3fff) != 0;
+            if *b.get(9)? != 17 || fragmented {
+                return None;
+            }
+            let dst_ip: [u8; 4] = b.get(16..20)?.try_into().ok()?;
+            (IpAddr::V4(Ipv4Addr::from(dst_ip)), b.get(ihl..)?)
+        }
+        6 => {
+            if *b.get(6)? != 17 {
+                return None;
+            }
+            let dst_ip: [u8; 16] = b.get(24..40)?.try_into().ok()?;
+            (IpAddr::V6(Ipv6Addr::from(dst_ip)), b.get(40..)?)
+        }
+        _ => return None,
+    };
+
+    let dst_port = u16::from_be_bytes([*udp.get(2)?, *udp.get(3)?]);
+    let len = usize::from(u16::from_be_bytes([*udp.get(4)?, *udp.get(5)?]));
+    Some((
+        SocketAddr::new(dst_ip, dst_port),
+        udp.get(8..len.max(8).min(udp.len()))?,
+    ))
+}
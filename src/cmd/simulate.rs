@@ -0,0 +1,376 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Context, Result};
+use base64::{engine::general_purpose, Engine as _};
+use serde_json::json;
+use tokio::net::UdpSocket;
+use tokio::time::{interval_at, Instant, Interval, MissedTickBehavior};
+use tracing::{debug, info, warn};
+
+use crate::packets::{self, GatewayId, GatewayIdPrefix, PacketType};
+
+// Time after which a packet which is not acknowledged is counted as missing.
+const ACK_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct Options {
+    /// Target (hostname:port), e.g. the multiplexer.
+    pub target: String,
+    /// Number of gateways to simulate.
+    pub gateways: usize,
+    /// Gateway ID prefix, the Gateway IDs are the prefix + gateway index.
+    pub gateway_id_prefix: GatewayIdPrefix,
+    /// PULL_DATA keepalive interval (0 = disabled).
+    pub keepalive_interval: Duration,
+    /// Interval of the PUSH_DATA stat reports (0 = disabled).
+    pub stat_interval: Duration,
+    /// Interval of the rxpk uplinks, per gateway (0 = disabled).
+    pub uplink_interval: Duration,
+    /// Error to report in the TX_ACK in case of error injection.
+    pub tx_ack_error: String,
+    /// Ratio (0.0 - 1.0) of the PULL_RESP that are responded with an error.
+    pub tx_ack_error_rate: f64,
+    /// Duration of the simulation, None runs forever.
+    pub duration: Option<Duration>,
+}
+
+/// Summary of the simulation, over all gateways.
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub push_data_sent: AtomicUsize,
+    pub push_ack_received: AtomicUsize,
+    pub pull_data_sent: AtomicUsize,
+    pub pull_ack_received: AtomicUsize,
+    pub pull_resp_received: AtomicUsize,
+    pub tx_ack_sent: AtomicUsize,
+    pub tx_ack_errors: AtomicUsize,
+    // ACKs of which the token does not match a sent packet.
+    pub invalid_acks: AtomicUsize,
+    // Sent packets which were not acknowledged within the ACK timeout.
+    pub missing_acks: AtomicUsize,
+}
+
+/// Simulates the configured number of gateways, speaking the Semtech UDP
+/// protocol against the target.
+pub async fn run(opts: &Options) -> Result<Arc<Summary>> {
+    if opts.gateways == 0 {
+        return Err(anyhow!("At least one gateway is expected"));
+    }
+    if !(0.0..=1.0).contains(&opts.tx_ack_error_rate) {
+        return Err(anyhow!("TX_ACK error rate must be between 0.0 and 1.0"));
+    }
+
+    info!(target = %opts.target, gateways = opts.gateways, gateway_id_prefix = %opts.gateway_id_prefix, "Starting gateway simulator");
+
+    let summary = Arc::new(Summary::default());
+    let mut tasks = Vec::with_capacity(opts.gateways);
+
+    for i in 0..opts.gateways {
+        let gateway_id = opts
+            .gateway_id_prefix
+            .rewrite(GatewayId::from_bytes_be((i as u64).to_be_bytes()));
+        let mut gw = Gateway::new(gateway_id, opts, summary.clone()).await?;
+        tasks.push(tokio::spawn(async move { gw.run().await }));
+    }
+
+    match opts.duration {
+        Some(v) => tokio::time::sleep(v).await,
+        None => std::future::pending().await,
+    }
+
+    for task in &tasks {
+        task.abort();
+    }
+    for task in tasks {
+        // The gateways are aborted, only missing ACKs are of interest.
+        let _ = task.await;
+    }
+
+    info!(
+        push_data_sent = summary.push_data_sent.load(Ordering::Relaxed),
+        push_ack_received = summary.push_ack_received.load(Ordering::Relaxed),
+        pull_data_sent = summary.pull_data_sent.load(Ordering::Relaxed),
+        pull_ack_received = summary.pull_ack_received.load(Ordering::Relaxed),
+        pull_resp_received = summary.pull_resp_received.load(Ordering::Relaxed),
+        tx_ack_sent = summary.tx_ack_sent.load(Ordering::Relaxed),
+        tx_ack_errors = summary.tx_ack_errors.load(Ordering::Relaxed),
+        invalid_acks = summary.invalid_acks.load(Ordering::Relaxed),
+        missing_acks = summary.missing_acks.load(Ordering::Relaxed),
+        "Gateway simulator completed"
+    );
+
+    Ok(summary)
+}
+
+struct Gateway {
+    gateway_id: GatewayId,
+    socket: UdpSocket,
+    keepalive: Option<Interval>,
+    stat: Option<Interval>,
+    uplink: Option<Interval>,
+    tx_ack_error: String,
+    tx_ack_error_rate: f64,
+    summary: Arc<Summary>,
+    rng: Rng,
+    // Tokens of the PUSH_DATA and PULL_DATA waiting for an ACK, with the time
+    // the packet was sent.
+    pending_push: HashMap<u16, Instant>,
+    pending_pull: HashMap<u16, Instant>,
+    expire: Interval,
+    // Counters reported in the stat object.
+    rx_count: u32,
+    tx_count: u32,
+    f_cnt: u16,
+}
+
+impl Gateway {
+    async fn new(gateway_id: GatewayId, opts: &Options, summary: Arc<Summary>) -> Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0").await.context("Bind socket")?;
+        socket
+            .connect(&opts.target)
+            .await
+            .context("Connect socket")?;
+
+        Ok(Gateway {
+            gateway_id,
+            socket,
+            keepalive: new_interval(opts.keepalive_interval),
+            stat: new_interval(opts.stat_interval),
+            uplink: new_interval(opts.uplink_interval),
+            tx_ack_error: opts.tx_ack_error.clone(),
+            tx_ack_error_rate: opts.tx_ack_error_rate,
+            summary,
+            rng: Rng::new(u64::from_be_bytes(gateway_id.as_bytes_be())),
+            pending_push: HashMap::new(),
+            pending_pull: HashMap::new(),
+            expire: new_interval(Duration::from_secs(1)).unwrap(),
+            rx_count: 0,
+            tx_count: 0,
+            f_cnt: 0,
+        })
+    }
+
+    async fn run(&mut self) {
+        let mut buffer = vec![0; 65535];
+
+        loop {
+            let res = tokio::select! {
+                _ = tick(&mut self.keepalive) => self.send_pull_data().await,
+                _ = tick(&mut self.stat) => self.send_stat().await,
+                _ = tick(&mut self.uplink) => self.send_uplink().await,
+                _ = self.expire.tick() => {
+                    self.expire_pending();
+                    Ok(())
+                }
+                res = self.socket.recv(&mut buffer) => match res {
+                    Ok(size) => self.handle_packet(&buffer[..size]).await,
+                    Err(e) => Err(e).context("Receive"),
+                },
+            };
+
+            if let Err(e) = res {
+                warn!(gateway_id = %self.gateway_id, error = %format!("{:#}", e), "Gateway simulator error");
+            }
+        }
+    }
+
+    async fn send_pull_data(&mut self) -> Result<()> {
+        let token = self.rng.next() as u16;
+        self.send(PacketType::PullData, token, None).await?;
+        self.pending_pull.insert(token, Instant::now());
+        self.summary.pull_data_sent.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    async fn send_stat(&mut self) -> Result<()> {
+        let payload = json!({
+            "stat": {
+                "rxnb": self.rx_count,
+                "rxok": self.rx_count,
+                "rxfw": self.rx_count,
+                "ackr": 100.0,
+                "dwnb": self.tx_count,
+                "txnb": self.tx_count,
+            }
+        });
+        self.send_push_data(payload).await
+    }
+
+    async fn send_uplink(&mut self) -> Result<()> {
+        // Unconfirmed data uplink: MHDR | DevAddr | FCtrl | FCnt | FPort |
+        // FRMPayload | MIC.
+        let mut phy = vec![0x40];
+        phy.extend_from_slice(&(self.rng.next() as u32).to_le_bytes());
+        phy.push(0x00);
+        phy.extend_from_slice(&self.f_cnt.to_le_bytes());
+        phy.push(0x01);
+        phy.extend_from_slice(&(self.rng.next() as u32).to_le_bytes());
+        phy.extend_from_slice(&(self.rng.next() as u32).to_le_bytes());
+
+        let tmst = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u32;
+
+        let payload = json!({
+            "rxpk": [{
+                "tmst": tmst,
+                "chan": 0,
+                "rfch": 0,
+                "freq": 868.1,
+                "stat": 1,
+                "modu": "LORA",
+                "datr": "SF7BW125",
+                "codr": "4/5",
+                "rssi": -60,
+                "lsnr": 7.0,
+                "size": phy.len(),
+                "data": general_purpose::STANDARD.encode(&phy),
+            }]
+        });
+
+        self.f_cnt = self.f_cnt.wrapping_add(1);
+        self.rx_count += 1;
+        self.send_push_data(payload).await
+    }
+
+    async fn send_push_data(&mut self, payload: serde_json::Value) -> Result<()> {
+        let token = self.rng.next() as u16;
+        let payload = serde_json::to_vec(&payload)?;
+        self.send(PacketType::PushData, token, Some(&payload))
+            .await?;
+        self.pending_push.insert(token, Instant::now());
+        self.summary.push_data_sent.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    async fn send(
+        &self,
+        packet_type: PacketType,
+        token: u16,
+        payload: Option<&[u8]>,
+    ) -> Result<()> {
+        let b = packets::encode(
+            packet_type,
+            token,
+            Some(self.gateway_id),
+            payload.unwrap_or_default(),
+        );
+
+        debug!(gateway_id = %self.gateway_id, packet_type = %packet_type, token = token, "Sending UDP packet");
+        self.socket.send(&b).await.context("Send")?;
+        Ok(())
+    }
+
+    // Removes the pending packets which are not acknowledged within the ACK
+    // timeout and counts these as missing.
+    fn expire_pending(&mut self) {
+        let mut missing = 0;
+        for pending in [&mut self.pending_push, &mut self.pending_pull] {
+            let before = pending.len();
+            pending.retain(|_, sent_at| sent_at.elapsed() < ACK_TIMEOUT);
+            missing += before - pending.len();
+        }
+
+        if missing != 0 {
+            warn!(gateway_id = %self.gateway_id, missing = missing, "Packets not acknowledged within timeout");
+            self.summary
+                .missing_acks
+                .fetch_add(missing, Ordering::Relaxed);
+        }
+    }
+
+    async fn handle_packet(&mut self, b: &[u8]) -> Result<()> {
+        let packet_type = PacketType::try_from(b)?;
+        let token = u16::from_be_bytes([b[1], b[2]]);
+
+        debug!(gateway_id = %self.gateway_id, packet_type = %packet_type, token = token, "UDP packet received");
+
+        match packet_type {
+            PacketType::PushAck => {
+                if self.pending_push.remove(&token).is_some() {
+                    self.summary
+                        .push_ack_received
+                        .fetch_add(1, Ordering::Relaxed);
+                } else {
+                    warn!(gateway_id = %self.gateway_id, token = token, "PUSH_ACK with unexpected token");
+                    self.summary.invalid_acks.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            PacketType::PullAck => {
+                if self.pending_pull.remove(&token).is_some() {
+                    self.summary
+                        .pull_ack_received
+                        .fetch_add(1, Ordering::Relaxed);
+                } else {
+                    warn!(gateway_id = %self.gateway_id, token = token, "PULL_ACK with unexpected token");
+                    self.summary.invalid_acks.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            PacketType::PullResp => {
+                self.summary
+                    .pull_resp_received
+                    .fetch_add(1, Ordering::Relaxed);
+
+                let error = if (self.rng.next() as f64 / u64::MAX as f64) < self.tx_ack_error_rate {
+                    self.summary.tx_ack_errors.fetch_add(1, Ordering::Relaxed);
+                    self.tx_ack_error.as_str()
+                } else {
+                    self.tx_count += 1;
+                    "NONE"
+                };
+
+                let payload = serde_json::to_vec(&json!({"txpk_ack": {"error": error}}))?;
+                self.send(PacketType::TxAck, token, Some(&payload)).await?;
+                self.summary.tx_ack_sent.fetch_add(1, Ordering::Relaxed);
+            }
+            _ => {
+                warn!(gateway_id = %self.gateway_id, packet_type = %packet_type, "Unexpected packet-type")
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn new_interval(period: Duration) -> Option<Interval> {
+    if period.is_zero() {
+        return None;
+    }
+
+    let mut interval = interval_at(Instant::now(), period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    Some(interval)
+}
+
+// Ticks the interval, or never completes in case it is disabled.
+async fn tick(interval: &mut Option<Interval>) {
+    match interval {
+        Some(v) => {
+            v.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+// Small xorshift PRNG, used for the tokens, payloads and error injection.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        Rng((seed ^ nanos) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
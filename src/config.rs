@@ -1,36 +1,262 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
 use std::{env, fs};
 
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use serde::{Deserialize, Serialize};
+use tokio::net::lookup_host;
+use tracing::Level;
+
+use crate::packets::{GatewayId, GatewayIdPrefix, NetId};
 
 #[derive(Default, Serialize, Deserialize)]
-#[serde(default)]
+#[serde(default, deny_unknown_fields)]
 pub struct Configuration {
     pub logging: Logging,
     pub multiplexer: Multiplexer,
+    pub gateways: Gateways,
     pub monitoring: Monitoring,
+    pub capture: Capture,
+    #[serde(rename = "webhook")]
+    pub webhooks: Vec<Webhook>,
 }
 
 impl Configuration {
     pub fn get(filenames: &[String]) -> Result<Configuration> {
         let mut content = String::new();
+        // File name and offset within the content of each file, used for
+        // reporting the file and line of parse errors.
+        let mut offsets: Vec<(&str, usize)> = Vec::new();
 
         for file_name in filenames {
-            content.push_str(&fs::read_to_string(file_name)?);
+            let mut file_content = fs::read_to_string(file_name)
+                .with_context(|| format!("Read configuration file: {}", file_name))?;
+
+            // Replace environment variables in config.
+            for (k, v) in env::vars() {
+                file_content = file_content.replace(&format!("${}", k), &v);
+            }
+
+            if !file_content.ends_with('\n') {
+                file_content.push('\n');
+            }
+
+            offsets.push((file_name, content.len()));
+            content.push_str(&file_content);
         }
 
-        // Replace environment variables in config.
-        for (k, v) in env::vars() {
-            content = content.replace(&format!("${}", k), &v);
+        toml::from_str(&content).map_err(|e| match e.span() {
+            Some(span) => {
+                let (file_name, line, col) = get_location(&content, &offsets, span.start);
+                anyhow!("{}:{}:{}: {}", file_name, line, col, e.message().trim())
+            }
+            None => anyhow!("{}", e.message().trim()),
+        })
+    }
+
+    /// Returns the problems found in the configuration, e.g. servers that do
+    /// not resolve, duplicate servers and empty or overlapping prefixes. An
+    /// empty list means that the configuration is valid.
+    pub async fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if Level::from_str(&self.logging.level).is_err() {
+            problems.push(format!(
+                "logging.level: invalid level: {}",
+                self.logging.level
+            ));
         }
 
-        let config: Configuration = toml::from_str(&content)?;
-        Ok(config)
+        let mut binds = vec![("multiplexer.bind", &self.multiplexer.bind)];
+        binds.extend(
+            self.multiplexer
+                .binds
+                .iter()
+                .map(|v| ("multiplexer.binds", v)),
+        );
+        if !self.multiplexer.bind_down.is_empty() {
+            binds.push(("multiplexer.bind_down", &self.multiplexer.bind_down));
+        }
+        for (key, bind) in binds {
+            if let Err(e) = resolve(bind).await {
+                problems.push(format!("{}: {:#}", key, e));
+            }
+        }
+
+        // Server address (including port_up / port_down) and the addresses it
+        // resolves to.
+        let mut server_addrs: Vec<(String, Vec<SocketAddr>)> = Vec::new();
+        // Spill files in use by the servers.
+        let mut spill_files: Vec<&str> = Vec::new();
+
+        for server in &self.multiplexer.servers {
+            let name = format!("multiplexer.server {}", server.server);
+
+            let mut addrs = vec![get_server_addr(&server.server, server.port_up)];
+            let addr_down = get_server_addr(&server.server, server.port_down);
+            if !addrs.contains(&addr_down) {
+                addrs.push(addr_down);
+            }
+
+            for addr in addrs {
+                if server_addrs.iter().any(|(v, _)| v == &addr) {
+                    problems.push(format!("Duplicate server: {}", addr));
+                    continue;
+                }
+
+                match resolve(&addr).await {
+                    Ok(resolved) => {
+                        for (other, other_resolved) in &server_addrs {
+                            if let Some(v) = resolved.iter().find(|v| other_resolved.contains(v)) {
+                                problems.push(format!(
+                                    "Servers {} and {} overlap, both resolve to {}",
+                                    other, addr, v
+                                ));
+                            }
+                        }
+                        server_addrs.push((addr, resolved));
+                    }
+                    Err(e) => {
+                        problems.push(format!("{}: {:#}", name, e));
+                        server_addrs.push((addr, Vec::new()));
+                    }
+                }
+            }
+
+            check_prefixes(
+                &mut problems,
+                &format!("{}: gateway_id_prefixes", name),
+                &server.gateway_id_prefixes,
+            );
+            check_prefixes(
+                &mut problems,
+                &format!("{}: join_eui_prefixes", name),
+                &server.join_eui_prefixes,
+            );
+
+            let spill_file = server.buffer.spill_file.as_str();
+            if !spill_file.is_empty() {
+                if spill_files.contains(&spill_file) {
+                    problems.push(format!(
+                        "{}: buffer.spill_file: {} is already used by another server",
+                        name, spill_file
+                    ));
+                }
+                spill_files.push(spill_file);
+            }
+        }
+
+        for (i, allow) in self.gateways.allowed.iter().enumerate() {
+            check_prefixes(
+                &mut problems,
+                &format!("gateways.allow[{}]: gateway_id_prefixes", i),
+                &allow.gateway_id_prefixes,
+            );
+        }
+
+        check_prefixes(
+            &mut problems,
+            "capture: gateway_id_prefixes",
+            &self.capture.gateway_id_prefixes,
+        );
+
+        problems
     }
 }
 
+// Returns the file name, line and column of the given offset within the
+// concatenated content.
+fn get_location<'a>(
+    content: &str,
+    offsets: &[(&'a str, usize)],
+    pos: usize,
+) -> (&'a str, usize, usize) {
+    let (file_name, start) = offsets
+        .iter()
+        .rev()
+        .find(|(_, start)| *start <= pos)
+        .copied()
+        .unwrap_or(("", 0));
+
+    let before = &content[start..pos.min(content.len())];
+    let line = before.matches('\n').count() + 1;
+    let col = before.len() - before.rfind('\n').map(|v| v + 1).unwrap_or(0) + 1;
+
+    (file_name, line, col)
+}
+
+async fn resolve(addr: &str) -> Result<Vec<SocketAddr>> {
+    let addrs: Vec<SocketAddr> = lookup_host(addr)
+        .await
+        .with_context(|| format!("Unable to resolve {}", addr))?
+        .collect();
+
+    if addrs.is_empty() {
+        return Err(anyhow!("Unable to resolve {}", addr));
+    }
+
+    Ok(addrs)
+}
+
+// Returns the server address, with the port replaced by the given port if it
+// is not 0.
+pub fn get_server_addr(server: &str, port: u16) -> String {
+    if port == 0 {
+        return server.to_string();
+    }
+
+    let host = server
+        .rsplit_once(':')
+        .map(|(host, _)| host)
+        .unwrap_or(server);
+
+    format!("{}:{}", host, port)
+}
+
+// Adds a problem for each empty (/0) prefix and for each pair of prefixes of
+// which one contains the other.
+fn check_prefixes(problems: &mut Vec<String>, key: &str, prefixes: &[lrwn_filters::EuiPrefix]) {
+    let parsed: Vec<(u64, u32)> = prefixes.iter().map(get_prefix_parts).collect();
+
+    for (i, (prefix, size)) in parsed.iter().enumerate() {
+        if *size == 0 {
+            problems.push(format!(
+                "{}: empty prefix {} matches everything, use an empty list instead",
+                key, prefixes[i]
+            ));
+            continue;
+        }
+
+        for (j, (other_prefix, other_size)) in parsed.iter().enumerate().skip(i + 1) {
+            if *other_size == 0 {
+                continue;
+            }
+
+            let size = (*size).min(*other_size);
+            if prefix >> (64 - size) == other_prefix >> (64 - size) {
+                problems.push(format!(
+                    "{}: prefix {} overlaps with {}",
+                    key, prefixes[i], prefixes[j]
+                ));
+            }
+        }
+    }
+}
+
+// Returns the prefix and size, these are not exposed by EuiPrefix, but are
+// part of its string representation.
+fn get_prefix_parts(prefix: &lrwn_filters::EuiPrefix) -> (u64, u32) {
+    let s = prefix.to_string();
+    let (prefix, size) = s.split_once('/').unwrap_or_default();
+
+    let mut b = [0; 8];
+    let _ = hex::decode_to_slice(prefix, &mut b);
+    (u64::from_be_bytes(b), size.parse().unwrap_or_default())
+}
+
 #[derive(Serialize, Deserialize)]
-#[serde(default)]
+#[serde(default, deny_unknown_fields)]
 pub struct Logging {
     pub level: String,
 }
@@ -44,9 +270,15 @@ impl Default for Logging {
 }
 
 #[derive(Serialize, Deserialize)]
-#[serde(default)]
+#[serde(default, deny_unknown_fields)]
 pub struct Multiplexer {
     pub bind: String,
+    pub binds: Vec<String>,
+    pub bind_down: String,
+    pub sockets_per_bind: usize,
+    pub udp_batch_size: usize,
+    pub recv_buffer_size: usize,
+    pub send_buffer_size: usize,
     #[serde(rename = "server")]
     pub servers: Vec<Server>,
 }
@@ -55,21 +287,263 @@ impl Default for Multiplexer {
     fn default() -> Self {
         Multiplexer {
             bind: "0.0.0.0:1700".into(),
+            binds: Vec::new(),
+            bind_down: "".into(),
+            sockets_per_bind: 1,
+            udp_batch_size: 32,
+            recv_buffer_size: 0,
+            send_buffer_size: 0,
             servers: Vec::new(),
         }
     }
 }
 
 #[derive(Default, Serialize, Deserialize, Clone)]
-#[serde(default)]
+#[serde(default, deny_unknown_fields)]
 pub struct Server {
     pub server: String,
+    pub port_up: u16,
+    pub port_down: u16,
     pub uplink_only: bool,
+    pub primary: bool,
+    pub gateway_id_prefixes: Vec<lrwn_filters::EuiPrefix>,
+    pub net_ids: Vec<NetId>,
+    pub join_eui_prefixes: Vec<lrwn_filters::EuiPrefix>,
+    pub gateway_id_prefix_rewrite: Option<GatewayIdPrefix>,
+    pub gateway_id_mapping: HashMap<GatewayId, GatewayId>,
+    pub inject_location: bool,
+    pub rxpk_filters: RxpkFilters,
+    pub privacy: Privacy,
+    pub rate_limit: RateLimit,
+    pub max_sockets: usize,
+    pub local_port_range: Option<(u16, u16)>,
+    pub local_address: Option<IpAddr>,
+    pub interface: String,
+    pub dscp: u8,
+    pub recv_buffer_size: usize,
+    pub send_buffer_size: usize,
+    pub retransmit: Retransmit,
+    pub buffer: Buffer,
+    pub unreachable_after_secs: u64,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RxpkFilters {
+    pub crc_status: Vec<i32>,
+    pub min_rssi: Option<i32>,
+    pub min_snr: Option<f64>,
+    pub frequency_ranges: Vec<(u32, u32)>,
+    pub modulations: Vec<String>,
+    pub data_rates: Vec<String>,
+    pub spreading_factors: Vec<u8>,
+    pub drop_stat_only: bool,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Privacy {
+    pub remove_location: bool,
+    pub location_decimals: Option<u32>,
+    pub remove_tmms: bool,
+    pub remove_ftime: bool,
+    pub remove_rsig: bool,
+    pub rssi_step: Option<u32>,
+    pub snr_step: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Gateways {
+    pub metadata_file: String,
+    pub address_change_policy: AddressChangePolicy,
+    pub address_change_keepalives: u32,
+    pub rate_limit: RateLimit,
+    pub dedup_window_ms: u64,
+    pub keepalive_interval_secs: u64,
+    pub offline_after_keepalives: u32,
+    pub downlink_queue: DownlinkQueue,
+    pub ack: Ack,
+    #[serde(rename = "allow")]
+    pub allowed: Vec<GatewayAllow>,
+}
+
+impl Default for Gateways {
+    fn default() -> Self {
+        Gateways {
+            metadata_file: "".into(),
+            address_change_policy: AddressChangePolicy::AcceptLatest,
+            address_change_keepalives: 3,
+            rate_limit: RateLimit::default(),
+            dedup_window_ms: 0,
+            keepalive_interval_secs: 10,
+            offline_after_keepalives: 3,
+            downlink_queue: DownlinkQueue::default(),
+            ack: Ack::default(),
+            allowed: Vec::new(),
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum AddressChangePolicy {
+    #[default]
+    AcceptLatest,
+    KeepFirst,
+    RequireConsecutive,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct DownlinkQueue {
+    pub max_packets: usize,
+    pub max_age_ms: u64,
+}
+
+impl Default for DownlinkQueue {
+    fn default() -> Self {
+        DownlinkQueue {
+            max_packets: 0,
+            max_age_ms: 1000,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Ack {
+    pub mode: AckMode,
+    pub timeout_ms: u64,
+    pub send_on_timeout: bool,
+}
+
+impl Default for Ack {
+    fn default() -> Self {
+        Ack {
+            mode: AckMode::Local,
+            timeout_ms: 100,
+            send_on_timeout: false,
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum AckMode {
+    #[default]
+    Local,
+    Primary,
+    Any,
+    All,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct GatewayAllow {
     pub gateway_id_prefixes: Vec<lrwn_filters::EuiPrefix>,
+    pub source_cidrs: Vec<ipnet::IpNet>,
 }
 
 #[derive(Default, Serialize, Deserialize, Clone)]
-#[serde(default)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimit {
+    pub packets_per_second: u32,
+    pub packet_burst: u32,
+    pub bytes_per_second: u32,
+    pub byte_burst: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Retransmit {
+    pub max_retries: u32,
+    pub timeout_ms: u64,
+}
+
+impl Default for Retransmit {
+    fn default() -> Self {
+        Retransmit {
+            max_retries: 0,
+            timeout_ms: 500,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Buffer {
+    pub max_packets: usize,
+    pub max_memory_packets: usize,
+    pub spill_file: String,
+    pub max_age_secs: u64,
+    pub ack_timeout_ms: u64,
+    pub replay_packets_per_second: u32,
+}
+
+impl Default for Buffer {
+    fn default() -> Self {
+        Buffer {
+            max_packets: 0,
+            max_memory_packets: 1000,
+            spill_file: "".into(),
+            max_age_secs: 3600,
+            ack_timeout_ms: 1000,
+            replay_packets_per_second: 10,
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
 pub struct Monitoring {
     pub bind: String,
 }
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Webhook {
+    pub url: String,
+    pub events: Vec<String>,
+    pub secret: String,
+    pub headers: HashMap<String, String>,
+    pub timeout_ms: u64,
+    pub max_retries: u32,
+    pub retry_interval_ms: u64,
+}
+
+impl Default for Webhook {
+    fn default() -> Self {
+        Webhook {
+            url: "".into(),
+            events: Vec::new(),
+            secret: "".into(),
+            headers: HashMap::new(),
+            timeout_ms: 5000,
+            max_retries: 3,
+            retry_interval_ms: 1000,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Capture {
+    pub file: String,
+    pub max_file_size: u64,
+    pub max_files: usize,
+    pub gateway_id_prefixes: Vec<lrwn_filters::EuiPrefix>,
+    pub servers: Vec<String>,
+}
+
+impl Default for Capture {
+    fn default() -> Self {
+        Capture {
+            file: "".into(),
+            max_file_size: 10485760,
+            max_files: 5,
+            gateway_id_prefixes: Vec::new(),
+            servers: Vec::new(),
+        }
+    }
+}
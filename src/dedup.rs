@@ -0,0 +1,55 @@
+use std::collections::VecDeque;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::time::{Duration, Instant};
+
+/// Window of recently received PUSH_DATA of a gateway, used to detect
+/// retransmissions and duplicates caused by NAT re-bindings.
+pub struct DedupWindow {
+    window: Duration,
+    // Token, payload hash and time received, oldest first.
+    entries: VecDeque<(u16, u64, Instant)>,
+    last_used: Instant,
+}
+
+impl DedupWindow {
+    pub fn new(window: Duration) -> Self {
+        DedupWindow {
+            window,
+            entries: VecDeque::new(),
+            last_used: Instant::now(),
+        }
+    }
+
+    /// Returns true if a packet with the same token and payload was received
+    /// within the window. Otherwise the packet is added to the window.
+    pub fn is_duplicate(&mut self, token: u16, payload: &[u8]) -> bool {
+        let now = Instant::now();
+        self.last_used = now;
+
+        while let Some((_, _, received_at)) = self.entries.front() {
+            if now.duration_since(*received_at) < self.window {
+                break;
+            }
+            self.entries.pop_front();
+        }
+
+        let mut hasher = DefaultHasher::new();
+        payload.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        if self
+            .entries
+            .iter()
+            .any(|(t, h, _)| *t == token && *h == hash)
+        {
+            return true;
+        }
+
+        self.entries.push_back((token, hash, now));
+        false
+    }
+
+    pub fn last_used(&self) -> Instant {
+        self.last_used
+    }
+}
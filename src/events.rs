@@ -0,0 +1,112 @@
+use std::net::SocketAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use tokio::sync::{broadcast, OnceCell};
+use tracing::info;
+
+use crate::packets::GatewayId;
+
+static EVENTS: OnceCell<broadcast::Sender<Event>> = OnceCell::const_new();
+
+/// Gateway and server state event. Timestamps are UNIX timestamps (seconds).
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// The Gateway ID was seen for the first time since the start of the
+    /// multiplexer.
+    GatewayFirstSeen {
+        gateway_id: GatewayId,
+        addr: SocketAddr,
+        time: u64,
+    },
+    /// PUSH_DATA or PULL_DATA was received from a gateway which was offline
+    /// or seen for the first time.
+    GatewayOnline {
+        gateway_id: GatewayId,
+        addr: SocketAddr,
+        time: u64,
+    },
+    /// No PUSH_DATA or PULL_DATA was received from the gateway within the
+    /// configured number of keepalive intervals.
+    GatewayOffline {
+        gateway_id: GatewayId,
+        last_seen: u64,
+        time: u64,
+    },
+    /// The server did not acknowledge the PUSH_DATA or PULL_DATA within the
+    /// configured time.
+    ServerUnreachable { server: String, time: u64 },
+    /// The server acknowledged PUSH_DATA or PULL_DATA again.
+    ServerRecovered { server: String, time: u64 },
+    /// The gateway rejected the downlink of the server (TX_ACK with error).
+    DownlinkRejected {
+        gateway_id: GatewayId,
+        server: String,
+        error: String,
+        time: u64,
+    },
+}
+
+impl Event {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::GatewayFirstSeen { .. } => "gateway_first_seen",
+            Event::GatewayOnline { .. } => "gateway_online",
+            Event::GatewayOffline { .. } => "gateway_offline",
+            Event::ServerUnreachable { .. } => "server_unreachable",
+            Event::ServerRecovered { .. } => "server_recovered",
+            Event::DownlinkRejected { .. } => "downlink_rejected",
+        }
+    }
+
+    pub fn gateway_id(&self) -> Option<GatewayId> {
+        match self {
+            Event::GatewayFirstSeen { gateway_id, .. }
+            | Event::GatewayOnline { gateway_id, .. }
+            | Event::GatewayOffline { gateway_id, .. }
+            | Event::DownlinkRejected { gateway_id, .. } => Some(*gateway_id),
+            Event::ServerUnreachable { .. } | Event::ServerRecovered { .. } => None,
+        }
+    }
+
+    pub fn server(&self) -> Option<&str> {
+        match self {
+            Event::ServerUnreachable { server, .. }
+            | Event::ServerRecovered { server, .. }
+            | Event::DownlinkRejected { server, .. } => Some(server),
+            _ => None,
+        }
+    }
+}
+
+async fn sender() -> &'static broadcast::Sender<Event> {
+    EVENTS
+        .get_or_init(|| async { broadcast::channel(1024).0 })
+        .await
+}
+
+/// Logs the event and sends it to all subscribers.
+pub async fn emit(event: Event) {
+    info!(
+        event = event.name(),
+        gateway_id = ?event.gateway_id(),
+        server = ?event.server(),
+        "Event"
+    );
+
+    // This only fails in case there are no subscribers.
+    let _ = sender().await.send(event);
+}
+
+/// Returns a receiver for all events emitted after subscribing.
+pub async fn subscribe() -> broadcast::Receiver<Event> {
+    sender().await.subscribe()
+}
+
+/// Returns the given time as UNIX timestamp (seconds).
+pub fn timestamp(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH)
+        .map(|v| v.as_secs())
+        .unwrap_or_default()
+}
@@ -0,0 +1,96 @@
+use serde_json::Value;
+
+use crate::config::RxpkFilters;
+
+/// Returns true if none of the rxpk filters are set.
+pub fn is_empty(filters: &RxpkFilters) -> bool {
+    filters.crc_status.is_empty()
+        && filters.min_rssi.is_none()
+        && filters.min_snr.is_none()
+        && filters.frequency_ranges.is_empty()
+        && filters.modulations.is_empty()
+        && filters.data_rates.is_empty()
+        && filters.spreading_factors.is_empty()
+}
+
+/// Returns true if the given rxpk object matches the given filters.
+///
+/// Filters that are not set are ignored. If a filter is set, but the rxpk
+/// object does not contain the field to filter on, the rxpk does not pass the
+/// filter.
+pub fn matches(rxpk: &Value, filters: &RxpkFilters) -> bool {
+    if !filters.crc_status.is_empty() {
+        match rxpk.get("stat").and_then(|v| v.as_i64()) {
+            Some(stat) if filters.crc_status.iter().any(|v| *v as i64 == stat) => {}
+            _ => return false,
+        }
+    }
+
+    if let Some(min_rssi) = filters.min_rssi {
+        match rxpk.get("rssi").and_then(|v| v.as_i64()) {
+            Some(rssi) if rssi >= min_rssi as i64 => {}
+            _ => return false,
+        }
+    }
+
+    if let Some(min_snr) = filters.min_snr {
+        match rxpk.get("lsnr").and_then(|v| v.as_f64()) {
+            Some(snr) if snr >= min_snr => {}
+            _ => return false,
+        }
+    }
+
+    if !filters.frequency_ranges.is_empty() {
+        // The rxpk frequency is in MHz, the configured ranges are in Hz.
+        match rxpk
+            .get("freq")
+            .and_then(|v| v.as_f64())
+            .map(|v| (v * 1_000_000.0).round() as u32)
+        {
+            Some(freq)
+                if filters
+                    .frequency_ranges
+                    .iter()
+                    .any(|(min, max)| freq >= *min && freq <= *max) => {}
+            _ => return false,
+        }
+    }
+
+    if !filters.modulations.is_empty() {
+        match rxpk.get("modu").and_then(|v| v.as_str()) {
+            Some(modu) if filters.modulations.iter().any(|v| v == modu) => {}
+            _ => return false,
+        }
+    }
+
+    // The datr field is a string for LoRa (e.g. SF7BW125) and a number for
+    // FSK (bitrate).
+    let datr = match rxpk.get("datr") {
+        Some(Value::String(v)) => Some(v.clone()),
+        Some(Value::Number(v)) => Some(v.to_string()),
+        _ => None,
+    };
+
+    if !filters.data_rates.is_empty() {
+        match &datr {
+            Some(datr) if filters.data_rates.contains(datr) => {}
+            _ => return false,
+        }
+    }
+
+    if !filters.spreading_factors.is_empty() {
+        match datr.as_deref().and_then(get_spreading_factor) {
+            Some(sf) if filters.spreading_factors.contains(&sf) => {}
+            _ => return false,
+        }
+    }
+
+    true
+}
+
+// Returns the spreading-factor from a LoRa datr string, e.g. SF7BW125.
+fn get_spreading_factor(datr: &str) -> Option<u8> {
+    let datr = datr.strip_prefix("SF")?;
+    let end = datr.find("BW")?;
+    datr[..end].parse().ok()
+}
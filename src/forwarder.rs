@@ -1,29 +1,122 @@
 use std::collections::HashMap;
-use std::sync::Arc;
-use std::time::{Duration, SystemTime};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::{anyhow, Context, Result};
+use bytes::{Bytes, BytesMut};
 use tokio::net::UdpSocket;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 use tokio::sync::{oneshot, OnceCell, RwLock};
 use tracing::{debug, error, info, trace, warn, Instrument};
 
+use crate::acks;
+use crate::buffer::{Buffer, Entry};
+use crate::capture;
 use crate::config;
-use crate::monitoring::{inc_server_udp_received_count, inc_server_udp_sent_count};
-use crate::packets::{get_random_token, GatewayId, PacketType};
+use crate::events::{self, Event};
+use crate::filters;
+use crate::metadata::{self, GatewayMetadata};
+use crate::monitoring::{
+    inc_server_buffer_count, inc_server_retransmit_count, inc_server_socket_error_count,
+    inc_server_udp_received_count, inc_server_udp_send_error_count, inc_server_udp_sent_count,
+    inc_server_udp_throttled_count, set_server_buffer_packets, set_server_sockets,
+};
+use crate::packets::{
+    get_phy_payload, get_random_token, get_tx_ack_error, GatewayId, GatewayIdPrefix, PacketType,
+    PushData,
+};
+use crate::privacy;
+use crate::ratelimit::{self, RateLimiter};
 use crate::traits::PrintFullError;
+use crate::udp;
 
 static SERVERS: OnceCell<RwLock<Vec<Server>>> = OnceCell::const_new();
 
+// Interval in which unacknowledged PUSH_DATA are retransmitted or buffered
+// and buffered PUSH_DATA are replayed.
+const ACK_TICK: Duration = Duration::from_millis(50);
+
+// Min. interval between logging that the server socket limit is reached.
+const SOCKET_LIMIT_LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+// Returned when a new server socket can not be created because max_sockets
+// is reached. This is logged by add_server_socket itself (rate-limited).
+#[derive(Debug)]
+struct SocketLimitReached;
+
+impl std::fmt::Display for SocketLimitReached {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Server socket limit reached")
+    }
+}
+
+impl std::error::Error for SocketLimitReached {}
+
 struct Server {
     server: String,
+    server_up: String,
+    server_down: String,
     uplink_only: bool,
+    primary: bool,
     gateway_id_prefixes: Vec<lrwn_filters::EuiPrefix>,
-    downlink_tx: UnboundedSender<(GatewayId, Vec<u8>)>,
+    filters: lrwn_filters::Filters,
+    rxpk_filters: config::RxpkFilters,
+    privacy: config::Privacy,
+    gateway_id_prefix_rewrite: Option<GatewayIdPrefix>,
+    gateway_id_mapping: HashMap<GatewayId, GatewayId>,
+    inject_location: bool,
+    rate_limiter: Option<RateLimiter>,
+    max_sockets: usize,
+    // Last time the socket limit reached error was logged.
+    socket_limit_logged_at: Option<Instant>,
+    local_port_range: Option<(u16, u16)>,
+    next_port: u16,
+    local_address: IpAddr,
+    socket_options: udp::SocketOptions,
+    downlink_tx: UnboundedSender<(GatewayId, Bytes)>,
     sockets: HashMap<GatewayId, ServerSocket>,
+    // PUSH_DATA which have not been acknowledged yet by Gateway ID and token.
+    // These are only tracked in case retransmission or buffering is enabled.
+    pending: HashMap<(GatewayId, u16), PendingPushData>,
+    retransmit: config::Retransmit,
+    buffer: Option<ServerBuffer>,
+    // The server is considered unreachable in case PUSH_DATA or PULL_DATA was
+    // sent, but no ACK was received within this duration.
+    unreachable_after: Option<Duration>,
+    // Time of the first PUSH_DATA or PULL_DATA sent after the last ACK.
+    unacked_since: Option<Instant>,
+    reachable: bool,
+}
+
+struct PendingPushData {
+    data: Bytes,
+    // Time the PUSH_DATA was last sent to the server.
+    sent_at: Instant,
+    // Time the PUSH_DATA was received from the gateway.
+    received_at: SystemTime,
+    retries: u32,
+}
+
+// Store-and-forward state of the server.
+struct ServerBuffer {
+    buffer: Arc<Mutex<Buffer>>,
+    // Number of buffered PUSH_DATA, updated after each buffer update.
+    buffered: usize,
+    ack_timeout: Duration,
+    max_age: Duration,
+    replay_packets_per_tick: usize,
+    // Set to false when PUSH_DATA is not acknowledged within the ACK timeout,
+    // set to true again on the first received PUSH_ACK.
+    online: bool,
 }
 
 impl Server {
+    fn tracks_push_ack(&self) -> bool {
+        self.retransmit.max_retries != 0 || self.buffer.is_some()
+    }
+
     fn match_prefixes(&self, gateway_id: GatewayId) -> bool {
         let gw_id_le = gateway_id.as_bytes_le();
         if self.gateway_id_prefixes.is_empty() {
@@ -39,95 +132,384 @@ impl Server {
         false
     }
 
+    // Returns the Gateway ID as it must be presented to the server. The mapping
+    // table has priority over the prefix rewrite.
+    fn get_server_gateway_id(&self, gateway_id: GatewayId) -> GatewayId {
+        if let Some(v) = self.gateway_id_mapping.get(&gateway_id) {
+            return *v;
+        }
+
+        if let Some(prefix) = &self.gateway_id_prefix_rewrite {
+            return prefix.rewrite(gateway_id);
+        }
+
+        gateway_id
+    }
+
+    // Returns the PUSH_DATA to forward to the server, with the rxpk objects
+    // not matching the DevAddr (NetID), JoinEUI and rxpk filters removed, the
+    // gateway location injected and the privacy transformations applied. None
+    // is returned in case there is nothing left to forward.
+    fn prepare_push_data(
+        &self,
+        data: &Bytes,
+        metadata: Option<&GatewayMetadata>,
+    ) -> Result<Option<Bytes>> {
+        let inject_location = self.inject_location && metadata.is_some_and(|v| v.has_location());
+
+        if self.filters.dev_addr_prefixes.is_empty()
+            && self.filters.join_eui_prefixes.is_empty()
+            && filters::is_empty(&self.rxpk_filters)
+            && !self.rxpk_filters.drop_stat_only
+            && privacy::is_empty(&self.privacy)
+            && !inject_location
+        {
+            return Ok(Some(data.clone()));
+        }
+
+        let mut push_data = PushData::try_from(&data[..])?;
+        let rxpk_count = push_data.rxpk().len();
+
+        if let Some(rxpk) = push_data.rxpk_mut() {
+            rxpk.retain(|v| {
+                if !filters::matches(v, &self.rxpk_filters) {
+                    return false;
+                }
+
+                match get_phy_payload(v) {
+                    Ok(phy_payload) => lrwn_filters::matches(&phy_payload, &self.filters),
+                    Err(e) => {
+                        warn!(error = %e.full(), "Get PHYPayload error");
+                        true
+                    }
+                }
+            });
+        }
+
+        if push_data.rxpk().is_empty() && self.rxpk_filters.drop_stat_only {
+            debug!(server = %self.server, "Dropping PUSH_DATA without rxpk objects");
+            return Ok(None);
+        }
+
+        let rxpk_filtered = rxpk_count - push_data.rxpk().len();
+        if rxpk_filtered != 0 {
+            debug!(
+                server = %self.server,
+                rxpk_filtered = rxpk_filtered,
+                "Filtered rxpk objects from PUSH_DATA"
+            );
+
+            if push_data.rxpk().is_empty() {
+                push_data.payload.remove("rxpk");
+
+                if !push_data.has_stat() {
+                    return Ok(None);
+                }
+            }
+        }
+
+        let mut location_injected = false;
+        if inject_location {
+            if let Some(metadata) = metadata {
+                location_injected = metadata::inject_location(&mut push_data, metadata);
+            }
+        }
+
+        if rxpk_filtered == 0 && !location_injected && privacy::is_empty(&self.privacy) {
+            return Ok(Some(data.clone()));
+        }
+
+        privacy::apply(&mut push_data, &self.privacy);
+
+        Ok(Some(push_data.to_vec()?.into()))
+    }
+
+    // Returns the number of open sockets to the server.
+    fn socket_count(&self) -> usize {
+        self.sockets.len() * self.sockets_per_gateway()
+    }
+
+    fn sockets_per_gateway(&self) -> usize {
+        if self.server_up == self.server_down {
+            1
+        } else {
+            2
+        }
+    }
+
     async fn get_server_socket(&mut self, gateway_id: GatewayId) -> Result<&mut ServerSocket> {
         // Check if we already have a socket for the given Gateway ID to the
         // server and if not, we create it.
-        if let std::collections::hash_map::Entry::Vacant(e) = self.sockets.entry(gateway_id) {
-            info!(gateway_id = %gateway_id, server = %self.server, "Initializing forwarder to server");
-
-            let socket = UdpSocket::bind("0.0.0.0:0")
-                .await
-                .context("UDP socket bind")?;
-            socket
-                .connect(&self.server)
-                .await
-                .context("UDP socket connect")?;
-
-            let socket = Arc::new(socket);
+        if !self.sockets.contains_key(&gateway_id) {
+            self.add_server_socket(gateway_id).await?;
+        }
+
+        // This should never error since we check the existence of the GatewayId key above.
+        let socket = self
+            .sockets
+            .get_mut(&gateway_id)
+            .ok_or_else(|| anyhow!("Gateway ID not found"))?;
+
+        Ok(socket)
+    }
+
+    async fn add_server_socket(&mut self, gateway_id: GatewayId) -> Result<()> {
+        if self.max_sockets != 0
+            && self.socket_count() + self.sockets_per_gateway() > self.max_sockets
+        {
+            inc_server_socket_error_count(&self.server, "limit_reached").await;
+
+            if self
+                .socket_limit_logged_at
+                .map(|v| v.elapsed() >= SOCKET_LIMIT_LOG_INTERVAL)
+                .unwrap_or(true)
+            {
+                error!(
+                    gateway_id = %gateway_id,
+                    server = %self.server,
+                    max_sockets = self.max_sockets,
+                    "Server socket limit reached, dropping packets for new gateways"
+                );
+                self.socket_limit_logged_at = Some(Instant::now());
+            }
+
+            return Err(SocketLimitReached.into());
+        }
+
+        info!(gateway_id = %gateway_id, server = %self.server, "Initializing forwarder to server");
+
+        let socket_down = self.connect_socket(false).await?;
+        let (stop_tx, stop_rx) = oneshot::channel::<()>();
+        let mut stop = vec![stop_tx];
+
+        tokio::spawn(handle_downlink(
+            self.server.clone(),
+            stop_rx,
+            self.uplink_only,
+            self.unreachable_after.is_some(),
+            socket_down.clone(),
+            self.downlink_tx.clone(),
+            gateway_id,
+        ));
+
+        // In case the server uses a different port for uplink, a second
+        // socket is used for sending PUSH_DATA and receiving PUSH_ACK.
+        let socket_up = if self.server_up == self.server_down {
+            socket_down.clone()
+        } else {
+            let socket_up = self.connect_socket(true).await?;
             let (stop_tx, stop_rx) = oneshot::channel::<()>();
+            stop.push(stop_tx);
 
             tokio::spawn(handle_downlink(
                 self.server.clone(),
                 stop_rx,
                 self.uplink_only,
-                socket.clone(),
+                self.unreachable_after.is_some(),
+                socket_up.clone(),
                 self.downlink_tx.clone(),
                 gateway_id,
             ));
 
-            e.insert(ServerSocket {
+            socket_up
+        };
+
+        self.sockets.insert(
+            gateway_id,
+            ServerSocket {
                 last_uplink: SystemTime::now(),
                 push_data_token: None,
                 pull_data_token: None,
                 pull_resp_token: None,
-                _stop_tx: stop_tx,
-                socket,
-            });
+                _stop_tx: stop,
+                socket_up,
+                socket_down,
+            },
+        );
+
+        set_server_sockets(&self.server, self.socket_count()).await;
+
+        Ok(())
+    }
+
+    async fn connect_socket(&mut self, up: bool) -> Result<Arc<UdpSocket>> {
+        let socket = self.bind_socket().await?;
+        let addr = if up {
+            &self.server_up
+        } else {
+            &self.server_down
+        };
+        socket.connect(addr).await.context("UDP socket connect")?;
+
+        Ok(Arc::new(socket))
+    }
+
+    // Binds a new socket, using the next available port of the local port
+    // range if configured.
+    async fn bind_socket(&mut self) -> Result<UdpSocket> {
+        let (start, end) = match self.local_port_range {
+            Some(v) => v,
+            None => {
+                return match udp::bind(SocketAddr::new(self.local_address, 0), &self.socket_options)
+                {
+                    Ok(v) => Ok(v),
+                    Err(e) => Err(self.bind_error(e).await),
+                }
+            }
+        };
+
+        for _ in start..=end {
+            let port = self.next_port;
+            self.next_port = if port >= end { start } else { port + 1 };
+
+            match udp::bind(
+                SocketAddr::new(self.local_address, port),
+                &self.socket_options,
+            ) {
+                Ok(v) => return Ok(v),
+                Err(e) if e.kind() == io::ErrorKind::AddrInUse => continue,
+                Err(e) => return Err(self.bind_error(e).await),
+            }
         }
 
-        // This should never error since we check the existence of the GatewayId key above.
-        let socket = self
-            .sockets
-            .get_mut(&gateway_id)
-            .ok_or_else(|| anyhow!("Gateway ID not found"))?;
+        inc_server_socket_error_count(&self.server, "port_range_exhausted").await;
+        Err(anyhow!(
+            "No free local port in local_port_range: {}-{}",
+            start,
+            end
+        ))
+    }
 
-        Ok(socket)
+    async fn bind_error(&self, e: io::Error) -> anyhow::Error {
+        if matches!(e.raw_os_error(), Some(libc::EMFILE) | Some(libc::ENFILE)) {
+            inc_server_socket_error_count(&self.server, "fd_limit").await;
+            return anyhow::Error::new(e).context(
+                "UDP socket bind, file-descriptor limit reached (increase the limit or configure max_sockets)",
+            );
+        }
+
+        inc_server_socket_error_count(&self.server, "bind").await;
+        anyhow::Error::new(e).context("UDP socket bind")
     }
 }
 
 struct ServerSocket {
     last_uplink: SystemTime,
-    _stop_tx: oneshot::Sender<()>,
-    socket: Arc<UdpSocket>,
+    _stop_tx: Vec<oneshot::Sender<()>>,
+    // Socket for PUSH_DATA, this is the same socket as socket_down in case the
+    // server uses a single port.
+    socket_up: Arc<UdpSocket>,
+    // Socket for PULL_DATA and TX_ACK.
+    socket_down: Arc<UdpSocket>,
     pull_data_token: Option<u16>,
     push_data_token: Option<u16>,
     pull_resp_token: Option<u16>,
 }
 
 pub async fn setup(
-    downlink_tx: UnboundedSender<(GatewayId, Vec<u8>)>,
-    uplink_rx: UnboundedReceiver<(GatewayId, Vec<u8>)>,
+    downlink_tx: UnboundedSender<(GatewayId, Bytes)>,
+    uplink_rx: UnboundedReceiver<(GatewayId, Bytes)>,
     servers: Vec<config::Server>,
 ) -> Result<()> {
     info!("Setting up forwarder");
 
+    // Each (gateway, server) combination uses one or two UDP sockets. Warn in
+    // case the configured socket limits exceed the file-descriptor limit.
+    if let Some(fd_limit) = get_fd_limit() {
+        let max_sockets: usize = servers.iter().map(|v| v.max_sockets).sum();
+        let unlimited = servers.iter().any(|v| v.max_sockets == 0);
+
+        info!(fd_limit = fd_limit, "File-descriptor limit");
+
+        if !unlimited && max_sockets as u64 > fd_limit {
+            warn!(
+                fd_limit = fd_limit,
+                max_sockets = max_sockets,
+                "Sum of server max_sockets exceeds the file-descriptor limit"
+            );
+        }
+    }
+
+    // In case the forwarder is setup again, the servers of the previous setup
+    // are replaced.
+    SERVERS
+        .get_or_init(|| async { RwLock::new(Vec::new()) })
+        .await
+        .write()
+        .await
+        .clear();
+
+    let track_reachability = servers.iter().any(|v| v.unreachable_after_secs != 0);
+    let track_acks = servers
+        .iter()
+        .any(|v| v.retransmit.max_retries != 0 || v.buffer.max_packets != 0);
+
     for server in servers {
-        add_server(
-            server.server.clone(),
-            server.uplink_only,
-            server.gateway_id_prefixes.clone(),
-            downlink_tx.clone(),
-        )
-        .await?;
+        add_server(server, downlink_tx.clone()).await?;
     }
 
     tokio::spawn(handle_uplink(uplink_rx));
     tokio::spawn(cleanup_sockets());
 
+    if track_acks {
+        tokio::spawn(handle_push_data_acks());
+    }
+
+    if track_reachability {
+        tokio::spawn(handle_server_states());
+    }
+
     Ok(())
 }
 
-async fn handle_uplink(mut uplink_rx: UnboundedReceiver<(GatewayId, Vec<u8>)>) {
+/// Returns the local addresses of the sockets used for forwarding the packets
+/// of the given gateway, with for each address the server. The sockets are
+/// created on the first packet of the gateway, by default these are bound to
+/// a port assigned by the OS.
+pub async fn get_bound_addrs(gateway_id: GatewayId) -> Vec<(String, SocketAddr)> {
+    let servers = SERVERS
+        .get_or_init(|| async { RwLock::new(Vec::new()) })
+        .await;
+    let servers = servers.read().await;
+
+    let mut out = Vec::new();
+    for server in servers.iter() {
+        let Some(socket) = server.sockets.get(&gateway_id) else {
+            continue;
+        };
+
+        for s in [&socket.socket_up, &socket.socket_down] {
+            if let Ok(addr) = s.local_addr() {
+                if !out.contains(&(server.server.clone(), addr)) {
+                    out.push((server.server.clone(), addr));
+                }
+            }
+        }
+    }
+
+    out
+}
+
+async fn handle_uplink(mut uplink_rx: UnboundedReceiver<(GatewayId, Bytes)>) {
     while let Some((gateway_id, data)) = uplink_rx.recv().await {
-        if let Err(e) = handle_uplink_packet(gateway_id, &data).await {
+        if let Err(e) = handle_uplink_packet(gateway_id, data).await {
             error!(error = %e.full(), "Handle uplink error");
         }
     }
 }
 
-async fn handle_uplink_packet(gateway_id: GatewayId, data: &[u8]) -> Result<()> {
-    let packet_type = PacketType::try_from(data)?;
-    let random_token = get_random_token(data)?;
+async fn handle_uplink_packet(gateway_id: GatewayId, data: Bytes) -> Result<()> {
+    let packet_type = PacketType::try_from(&data[..])?;
+    let random_token = get_random_token(&data)?;
+
+    let metadata = match packet_type {
+        PacketType::PushData => metadata::get(gateway_id).await,
+        _ => None,
+    };
+
+    // Servers to which the PUSH_DATA or PULL_DATA was forwarded, used in ACK
+    // pass-through mode.
+    let mut forwarded = Vec::new();
 
     let servers = SERVERS
         .get_or_init(|| async { RwLock::new(Vec::new()) })
@@ -139,31 +521,126 @@ async fn handle_uplink_packet(gateway_id: GatewayId, data: &[u8]) -> Result<()>
             continue;
         }
 
-        let socket = server.get_server_socket(gateway_id).await?;
+        let mut data = match packet_type {
+            PacketType::PushData => match server.prepare_push_data(&data, metadata.as_deref()) {
+                Ok(Some(v)) => v,
+                Ok(None) => continue,
+                Err(e) => {
+                    error!(server = %server.server, error = %e.full(), "Prepare PUSH_DATA error");
+                    continue;
+                }
+            },
+            _ => data.clone(),
+        };
+
+        // Only PUSH_DATA is rate limited, PULL_DATA and TX_ACK are needed to
+        // keep the downlink path intact.
+        if let (PacketType::PushData, Some(rate_limiter)) = (packet_type, &mut server.rate_limiter)
+        {
+            if !rate_limiter.allow(data.len()) {
+                debug!(
+                    server = %server.server,
+                    gateway_id = %gateway_id,
+                    "Server rate limit exceeded, dropping PUSH_DATA"
+                );
+                inc_server_udp_throttled_count(&server.server, packet_type).await;
+                continue;
+            }
+        }
+
+        // The server socket is bound to the real Gateway ID, therefore the
+        // PULL_RESP received on it is always sent to the real gateway.
+        let server_gateway_id = server.get_server_gateway_id(gateway_id);
+        if server_gateway_id != gateway_id {
+            trace!(gateway_id = %gateway_id, server_gateway_id = %server_gateway_id, "Rewriting Gateway ID");
+            let mut b = BytesMut::from(&data[..]);
+            b[4..12].copy_from_slice(&server_gateway_id.as_bytes_be());
+            data = b.freeze();
+        }
+
+        let socket = match server.get_server_socket(gateway_id).await {
+            Ok(v) => v,
+            Err(e) if e.is::<SocketLimitReached>() => continue,
+            Err(e) => {
+                error!(server = %server.server, gateway_id = %gateway_id, error = %e.full(), "Get server socket error");
+                continue;
+            }
+        };
         socket.last_uplink = SystemTime::now();
 
-        let span = tracing::info_span!("", addr = %socket.socket.peer_addr().unwrap());
+        let udp_socket = match packet_type {
+            PacketType::PushData => socket.socket_up.clone(),
+            _ => socket.socket_down.clone(),
+        };
+
+        let span = tracing::info_span!("", addr = %udp_socket.peer_addr().unwrap());
         let _enter = span.enter();
 
         match packet_type {
             PacketType::PushData => {
                 info!(packet_type = %packet_type, "Sending UDP packet");
                 socket.push_data_token = Some(random_token);
-                socket.socket.send(data).await.context("Send UDP packet")?;
+                server.unacked_since.get_or_insert_with(Instant::now);
+
+                // Track the PUSH_DATA before sending it, such that it is
+                // retransmitted or buffered in case sending fails.
+                if server.tracks_push_ack() {
+                    server.pending.insert(
+                        (gateway_id, random_token),
+                        PendingPushData {
+                            data: data.clone(),
+                            sent_at: Instant::now(),
+                            received_at: SystemTime::now(),
+                            retries: 0,
+                        },
+                    );
+                }
+
+                if let Err(e) = send_packet(&server.server, gateway_id, &udp_socket, &data).await {
+                    error!(server = %server.server, gateway_id = %gateway_id, error = %e.full(), "Send UDP packet error");
+                    inc_server_udp_send_error_count(&server.server, packet_type).await;
+                    continue;
+                }
                 inc_server_udp_sent_count(&server.server, packet_type).await;
+                forwarded.push((server.server.clone(), server.primary));
             }
             PacketType::PullData => {
                 info!(packet_type = %packet_type, "Sending UDP packet");
                 socket.pull_data_token = Some(random_token);
-                socket.socket.send(data).await.context("Send UDP packet")?;
+                server.unacked_since.get_or_insert_with(Instant::now);
+
+                if let Err(e) = send_packet(&server.server, gateway_id, &udp_socket, &data).await {
+                    error!(server = %server.server, gateway_id = %gateway_id, error = %e.full(), "Send UDP packet error");
+                    inc_server_udp_send_error_count(&server.server, packet_type).await;
+                    continue;
+                }
                 inc_server_udp_sent_count(&server.server, packet_type).await;
+                forwarded.push((server.server.clone(), server.primary));
             }
             PacketType::TxAck => {
                 if let Some(pull_resp_token) = socket.pull_resp_token {
                     if pull_resp_token == random_token {
                         info!(packet_type = %packet_type, "Sending UDP packet");
                         socket.pull_resp_token = None;
-                        socket.socket.send(data).await.context("Send UDP packet")?;
+
+                        if let Some(error) = get_tx_ack_error(&data) {
+                            warn!(server = %server.server, gateway_id = %gateway_id, error = %error, "Downlink rejected by gateway");
+                            events::emit(Event::DownlinkRejected {
+                                gateway_id,
+                                server: server.server.clone(),
+                                error,
+                                time: events::timestamp(SystemTime::now()),
+                            })
+                            .await;
+                        }
+
+                        if let Err(e) =
+                            send_packet(&server.server, gateway_id, &udp_socket, &data).await
+                        {
+                            error!(server = %server.server, gateway_id = %gateway_id, error = %e.full(), "Send UDP packet error");
+                            inc_server_udp_send_error_count(&server.server, packet_type).await;
+                            continue;
+                        }
                         inc_server_udp_sent_count(&server.server, packet_type).await;
                     }
                 }
@@ -172,6 +649,38 @@ async fn handle_uplink_packet(gateway_id: GatewayId, data: &[u8]) -> Result<()>
         }
     }
 
+    // Release the servers lock first, as the server ACK might already have
+    // been received.
+    drop(servers);
+
+    match packet_type {
+        PacketType::PushData => {
+            acks::forwarded(gateway_id, PacketType::PushAck, random_token, forwarded).await
+        }
+        PacketType::PullData => {
+            acks::forwarded(gateway_id, PacketType::PullAck, random_token, forwarded).await
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+// Sends the packet to the server using the (connected) socket.
+async fn send_packet(
+    server: &str,
+    gateway_id: GatewayId,
+    socket: &UdpSocket,
+    data: &[u8],
+) -> Result<()> {
+    socket.send(data).await.context("Send UDP packet")?;
+
+    if capture::is_enabled() {
+        if let (Ok(local_addr), Ok(peer_addr)) = (socket.local_addr(), socket.peer_addr()) {
+            capture::capture(Some(gateway_id), Some(server), local_addr, peer_addr, data);
+        }
+    }
+
     Ok(())
 }
 
@@ -179,8 +688,11 @@ async fn handle_downlink(
     server: String,
     mut stop_rx: oneshot::Receiver<()>,
     uplink_only: bool,
+    // Only in case the server reachability is tracked, the received ACKs
+    // update the server state (which requires the servers write lock).
+    track_reachability: bool,
     socket: Arc<UdpSocket>,
-    downlink_tx: UnboundedSender<(GatewayId, Vec<u8>)>,
+    downlink_tx: UnboundedSender<(GatewayId, Bytes)>,
     gateway_id: GatewayId,
 ) {
     let mut buffer: [u8; 65535] = [0; 65535];
@@ -188,21 +700,42 @@ async fn handle_downlink(
     loop {
         let (size, addr) = tokio::select! {
             _ = &mut stop_rx => {
-                break;
+                debug!("Downlink loop has ended");
+                return;
             }
            v = socket.recv_from(&mut buffer) =>
                 match v  {
                     Ok(v) => v,
+                    // E.g. an ICMP port unreachable in case the server is
+                    // (temporarily) not listening.
+                    Err(e) if matches!(
+                        e.kind(),
+                        io::ErrorKind::ConnectionRefused
+                            | io::ErrorKind::ConnectionReset
+                            | io::ErrorKind::Interrupted
+                    ) => {
+                        warn!(server = %server, gateway_id = %gateway_id, error = %e, "UDP socket receive error");
+                        continue;
+                    }
                     Err(e) => {
-                        error!(error = %e, "UDP socket receive error");
+                        error!(server = %server, gateway_id = %gateway_id, error = %e, "UDP socket receive error");
                         break;
                     },
                 },
-            else => {
-                break;
-            }
         };
 
+        if capture::is_enabled() {
+            if let Ok(local_addr) = socket.local_addr() {
+                capture::capture(
+                    Some(gateway_id),
+                    Some(&server),
+                    addr,
+                    local_addr,
+                    &buffer[..size],
+                );
+            }
+        }
+
         if size < 4 {
             warn!(addr = %addr, received_bytes = size, "At least 4 bytes are expected");
             continue;
@@ -211,6 +744,7 @@ async fn handle_downlink(
         if let Err(e) = handle_downlink_packet(
             &server,
             uplink_only,
+            track_reachability,
             &downlink_tx,
             gateway_id,
             &buffer[..size],
@@ -222,13 +756,36 @@ async fn handle_downlink(
         }
     }
 
+    // The socket can no longer receive, remove it so that it is re-created on
+    // the next uplink of the gateway.
+    remove_server_socket(&server, gateway_id, &socket).await;
     debug!("Downlink loop has ended");
 }
 
+async fn remove_server_socket(server: &str, gateway_id: GatewayId, socket: &Arc<UdpSocket>) {
+    let servers = SERVERS
+        .get_or_init(|| async { RwLock::new(Vec::new()) })
+        .await;
+    let mut servers = servers.write().await;
+
+    for s in servers.iter_mut().filter(|v| v.server == server) {
+        let is_socket = s.sockets.get(&gateway_id).is_some_and(|v| {
+            Arc::ptr_eq(&v.socket_up, socket) || Arc::ptr_eq(&v.socket_down, socket)
+        });
+
+        if is_socket {
+            warn!(server = %server, gateway_id = %gateway_id, "Removing server socket");
+            s.sockets.remove(&gateway_id);
+            set_server_sockets(&s.server, s.socket_count()).await;
+        }
+    }
+}
+
 async fn handle_downlink_packet(
     server: &str,
     uplink_only: bool,
-    downlink_tx: &UnboundedSender<(GatewayId, Vec<u8>)>,
+    track_reachability: bool,
+    downlink_tx: &UnboundedSender<(GatewayId, Bytes)>,
     gateway_id: GatewayId,
     data: &[u8],
 ) -> Result<()> {
@@ -249,10 +806,17 @@ async fn handle_downlink_packet(
             }
         }
         PacketType::PullAck => {
-            // TODO: keep ack stats
+            acks::acked(server, gateway_id, packet_type, token).await;
+            if track_reachability {
+                set_server_acked(server).await;
+            }
         }
         PacketType::PushAck => {
-            // TODO: keep ack stats
+            acks::acked(server, gateway_id, packet_type, token).await;
+            if track_reachability {
+                set_server_acked(server).await;
+            }
+            handle_push_ack(server, gateway_id, token).await;
         }
 
         _ => {}
@@ -262,47 +826,538 @@ async fn handle_downlink_packet(
 }
 
 async fn handle_pull_resp(
-    downlink_tx: &UnboundedSender<(GatewayId, Vec<u8>)>,
+    downlink_tx: &UnboundedSender<(GatewayId, Bytes)>,
     gateway_id: GatewayId,
     data: &[u8],
 ) -> Result<()> {
     debug!("Sending received data to downlink channel");
     downlink_tx
-        .send((gateway_id, data.to_vec()))
+        .send((gateway_id, Bytes::copy_from_slice(data)))
         .context("Downlink channel send")?;
 
     Ok(())
 }
 
 async fn add_server(
-    server: String,
-    uplink_only: bool,
-    gateway_id_prefixes: Vec<lrwn_filters::EuiPrefix>,
-    downlink_tx: UnboundedSender<(GatewayId, Vec<u8>)>,
+    conf: config::Server,
+    downlink_tx: UnboundedSender<(GatewayId, Bytes)>,
 ) -> Result<()> {
     info!(
-        server = server,
-        uplink_only = uplink_only,
-        gateway_id_prefixes = ?gateway_id_prefixes,
+        server = conf.server,
+        uplink_only = conf.uplink_only,
+        primary = conf.primary,
+        gateway_id_prefixes = ?conf.gateway_id_prefixes,
+        net_ids = ?conf.net_ids,
+        join_eui_prefixes = ?conf.join_eui_prefixes,
+        gateway_id_prefix_rewrite = ?conf.gateway_id_prefix_rewrite,
+        port_up = conf.port_up,
+        port_down = conf.port_down,
+        max_sockets = conf.max_sockets,
+        local_port_range = ?conf.local_port_range,
+        local_address = ?conf.local_address,
+        interface = conf.interface,
+        dscp = conf.dscp,
         "Adding server"
     );
 
+    if conf.dscp > 63 {
+        return Err(anyhow!(
+            "Invalid dscp: {}, server: {}",
+            conf.dscp,
+            conf.server
+        ));
+    }
+
+    ratelimit::check(&conf.rate_limit)
+        .with_context(|| format!("rate_limit, server: {}", conf.server))?;
+
+    if let Some((start, end)) = conf.local_port_range {
+        if start == 0 || start > end {
+            return Err(anyhow!(
+                "Invalid local_port_range: {}-{}, server: {}",
+                start,
+                end,
+                conf.server
+            ));
+        }
+    }
+
     let servers = SERVERS
         .get_or_init(|| async { RwLock::new(Vec::new()) })
         .await;
 
     let mut servers = servers.write().await;
     servers.push(Server {
-        server,
-        uplink_only,
-        gateway_id_prefixes,
+        server_up: config::get_server_addr(&conf.server, conf.port_up),
+        server_down: config::get_server_addr(&conf.server, conf.port_down),
+        server: conf.server,
+        uplink_only: conf.uplink_only,
+        primary: conf.primary,
+        gateway_id_prefixes: conf.gateway_id_prefixes,
+        filters: lrwn_filters::Filters {
+            dev_addr_prefixes: conf.net_ids.iter().map(|v| v.dev_addr_prefix()).collect(),
+            join_eui_prefixes: conf.join_eui_prefixes,
+        },
+        rxpk_filters: conf.rxpk_filters,
+        privacy: conf.privacy,
+        gateway_id_prefix_rewrite: conf.gateway_id_prefix_rewrite,
+        gateway_id_mapping: conf.gateway_id_mapping,
+        inject_location: conf.inject_location,
+        rate_limiter: RateLimiter::new(&conf.rate_limit),
+        max_sockets: conf.max_sockets,
+        socket_limit_logged_at: None,
+        local_port_range: conf.local_port_range,
+        next_port: conf
+            .local_port_range
+            .map(|(start, _)| start)
+            .unwrap_or_default(),
+        local_address: conf
+            .local_address
+            .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+        socket_options: udp::SocketOptions {
+            interface: conf.interface,
+            dscp: conf.dscp,
+            recv_buffer_size: conf.recv_buffer_size,
+            send_buffer_size: conf.send_buffer_size,
+            ..Default::default()
+        },
         downlink_tx,
         sockets: HashMap::new(),
+        pending: HashMap::new(),
+        retransmit: conf.retransmit,
+        buffer: match Buffer::new(&conf.buffer).context("Setup buffer")? {
+            Some(buffer) => Some(ServerBuffer {
+                buffered: buffer.len(),
+                buffer: Arc::new(Mutex::new(buffer)),
+                ack_timeout: Duration::from_millis(conf.buffer.ack_timeout_ms),
+                max_age: Duration::from_secs(conf.buffer.max_age_secs),
+                replay_packets_per_tick: (conf.buffer.replay_packets_per_second as usize
+                    * ACK_TICK.as_millis() as usize
+                    / 1000)
+                    .max(1),
+                online: true,
+            }),
+            None => None,
+        },
+        unreachable_after: (conf.unreachable_after_secs != 0)
+            .then(|| Duration::from_secs(conf.unreachable_after_secs)),
+        unacked_since: None,
+        reachable: true,
     });
 
     Ok(())
 }
 
+// Returns the soft limit of the number of open file-descriptors.
+fn get_fd_limit() -> Option<u64> {
+    let mut rlim = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+
+    // SAFETY: rlim is a valid pointer to a rlimit struct.
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut rlim) } != 0
+        || rlim.rlim_cur == libc::RLIM_INFINITY
+    {
+        return None;
+    }
+
+    Some(rlim.rlim_cur)
+}
+
+// Marks the server as reachable, as it acknowledged the PUSH_DATA or
+// PULL_DATA.
+async fn set_server_acked(srv: &str) {
+    let servers = SERVERS
+        .get_or_init(|| async { RwLock::new(Vec::new()) })
+        .await;
+    let mut servers = servers.write().await;
+
+    for server in servers.iter_mut() {
+        if server.server.eq(srv) {
+            server.unacked_since = None;
+
+            if !server.reachable {
+                info!(server = %server.server, "Server is reachable again");
+                server.reachable = true;
+                events::emit(Event::ServerRecovered {
+                    server: server.server.clone(),
+                    time: events::timestamp(SystemTime::now()),
+                })
+                .await;
+            }
+        }
+    }
+}
+
+// Marks servers as unreachable which did not acknowledge the PUSH_DATA or
+// PULL_DATA within the configured duration.
+async fn handle_server_states() {
+    loop {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        let servers = SERVERS
+            .get_or_init(|| async { RwLock::new(Vec::new()) })
+            .await;
+        let mut servers = servers.write().await;
+
+        for server in servers.iter_mut() {
+            let (Some(unreachable_after), Some(unacked_since)) =
+                (server.unreachable_after, server.unacked_since)
+            else {
+                continue;
+            };
+
+            if server.reachable && unacked_since.elapsed() >= unreachable_after {
+                warn!(server = %server.server, "Server is unreachable, no ACK received");
+                server.reachable = false;
+                events::emit(Event::ServerUnreachable {
+                    server: server.server.clone(),
+                    time: events::timestamp(SystemTime::now()),
+                })
+                .await;
+            }
+        }
+    }
+}
+
+async fn handle_push_ack(srv: &str, gateway_id: GatewayId, token: u16) {
+    let servers = SERVERS
+        .get_or_init(|| async { RwLock::new(Vec::new()) })
+        .await;
+
+    // Only take the write lock in case the pending PUSH_DATA or the buffer
+    // state must be updated.
+    {
+        let servers = servers.read().await;
+        let needs_update = servers.iter().any(|server| {
+            server.server.eq(srv)
+                && server.tracks_push_ack()
+                && (server.pending.contains_key(&(gateway_id, token))
+                    || server.buffer.as_ref().is_some_and(|buffer| !buffer.online))
+        });
+
+        if !needs_update {
+            return;
+        }
+    }
+
+    let mut servers = servers.write().await;
+
+    for server in servers.iter_mut() {
+        if server.server.eq(srv) {
+            if let Some(pending) = server.pending.remove(&(gateway_id, token)) {
+                if pending.retries != 0 {
+                    inc_server_retransmit_count(&server.server, "acknowledged").await;
+                }
+            }
+
+            if let Some(buffer) = &mut server.buffer {
+                if !buffer.online {
+                    info!(
+                        server = %server.server,
+                        buffered = buffer.buffered,
+                        "Server is acknowledging PUSH_DATA again, replaying buffered PUSH_DATA"
+                    );
+                    buffer.online = true;
+                }
+            }
+        }
+    }
+}
+
+async fn handle_push_data_acks() {
+    loop {
+        tokio::time::sleep(ACK_TICK).await;
+
+        let servers = SERVERS
+            .get_or_init(|| async { RwLock::new(Vec::new()) })
+            .await;
+
+        let mut updates = Vec::new();
+        {
+            let mut servers = servers.write().await;
+
+            for server in servers.iter_mut() {
+                if !server.tracks_push_ack() {
+                    continue;
+                }
+
+                if let Some(update) = handle_server_push_data_acks(server).await {
+                    updates.push(update);
+                }
+            }
+        }
+
+        // The buffer might read from or write to the spill file, this is done
+        // without holding the servers lock.
+        for update in updates {
+            let server_name = update.server.clone();
+            let buffer = update.buffer.clone();
+            let res = match tokio::task::spawn_blocking(move || update_buffer(update)).await {
+                Ok(v) => v,
+                Err(e) => {
+                    error!(server = %server_name, error = %e, "Update buffer error");
+                    continue;
+                }
+            };
+
+            for (action, count) in [
+                ("buffered", res.stored),
+                ("dropped", res.dropped),
+                ("expired", res.expired),
+            ] {
+                for _ in 0..count {
+                    inc_server_buffer_count(&server_name, action).await;
+                }
+            }
+
+            let mut servers = servers.write().await;
+            let Some(server) = servers.iter_mut().find(|v| v.server == server_name) else {
+                continue;
+            };
+
+            let requeue = replay_push_data(server, res.replay).await;
+            let buffered = if requeue.is_empty() {
+                res.buffered
+            } else {
+                let mut buffer = buffer.lock().unwrap_or_else(|e| e.into_inner());
+                buffer.push_front(requeue);
+                buffer.len()
+            };
+
+            if let Some(buffer) = &mut server.buffer {
+                buffer.buffered = buffered;
+            }
+            set_server_buffer_packets(&server.server, buffered).await;
+        }
+    }
+}
+
+// Buffer operations of a server, these are performed without holding the
+// servers lock.
+struct BufferUpdate {
+    server: String,
+    buffer: Arc<Mutex<Buffer>>,
+    // PUSH_DATA which were not acknowledged within the ACK timeout.
+    timed_out: Vec<Entry>,
+    // Max. number of PUSH_DATA to return for replay.
+    replay: usize,
+    max_age: Duration,
+}
+
+#[derive(Default)]
+struct BufferUpdateResult {
+    replay: Vec<Entry>,
+    buffered: usize,
+    stored: usize,
+    dropped: usize,
+    expired: usize,
+}
+
+// Stores the timed-out PUSH_DATA and returns the PUSH_DATA to replay.
+fn update_buffer(update: BufferUpdate) -> BufferUpdateResult {
+    let mut res = BufferUpdateResult::default();
+    let mut buffer = update.buffer.lock().unwrap_or_else(|e| e.into_inner());
+
+    for entry in update.timed_out {
+        match buffer.push(entry) {
+            Ok(true) => res.stored += 1,
+            Ok(false) => {
+                warn!(server = %update.server, "Buffer is full, dropping PUSH_DATA");
+                res.dropped += 1;
+            }
+            Err(e) => {
+                error!(server = %update.server, error = %e.full(), "Buffer PUSH_DATA error");
+                res.dropped += 1;
+            }
+        }
+    }
+
+    while res.replay.len() < update.replay {
+        let entry = match buffer.pop() {
+            Ok(Some(v)) => v,
+            Ok(None) => break,
+            Err(e) => {
+                error!(server = %update.server, error = %e.full(), "Read buffered PUSH_DATA error");
+                break;
+            }
+        };
+
+        if SystemTime::now()
+            .duration_since(entry.stored_at)
+            .unwrap_or_default()
+            > update.max_age
+        {
+            res.expired += 1;
+            continue;
+        }
+
+        res.replay.push(entry);
+    }
+
+    res.buffered = buffer.len();
+    res
+}
+
+// Retransmits the PUSH_DATA that have not been acknowledged within the
+// retransmit timeout. Once the retries are exhausted, the PUSH_DATA is
+// returned for buffering (if enabled) after the ACK timeout, together with
+// the number of buffered PUSH_DATA to replay in case the server is online.
+async fn handle_server_push_data_acks(server: &mut Server) -> Option<BufferUpdate> {
+    let max_retries = server.retransmit.max_retries;
+    let retransmit_timeout = Duration::from_millis(server.retransmit.timeout_ms);
+    let ack_timeout = server
+        .buffer
+        .as_ref()
+        .map(|v| v.ack_timeout)
+        .unwrap_or(retransmit_timeout);
+
+    let mut retransmit = Vec::new();
+    let mut timed_out = Vec::new();
+    server.pending.retain(|(gateway_id, token), v| {
+        if v.retries < max_retries {
+            if v.sent_at.elapsed() >= retransmit_timeout {
+                retransmit.push((*gateway_id, *token));
+            }
+            true
+        } else if v.sent_at.elapsed() >= ack_timeout {
+            timed_out.push((
+                v.sent_at,
+                Entry {
+                    gateway_id: *gateway_id,
+                    stored_at: v.received_at,
+                    data: v.data.clone(),
+                },
+            ));
+            false
+        } else {
+            true
+        }
+    });
+
+    // Buffer in the order in which the PUSH_DATA were sent.
+    timed_out.sort_by_key(|(sent_at, _)| *sent_at);
+    let timed_out: Vec<Entry> = timed_out.into_iter().map(|(_, v)| v).collect();
+
+    // The timed-out PUSH_DATA are handed to the buffer before retransmitting,
+    // such that these are not lost in case of a send error.
+    let update = match &mut server.buffer {
+        Some(buffer) => {
+            if !timed_out.is_empty() && buffer.online {
+                warn!(
+                    server = %server.server,
+                    "Server is not acknowledging PUSH_DATA, buffering PUSH_DATA"
+                );
+                buffer.online = false;
+            }
+
+            Some(BufferUpdate {
+                server: server.server.clone(),
+                buffer: buffer.buffer.clone(),
+                timed_out,
+                replay: if buffer.online {
+                    buffer.replay_packets_per_tick
+                } else {
+                    0
+                },
+                max_age: buffer.max_age,
+            })
+        }
+        None => {
+            for entry in timed_out {
+                warn!(
+                    server = %server.server,
+                    gateway_id = %entry.gateway_id,
+                    retries = max_retries,
+                    "PUSH_DATA was not acknowledged by server"
+                );
+                inc_server_retransmit_count(&server.server, "failed").await;
+            }
+
+            None
+        }
+    };
+
+    for (gateway_id, token) in retransmit {
+        if let Err(e) = retransmit_push_data(server, gateway_id, token).await {
+            error!(server = %server.server, gateway_id = %gateway_id, token = token, error = %e.full(), "Retransmit PUSH_DATA error");
+        }
+    }
+
+    update
+}
+
+async fn retransmit_push_data(
+    server: &mut Server,
+    gateway_id: GatewayId,
+    token: u16,
+) -> Result<()> {
+    let Some(pending) = server.pending.get_mut(&(gateway_id, token)) else {
+        return Ok(());
+    };
+
+    // The retry is counted (and sent_at updated) before sending, such that a
+    // failing send does not result in an endless number of retries.
+    pending.sent_at = Instant::now();
+    pending.retries += 1;
+    let data = pending.data.clone();
+    let retries = pending.retries;
+
+    let socket = server.get_server_socket(gateway_id).await?;
+    let udp_socket = socket.socket_up.clone();
+
+    debug!(server = %server.server, gateway_id = %gateway_id, token = token, retries = retries, "Retransmitting PUSH_DATA");
+    send_packet(&server.server, gateway_id, &udp_socket, &data).await?;
+    inc_server_retransmit_count(&server.server, "retransmitted").await;
+
+    Ok(())
+}
+
+// Replays the given buffered PUSH_DATA. In case of an error, the failed and
+// remaining PUSH_DATA are returned, these must be re-queued at the front of
+// the buffer.
+async fn replay_push_data(server: &mut Server, replay: Vec<Entry>) -> Vec<Entry> {
+    let mut replay = replay.into_iter();
+
+    while let Some(entry) = replay.next() {
+        if let Err(e) = replay_entry(server, &entry).await {
+            error!(server = %server.server, gateway_id = %entry.gateway_id, error = %e.full(), "Replay buffered PUSH_DATA error");
+            return std::iter::once(entry).chain(replay).collect();
+        }
+    }
+
+    Vec::new()
+}
+
+async fn replay_entry(server: &mut Server, entry: &Entry) -> Result<()> {
+    let token = get_random_token(&entry.data)?;
+    let socket = server.get_server_socket(entry.gateway_id).await?;
+    let udp_socket = socket.socket_up.clone();
+
+    debug!(server = %server.server, gateway_id = %entry.gateway_id, "Replaying buffered PUSH_DATA");
+    send_packet(&server.server, entry.gateway_id, &udp_socket, &entry.data).await?;
+
+    if let Some(socket) = server.sockets.get_mut(&entry.gateway_id) {
+        socket.push_data_token = Some(token);
+    }
+    inc_server_buffer_count(&server.server, "replayed").await;
+
+    // The replayed PUSH_DATA is tracked like any other PUSH_DATA, it is
+    // buffered again if it is not acknowledged.
+    server.pending.insert(
+        (entry.gateway_id, token),
+        PendingPushData {
+            data: entry.data.clone(),
+            sent_at: Instant::now(),
+            received_at: entry.stored_at,
+            retries: 0,
+        },
+    );
+
+    Ok(())
+}
+
 async fn cleanup_sockets() {
     loop {
         tokio::time::sleep(tokio::time::Duration::from_secs(60)).await;
@@ -315,6 +1370,8 @@ async fn cleanup_sockets() {
         let mut servers = servers.write().await;
 
         for server in servers.iter_mut() {
+            let socket_count = server.socket_count();
+
             server.sockets.retain(|k, v| {
                 if let Ok(duration) = SystemTime::now().duration_since(v.last_uplink) {
                     if duration < Duration::from_secs(60) {
@@ -328,6 +1385,10 @@ async fn cleanup_sockets() {
                     false
                 }
             });
+
+            if server.socket_count() != socket_count {
+                set_server_sockets(&server.server, server.socket_count()).await;
+            }
         }
     }
 }
@@ -1,7 +1,21 @@
+pub mod acks;
+pub mod allowlist;
+pub mod buffer;
+pub mod capture;
 pub mod cmd;
 pub mod config;
+pub mod dedup;
+pub mod events;
+pub mod filters;
 pub mod forwarder;
 pub mod listener;
+pub mod metadata;
 pub mod monitoring;
 pub mod packets;
+pub mod privacy;
+pub mod ratelimit;
+#[cfg(feature = "testing")]
+pub mod testing;
 pub mod traits;
+pub mod udp;
+pub mod webhooks;
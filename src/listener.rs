@@ -1,84 +1,309 @@
-use std::collections::HashMap;
+use std::collections::{hash_map::Entry, HashMap, VecDeque};
 use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use anyhow::{anyhow, Context, Result};
-use tokio::net::UdpSocket;
+use bytes::Bytes;
+use serde::Serialize;
+use tokio::net::{lookup_host, UdpSocket};
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 use tokio::sync::{OnceCell, RwLock};
 use tracing::{debug, error, info, trace, warn, Instrument};
 
-use crate::monitoring::{inc_gateway_udp_received_count, inc_gateway_udp_sent_count};
+use crate::acks;
+use crate::allowlist;
+use crate::capture;
+use crate::config;
+use crate::dedup::DedupWindow;
+use crate::events::{self, Event};
+use crate::metadata;
+use crate::monitoring::{
+    inc_gateway_address_conflict_count, inc_gateway_downlink_queue_count,
+    inc_gateway_udp_duplicate_count, inc_gateway_udp_received_count,
+    inc_gateway_udp_rejected_count, inc_gateway_udp_sent_count, inc_gateway_udp_throttled_count,
+    set_gateway_last_seen, set_gateways_online,
+};
 use crate::packets::{get_random_token, GatewayId, PacketType};
+use crate::ratelimit::{self, RateLimiter};
 use crate::traits::PrintFullError;
+use crate::udp;
 
 static GATEWAYS: OnceCell<RwLock<HashMap<GatewayId, Gateway>>> = OnceCell::const_new();
+static RATE_LIMITERS: OnceCell<RwLock<HashMap<GatewayId, RateLimiter>>> = OnceCell::const_new();
+static DEDUP_WINDOWS: OnceCell<RwLock<HashMap<GatewayId, DedupWindow>>> = OnceCell::const_new();
+static GATEWAY_STATES: OnceCell<RwLock<HashMap<GatewayId, GatewayState>>> = OnceCell::const_new();
+static DOWNLINK_QUEUES: OnceCell<RwLock<HashMap<GatewayId, VecDeque<QueuedDownlink>>>> =
+    OnceCell::const_new();
+static BOUND_ADDRS: OnceCell<RwLock<Vec<SocketAddr>>> = OnceCell::const_new();
 
 struct Gateway {
     addr: SocketAddr,
+    // Socket on which the PULL_DATA was received, this socket is used for
+    // sending downlinks to the gateway.
+    socket: Arc<UdpSocket>,
     last_seen: SystemTime,
+    // Address which is not (yet) accepted and its number of consecutive
+    // PULL_DATA packets.
+    candidate: Option<(SocketAddr, u32)>,
+    conflict_count: u64,
+    conflict_addr: Option<SocketAddr>,
+    conflict_at: Option<SystemTime>,
+}
+
+// ACK packet which is sent after handling the received batch of packets.
+struct Ack {
+    gateway_id: GatewayId,
+    packet_type: PacketType,
+    addr: SocketAddr,
+    data: [u8; 4],
+}
+
+// Connection state of the gateway. Unlike the Gateway ID to addr mapping,
+// this is never removed so that a gateway is only seen for the first time
+// once.
+struct GatewayState {
+    last_seen: SystemTime,
+    online: bool,
+}
+
+// Downlink for a gateway of which the address is not (yet) known, it is sent
+// on the next PULL_DATA of the gateway.
+struct QueuedDownlink {
+    queued_at: Instant,
+    data: Bytes,
+}
+
+#[derive(Serialize)]
+pub struct GatewayConflict {
+    pub gateway_id: GatewayId,
+    pub addr: SocketAddr,
+    pub conflict_addr: Option<SocketAddr>,
+    pub conflict_count: u64,
+    pub last_conflict_at: u64,
 }
 
 pub async fn setup(
-    bind: &str,
+    conf: &config::Configuration,
 ) -> Result<(
-    UnboundedSender<(GatewayId, Vec<u8>)>,
-    UnboundedReceiver<(GatewayId, Vec<u8>)>,
+    UnboundedSender<(GatewayId, Bytes)>,
+    UnboundedReceiver<(GatewayId, Bytes)>,
 )> {
-    info!(host = bind, "Setting up listener");
+    let (uplink_tx, uplink_rx) = unbounded_channel::<(GatewayId, Bytes)>();
+    let (downlink_tx, downlink_rx) = unbounded_channel::<(GatewayId, Bytes)>();
+
+    let gateways_conf = Arc::new(conf.gateways.clone());
+
+    // Only the addresses of the current setup are reported.
+    BOUND_ADDRS
+        .get_or_init(|| async { RwLock::new(Vec::new()) })
+        .await
+        .write()
+        .await
+        .clear();
+
+    ratelimit::check(&conf.gateways.rate_limit).context("gateways.rate_limit")?;
+    acks::setup(&conf.gateways.ack, &conf.multiplexer.servers).await?;
+
+    // In case a separate bind for downlink is configured, gateways send their
+    // PULL_DATA to this socket. As downlinks are sent from the socket on which
+    // the PULL_DATA was received, this bind is handled like any other bind.
+    let mut binds = vec![&conf.multiplexer.bind];
+    binds.extend(conf.multiplexer.binds.iter());
+    if !conf.multiplexer.bind_down.is_empty() {
+        binds.push(&conf.multiplexer.bind_down);
+    }
 
-    let (uplink_tx, uplink_rx) = unbounded_channel::<(GatewayId, Vec<u8>)>();
-    let (downlink_tx, downlink_rx) = unbounded_channel::<(GatewayId, Vec<u8>)>();
+    for bind in binds {
+        info!(
+            host = bind,
+            sockets = conf.multiplexer.sockets_per_bind,
+            "Setting up listener"
+        );
 
-    let sock = UdpSocket::bind(bind).await.context("Bind socket")?;
-    let sock = Arc::new(sock);
+        let socks = bind_sockets(bind, &conf.multiplexer).await?;
+        if let Some(sock) = socks.first() {
+            add_bound_addr(sock.local_addr().context("Get local address")?).await;
+        }
+
+        for sock in socks {
+            tokio::spawn(handle_uplink(
+                sock,
+                uplink_tx.clone(),
+                gateways_conf.clone(),
+                conf.multiplexer.udp_batch_size,
+            ));
+        }
+    }
+
+    tokio::spawn(handle_downlink(downlink_rx, gateways_conf.clone()));
+    tokio::spawn(cleanup_gateways(gateways_conf.clone()));
 
-    tokio::spawn(handle_uplink(sock.clone(), uplink_tx));
-    tokio::spawn(handle_downlink(sock.clone(), downlink_rx));
-    tokio::spawn(cleanup_gateways());
+    if conf.gateways.keepalive_interval_secs != 0 && conf.gateways.offline_after_keepalives != 0 {
+        tokio::spawn(handle_gateway_states(gateways_conf.clone()));
+    }
 
     Ok((downlink_tx, uplink_rx))
 }
 
-async fn handle_uplink(socket: Arc<UdpSocket>, uplink_tx: UnboundedSender<(GatewayId, Vec<u8>)>) {
-    let mut buffer: [u8; 65535] = [0; 65535];
+// Binds the given number of sockets to the given interface:port. In case
+// more than one socket is requested, SO_REUSEPORT is set on each socket so
+// that the kernel distributes the received packets over these sockets.
+async fn bind_sockets(bind: &str, conf: &config::Multiplexer) -> Result<Vec<Arc<UdpSocket>>> {
+    let addr = lookup_host(bind)
+        .await
+        .context("Lookup bind address")?
+        .next()
+        .ok_or_else(|| anyhow!("Unable to resolve bind address: {}", bind))?;
+
+    let count = conf.sockets_per_bind.max(1);
+    let opts = udp::SocketOptions {
+        reuse_port: count > 1,
+        recv_buffer_size: conf.recv_buffer_size,
+        send_buffer_size: conf.send_buffer_size,
+        ..Default::default()
+    };
+
+    let mut addr = addr;
+    let mut out = Vec::with_capacity(count);
+    for _ in 0..count {
+        let sock = udp::bind(addr, &opts).context("Bind socket")?;
+
+        // In case of port 0, the other sockets must bind to the port that was
+        // assigned to the first socket.
+        if addr.port() == 0 {
+            addr = sock.local_addr().context("Get local address")?;
+        }
+
+        out.push(Arc::new(sock));
+    }
+
+    Ok(out)
+}
+
+async fn handle_uplink(
+    socket: Arc<UdpSocket>,
+    uplink_tx: UnboundedSender<(GatewayId, Bytes)>,
+    conf: Arc<config::Gateways>,
+    batch_size: usize,
+) {
+    let mut batch = udp::RecvBatch::new(batch_size);
+    let mut acks = Vec::with_capacity(batch_size.max(1));
+
     loop {
-        let (size, addr) = match socket.recv_from(&mut buffer).await {
-            Ok(v) => v,
-            Err(e) => {
-                error!(error = %e, "Receive error");
+        if let Err(e) = batch.recv(&socket).await {
+            error!(error = %e, "Receive error");
+            continue;
+        }
+
+        for (data, addr) in batch.received() {
+            let size = data.len();
+
+            if capture::is_enabled() {
+                if let Ok(local_addr) = socket.local_addr() {
+                    capture::capture(GatewayId::try_from(data).ok(), None, addr, local_addr, data);
+                }
+            }
+
+            if size < 4 {
+                warn!(addr = %addr, received_bytes = size, "At least 4 bytes are expected");
                 continue;
             }
-        };
 
-        if size < 4 {
-            warn!(addr = %addr, received_bytes = size, "At least 4 bytes are expected");
+            // The received data is copied once, it is shared (not copied)
+            // with the forwarder.
+            let data = Bytes::copy_from_slice(data);
+
+            if let Err(e) = handle_uplink_packet(&socket, &uplink_tx, &conf, addr, data, &mut acks)
+                .instrument(tracing::info_span!("", addr = %addr))
+                .await
+            {
+                error!(error = %e.full(), "Handle uplink packet error");
+            }
+        }
+
+        send_acks(&socket, &mut acks).await;
+    }
+}
+
+// Sends the PUSH_ACK and PULL_ACK packets for the received batch.
+async fn send_acks(socket: &UdpSocket, acks: &mut Vec<Ack>) {
+    if acks.is_empty() {
+        return;
+    }
+
+    let packets: Vec<(&[u8], SocketAddr)> = acks.iter().map(|v| (&v.data[..], v.addr)).collect();
+    let errors = udp::send_batch(socket, &packets).await;
+
+    for (i, e) in &errors {
+        let ack = &acks[*i];
+        error!(
+            gateway_id = %ack.gateway_id,
+            addr = %ack.addr,
+            error = %e,
+            "Send ack error"
+        );
+    }
+
+    let local_addr = socket.local_addr().ok();
+    for (i, ack) in acks.iter().enumerate() {
+        if errors.iter().any(|(j, _)| *j == i) {
             continue;
         }
 
-        if let Err(e) = handle_uplink_packet(&socket, &uplink_tx, addr, &buffer[..size])
-            .instrument(tracing::info_span!("", addr = %addr))
-            .await
-        {
-            error!(error = %e.full(), "Handle uplink packet error");
+        if let Some(local_addr) = local_addr {
+            capture::capture(Some(ack.gateway_id), None, local_addr, ack.addr, &ack.data);
         }
+        inc_gateway_udp_sent_count(ack.gateway_id, ack.packet_type).await;
     }
+
+    acks.clear();
 }
 
 async fn handle_uplink_packet(
     socket: &Arc<UdpSocket>,
-    uplink_tx: &UnboundedSender<(GatewayId, Vec<u8>)>,
+    uplink_tx: &UnboundedSender<(GatewayId, Bytes)>,
+    conf: &config::Gateways,
     addr: SocketAddr,
-    data: &[u8],
+    data: Bytes,
+    acks: &mut Vec<Ack>,
 ) -> Result<()> {
-    let packet_type = PacketType::try_from(data)?;
-    let gateway_id = GatewayId::try_from(data)?;
-    let token = get_random_token(data)?;
+    let packet_type = PacketType::try_from(&data[..])?;
+    let gateway_id = GatewayId::try_from(&data[..])?;
+    let token = get_random_token(&data)?;
+
+    if let Err(reason) = allowlist::check(&conf.allowed, gateway_id, addr) {
+        debug!(
+            packet_type = %packet_type,
+            gateway_id = %gateway_id,
+            reason = %reason,
+            "Rejecting UDP packet",
+        );
+        inc_gateway_udp_rejected_count(packet_type, &reason.to_string()).await;
+        return Ok(());
+    }
+
+    // Only PUSH_DATA is rate limited, dropping PULL_DATA would make the gateway
+    // unreachable for downlinks.
+    if packet_type == PacketType::PushData
+        && !check_rate_limit(&conf.rate_limit, gateway_id, data.len()).await
+    {
+        debug!(
+            packet_type = %packet_type,
+            gateway_id = %gateway_id,
+            "Gateway rate limit exceeded, dropping UDP packet",
+        );
+        inc_gateway_udp_throttled_count(gateway_id, packet_type).await;
+        return Ok(());
+    }
+
+    // The gateway_name field is omitted in case there is no metadata.
+    let metadata = metadata::get(gateway_id).await;
 
     info!(
         packet_type = %packet_type,
         gateway_id = %gateway_id,
+        gateway_name = metadata.as_ref().map(|v| v.name.as_str()),
         token = token,
         "UDP packet received",
     );
@@ -86,10 +311,16 @@ async fn handle_uplink_packet(
     inc_gateway_udp_received_count(gateway_id, packet_type).await;
 
     match packet_type {
-        PacketType::PushData => handle_push_data(socket, uplink_tx, addr, gateway_id, data).await?,
+        PacketType::PushData => {
+            set_gateway_seen(gateway_id, addr).await;
+            handle_push_data(uplink_tx, socket, acks, conf, addr, gateway_id, data).await?
+        }
         PacketType::PullData => {
-            set_gateway(gateway_id, addr).await?;
-            handle_pull_data(socket, uplink_tx, addr, gateway_id, data).await?;
+            if set_gateway(gateway_id, addr, socket, conf).await? {
+                set_gateway_seen(gateway_id, addr).await;
+                handle_pull_data(uplink_tx, socket, acks, conf, addr, gateway_id, data).await?;
+                send_queued_downlinks(&conf.downlink_queue, gateway_id, addr, socket).await;
+            }
         }
         PacketType::TxAck => handle_tx_ack(uplink_tx, gateway_id, data).await?,
         _ => warn!(packet_type = %packet_type, "Unexpected packet-type"),
@@ -99,23 +330,43 @@ async fn handle_uplink_packet(
 }
 
 async fn handle_downlink(
-    socket: Arc<UdpSocket>,
-    mut downlink_rx: UnboundedReceiver<(GatewayId, Vec<u8>)>,
+    mut downlink_rx: UnboundedReceiver<(GatewayId, Bytes)>,
+    conf: Arc<config::Gateways>,
 ) {
     while let Some((gateway_id, data)) = downlink_rx.recv().await {
-        if let Err(e) = handle_downlink_packet(&socket, gateway_id, &data).await {
+        if let Err(e) = handle_downlink_packet(&conf.downlink_queue, gateway_id, data).await {
             error!(error = %e.full(), "Handle downlink packet error");
         }
     }
 }
 
 async fn handle_downlink_packet(
-    socket: &Arc<UdpSocket>,
+    conf: &config::DownlinkQueue,
     gateway_id: GatewayId,
+    data: Bytes,
+) -> Result<()> {
+    let (addr, socket) = match get_gateway(gateway_id).await {
+        Ok(v) => v,
+        Err(e) => {
+            if conf.max_packets == 0 {
+                return Err(e);
+            }
+
+            queue_downlink(conf, gateway_id, data).await;
+            return Ok(());
+        }
+    };
+
+    send_downlink(gateway_id, addr, &socket, &data).await
+}
+
+async fn send_downlink(
+    gateway_id: GatewayId,
+    addr: SocketAddr,
+    socket: &UdpSocket,
     data: &[u8],
 ) -> Result<()> {
     let packet_type = PacketType::try_from(data)?;
-    let addr = get_gateway(gateway_id).await?;
     let span = tracing::info_span!("", addr = %addr);
 
     async move {
@@ -130,72 +381,151 @@ async fn handle_downlink_packet(
     .instrument(span)
     .await?;
 
+    if let Ok(local_addr) = socket.local_addr() {
+        capture::capture(Some(gateway_id), None, local_addr, addr, data);
+    }
     inc_gateway_udp_sent_count(gateway_id, packet_type).await;
 
     Ok(())
 }
 
 async fn handle_push_data(
+    uplink_tx: &UnboundedSender<(GatewayId, Bytes)>,
     socket: &Arc<UdpSocket>,
-    uplink_tx: &UnboundedSender<(GatewayId, Vec<u8>)>,
+    acks: &mut Vec<Ack>,
+    conf: &config::Gateways,
     addr: SocketAddr,
     gateway_id: GatewayId,
-    data: &[u8],
+    data: Bytes,
 ) -> Result<()> {
     if data.len() < 12 {
         return Err(anyhow!("At least 12 bytes are expected"));
     }
 
-    info!(packet_type = %PacketType::PushAck, "Sending UDP packet");
+    let token = get_random_token(&data)?;
+    if is_duplicate(conf.dedup_window_ms, gateway_id, token, &data[12..]).await {
+        warn!(gateway_id = %gateway_id, token = token, "Suppressing duplicate PUSH_DATA");
+        inc_gateway_udp_duplicate_count(gateway_id).await;
+
+        // The duplicate might be caused by a lost PUSH_ACK, therefore it is
+        // acknowledged again. In ACK pass-through mode, this is only needed
+        // in case the ACK of the original PUSH_DATA is no longer pending, the
+        // duplicate itself is not forwarded and thus not acknowledged by the
+        // server(s).
+        if !acks::is_pass_through(&conf.ack)
+            || !acks::is_pending(gateway_id, PacketType::PushAck, token).await
+        {
+            info!(packet_type = %PacketType::PushAck, "Sending UDP packet");
+
+            acks.push(Ack {
+                gateway_id,
+                packet_type: PacketType::PushAck,
+                addr,
+                data: [data[0], data[1], data[2], PacketType::PushAck.into()],
+            });
+        }
+
+        return Ok(());
+    }
 
-    let b: [u8; 4] = [data[0], data[1], data[2], PacketType::PushAck.into()];
-    socket.send_to(&b, addr).await.context("Socket send")?;
-    inc_gateway_udp_sent_count(gateway_id, PacketType::PushAck).await;
+    queue_ack(
+        &conf.ack,
+        socket,
+        acks,
+        addr,
+        gateway_id,
+        PacketType::PushAck,
+        [data[0], data[1], data[2], PacketType::PushAck.into()],
+    )
+    .await;
 
     debug!("Sending received data to uplink channel");
     uplink_tx
-        .send((gateway_id, data.to_vec()))
+        .send((gateway_id, data))
         .context("Uplink channel send")?;
 
     Ok(())
 }
 
 async fn handle_tx_ack(
-    uplink_tx: &UnboundedSender<(GatewayId, Vec<u8>)>,
+    uplink_tx: &UnboundedSender<(GatewayId, Bytes)>,
     gateway_id: GatewayId,
-    data: &[u8],
+    data: Bytes,
 ) -> Result<()> {
     uplink_tx
-        .send((gateway_id, data.to_vec()))
+        .send((gateway_id, data))
         .context("Uplink channel send")?;
     Ok(())
 }
 
 async fn handle_pull_data(
+    uplink_tx: &UnboundedSender<(GatewayId, Bytes)>,
     socket: &Arc<UdpSocket>,
-    uplink_tx: &UnboundedSender<(GatewayId, Vec<u8>)>,
+    acks: &mut Vec<Ack>,
+    conf: &config::Gateways,
     addr: SocketAddr,
     gateway_id: GatewayId,
-    data: &[u8],
+    data: Bytes,
 ) -> Result<()> {
     if data.len() < 12 {
         return Err(anyhow!("At least 12 bytes are expected"));
     }
 
-    info!(packet_type = %PacketType::PullAck, "Sending UDP packet");
-
-    let b: [u8; 4] = [data[0], data[1], data[2], PacketType::PullAck.into()];
-    socket.send_to(&b, addr).await.context("Socket send")?;
-    inc_gateway_udp_sent_count(gateway_id, PacketType::PullAck).await;
+    queue_ack(
+        &conf.ack,
+        socket,
+        acks,
+        addr,
+        gateway_id,
+        PacketType::PullAck,
+        [data[0], data[1], data[2], PacketType::PullAck.into()],
+    )
+    .await;
 
     uplink_tx
-        .send((gateway_id, data.to_vec()))
+        .send((gateway_id, data))
         .context("Uplink channel send")?;
 
     Ok(())
 }
 
-async fn set_gateway(gateway_id: GatewayId, addr: SocketAddr) -> Result<()> {
+// Queues the ACK for sending after handling the received batch of packets.
+// In ACK pass-through mode, the ACK is sent once the server(s) have
+// acknowledged the packet.
+async fn queue_ack(
+    conf: &config::Ack,
+    socket: &Arc<UdpSocket>,
+    acks: &mut Vec<Ack>,
+    addr: SocketAddr,
+    gateway_id: GatewayId,
+    packet_type: PacketType,
+    data: [u8; 4],
+) {
+    if acks::is_pass_through(conf) {
+        debug!(packet_type = %packet_type, "Waiting for server ACK");
+        acks::add(conf, gateway_id, packet_type, addr, socket.clone(), data).await;
+        return;
+    }
+
+    info!(packet_type = %packet_type, "Sending UDP packet");
+
+    acks.push(Ack {
+        gateway_id,
+        packet_type,
+        addr,
+        data,
+    });
+}
+
+// Sets or updates the Gateway ID to addr mapping. In case the gateway
+// address changes, the configured address change policy decides if the new
+// address is accepted. Returns false if the address was not accepted.
+async fn set_gateway(
+    gateway_id: GatewayId,
+    addr: SocketAddr,
+    socket: &Arc<UdpSocket>,
+    conf: &config::Gateways,
+) -> Result<bool> {
     trace!(gateway_id = %gateway_id, addr = %addr, "Setting / updating Gateway ID to addr mapping");
 
     let gateways = GATEWAYS
@@ -203,18 +533,176 @@ async fn set_gateway(gateway_id: GatewayId, addr: SocketAddr) -> Result<()> {
         .await;
 
     let mut gateways = gateways.write().await;
-    let _ = gateways.insert(
+    let now = SystemTime::now();
+
+    let gw = gateways.entry(gateway_id).or_insert_with(|| Gateway {
+        addr,
+        socket: socket.clone(),
+        last_seen: now,
+        candidate: None,
+        conflict_count: 0,
+        conflict_addr: None,
+        conflict_at: None,
+    });
+
+    if gw.addr == addr {
+        gw.socket = socket.clone();
+        gw.last_seen = now;
+        gw.candidate = None;
+        return Ok(true);
+    }
+
+    let accept = match conf.address_change_policy {
+        config::AddressChangePolicy::AcceptLatest => true,
+        config::AddressChangePolicy::KeepFirst => false,
+        config::AddressChangePolicy::RequireConsecutive => {
+            let count = match gw.candidate {
+                Some((candidate, count)) if candidate == addr => count + 1,
+                _ => 1,
+            };
+            gw.candidate = Some((addr, count));
+            count >= conf.address_change_keepalives
+        }
+    };
+
+    gw.conflict_count += 1;
+    gw.conflict_at = Some(now);
+    inc_gateway_address_conflict_count(gateway_id).await;
+
+    if accept {
+        warn!(
+            gateway_id = %gateway_id,
+            addr = %addr,
+            previous_addr = %gw.addr,
+            "Gateway address changed"
+        );
+
+        gw.conflict_addr = Some(gw.addr);
+        gw.addr = addr;
+        gw.socket = socket.clone();
+        gw.last_seen = now;
+        gw.candidate = None;
+    } else {
+        warn!(
+            gateway_id = %gateway_id,
+            addr = %addr,
+            current_addr = %gw.addr,
+            policy = ?conf.address_change_policy,
+            "Ignoring PULL_DATA from conflicting gateway address"
+        );
+
+        gw.conflict_addr = Some(addr);
+    }
+
+    Ok(accept)
+}
+
+// Updates the last seen timestamp of the gateway, and sets the gateway
+// online in case it was offline or seen for the first time.
+async fn set_gateway_seen(gateway_id: GatewayId, addr: SocketAddr) {
+    let states = GATEWAY_STATES
+        .get_or_init(|| async { RwLock::new(HashMap::new()) })
+        .await;
+    let mut states = states.write().await;
+    let now = SystemTime::now();
+
+    set_gateway_last_seen(gateway_id, events::timestamp(now)).await;
+
+    match states.entry(gateway_id) {
+        Entry::Occupied(e) => {
+            let state = e.into_mut();
+            state.last_seen = now;
+            if state.online {
+                return;
+            }
+            state.online = true;
+        }
+        Entry::Vacant(e) => {
+            e.insert(GatewayState {
+                last_seen: now,
+                online: true,
+            });
+
+            events::emit(Event::GatewayFirstSeen {
+                gateway_id,
+                addr,
+                time: events::timestamp(now),
+            })
+            .await;
+        }
+    }
+
+    events::emit(Event::GatewayOnline {
         gateway_id,
-        Gateway {
-            addr,
-            last_seen: SystemTime::now(),
-        },
+        addr,
+        time: events::timestamp(now),
+    })
+    .await;
+
+    set_gateways_online(states.values().filter(|v| v.online).count()).await;
+}
+
+// Sets gateways offline from which no PUSH_DATA or PULL_DATA was received
+// within the configured number of keepalive intervals.
+async fn handle_gateway_states(conf: Arc<config::Gateways>) {
+    let offline_after = Duration::from_secs(
+        conf.keepalive_interval_secs * u64::from(conf.offline_after_keepalives),
     );
 
-    Ok(())
+    loop {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        let states = GATEWAY_STATES
+            .get_or_init(|| async { RwLock::new(HashMap::new()) })
+            .await;
+        let mut states = states.write().await;
+        let now = SystemTime::now();
+        let mut changed = false;
+
+        for (gateway_id, state) in states.iter_mut() {
+            if !state.online
+                || now.duration_since(state.last_seen).unwrap_or_default() < offline_after
+            {
+                continue;
+            }
+
+            state.online = false;
+            changed = true;
+
+            events::emit(Event::GatewayOffline {
+                gateway_id: *gateway_id,
+                last_seen: events::timestamp(state.last_seen),
+                time: events::timestamp(now),
+            })
+            .await;
+        }
+
+        if changed {
+            set_gateways_online(states.values().filter(|v| v.online).count()).await;
+        }
+    }
+}
+
+/// Returns the addresses to which the listener is bound, one per bind in the
+/// order bind, binds and bind_down. In case of port 0, this contains the port
+/// that was assigned by the OS.
+pub async fn get_bound_addrs() -> Vec<SocketAddr> {
+    let addrs = BOUND_ADDRS
+        .get_or_init(|| async { RwLock::new(Vec::new()) })
+        .await;
+
+    addrs.read().await.clone()
+}
+
+async fn add_bound_addr(addr: SocketAddr) {
+    let addrs = BOUND_ADDRS
+        .get_or_init(|| async { RwLock::new(Vec::new()) })
+        .await;
+
+    addrs.write().await.push(addr);
 }
 
-async fn get_gateway(gateway_id: GatewayId) -> Result<SocketAddr> {
+async fn get_gateway(gateway_id: GatewayId) -> Result<(SocketAddr, Arc<UdpSocket>)> {
     trace!(gateway_id = %gateway_id, "Getting addr for Gateway ID");
 
     let gateways = GATEWAYS
@@ -224,14 +712,173 @@ async fn get_gateway(gateway_id: GatewayId) -> Result<SocketAddr> {
     let gateways = gateways.read().await;
     gateways
         .get(&gateway_id)
-        .map(|v| v.addr)
+        .map(|v| (v.addr, v.socket.clone()))
         .ok_or_else(|| anyhow!("Unknown Gateway ID: {}", gateway_id))
 }
 
-async fn cleanup_gateways() {
+// Returns true if the packet is within the rate limit of the gateway.
+async fn check_rate_limit(conf: &config::RateLimit, gateway_id: GatewayId, size: usize) -> bool {
+    if !ratelimit::is_enabled(conf) {
+        return true;
+    }
+
+    let rate_limiters = RATE_LIMITERS
+        .get_or_init(|| async { RwLock::new(HashMap::new()) })
+        .await;
+
+    let mut rate_limiters = rate_limiters.write().await;
+    let rate_limiter = match rate_limiters.entry(gateway_id) {
+        Entry::Occupied(e) => e.into_mut(),
+        Entry::Vacant(e) => match RateLimiter::new(conf) {
+            Some(v) => e.insert(v),
+            None => return true,
+        },
+    };
+
+    rate_limiter.allow(size)
+}
+
+// Queues the downlink for a gateway of which the address is not known, e.g.
+// directly after a restart of the multiplexer.
+async fn queue_downlink(conf: &config::DownlinkQueue, gateway_id: GatewayId, data: Bytes) {
+    let queues = DOWNLINK_QUEUES
+        .get_or_init(|| async { RwLock::new(HashMap::new()) })
+        .await;
+    let mut queues = queues.write().await;
+    let queue = queues.entry(gateway_id).or_default();
+
+    if queue.len() >= conf.max_packets {
+        warn!(gateway_id = %gateway_id, "Downlink queue is full, dropping downlink");
+        inc_gateway_downlink_queue_count(gateway_id, "dropped").await;
+        return;
+    }
+
+    info!(gateway_id = %gateway_id, "Gateway address is unknown, queueing downlink");
+    queue.push_back(QueuedDownlink {
+        queued_at: Instant::now(),
+        data,
+    });
+    inc_gateway_downlink_queue_count(gateway_id, "queued").await;
+}
+
+// Sends the queued downlinks of the gateway, expired downlinks are dropped.
+async fn send_queued_downlinks(
+    conf: &config::DownlinkQueue,
+    gateway_id: GatewayId,
+    addr: SocketAddr,
+    socket: &UdpSocket,
+) {
+    let queue = {
+        let queues = DOWNLINK_QUEUES
+            .get_or_init(|| async { RwLock::new(HashMap::new()) })
+            .await;
+        match queues.write().await.remove(&gateway_id) {
+            Some(v) => v,
+            None => return,
+        }
+    };
+
+    let max_age = Duration::from_millis(conf.max_age_ms);
+    for downlink in queue {
+        if downlink.queued_at.elapsed() > max_age {
+            warn!(gateway_id = %gateway_id, "Queued downlink expired, dropping downlink");
+            inc_gateway_downlink_queue_count(gateway_id, "expired").await;
+            continue;
+        }
+
+        match send_downlink(gateway_id, addr, socket, &downlink.data).await {
+            Ok(_) => inc_gateway_downlink_queue_count(gateway_id, "sent").await,
+            Err(e) => error!(error = %e.full(), "Send queued downlink error"),
+        }
+    }
+}
+
+// Returns true if the PUSH_DATA with the given token and payload was already
+// received from the gateway within the dedup window.
+async fn is_duplicate(window_ms: u64, gateway_id: GatewayId, token: u16, payload: &[u8]) -> bool {
+    if window_ms == 0 {
+        return false;
+    }
+
+    let dedup_windows = DEDUP_WINDOWS
+        .get_or_init(|| async { RwLock::new(HashMap::new()) })
+        .await;
+
+    let mut dedup_windows = dedup_windows.write().await;
+    dedup_windows
+        .entry(gateway_id)
+        .or_insert_with(|| DedupWindow::new(Duration::from_millis(window_ms)))
+        .is_duplicate(token, payload)
+}
+
+/// Returns the gateways for which PULL_DATA was received from more than one
+/// address.
+pub async fn get_gateway_conflicts() -> Vec<GatewayConflict> {
+    let gateways = GATEWAYS
+        .get_or_init(|| async { RwLock::new(HashMap::new()) })
+        .await;
+
+    let gateways = gateways.read().await;
+    gateways
+        .iter()
+        .filter(|(_, v)| v.conflict_count > 0)
+        .map(|(k, v)| GatewayConflict {
+            gateway_id: *k,
+            addr: v.addr,
+            conflict_addr: v.conflict_addr,
+            conflict_count: v.conflict_count,
+            last_conflict_at: v
+                .conflict_at
+                .and_then(|v| v.duration_since(UNIX_EPOCH).ok())
+                .map(|v| v.as_secs())
+                .unwrap_or_default(),
+        })
+        .collect()
+}
+
+async fn cleanup_gateways(conf: Arc<config::Gateways>) {
     loop {
         tokio::time::sleep(tokio::time::Duration::from_secs(60)).await;
 
+        trace!("Cleaning up expired downlink queues");
+
+        let max_age = Duration::from_millis(conf.downlink_queue.max_age_ms);
+        let queues = DOWNLINK_QUEUES
+            .get_or_init(|| async { RwLock::new(HashMap::new()) })
+            .await;
+        let mut queues = queues.write().await;
+        for (gateway_id, queue) in queues.iter_mut() {
+            while queue
+                .front()
+                .is_some_and(|v| v.queued_at.elapsed() > max_age)
+            {
+                queue.pop_front();
+                inc_gateway_downlink_queue_count(*gateway_id, "expired").await;
+            }
+        }
+        queues.retain(|_, v| !v.is_empty());
+        drop(queues);
+
+        trace!("Cleaning up inactive rate limiters");
+
+        let rate_limiters = RATE_LIMITERS
+            .get_or_init(|| async { RwLock::new(HashMap::new()) })
+            .await;
+        rate_limiters
+            .write()
+            .await
+            .retain(|_, v| v.last_used().elapsed() < Duration::from_secs(60));
+
+        trace!("Cleaning up inactive dedup windows");
+
+        let dedup_windows = DEDUP_WINDOWS
+            .get_or_init(|| async { RwLock::new(HashMap::new()) })
+            .await;
+        dedup_windows
+            .write()
+            .await
+            .retain(|_, v| v.last_used().elapsed() < Duration::from_secs(60));
+
         trace!("Cleaning up inactive Gateway ID to addr mappings");
 
         let gateways = GATEWAYS
@@ -1,11 +1,17 @@
+use std::net::IpAddr;
+use std::process;
 use std::str::FromStr;
+use std::time::Duration;
 
 use clap::{Parser, Subcommand};
 use signal_hook::{consts::SIGINT, consts::SIGTERM, iterator::Signals};
-use tracing::{info, Level};
+use tracing::{info, warn, Level};
 use tracing_subscriber::{filter, prelude::*};
 
-use chirpstack_packet_multiplexer::{cmd, config, forwarder, listener, monitoring};
+use chirpstack_packet_multiplexer::{
+    capture, cmd, config, forwarder, listener, metadata, monitoring, packets::GatewayIdPrefix,
+    traits::PrintFullError, webhooks,
+};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -21,21 +27,109 @@ struct Cli {
 enum Commands {
     /// Print the configuration template
     Configfile {},
+
+    /// Validate the configuration, exits with a non-zero exit code on error
+    Validate {},
+
+    /// Replay the gateway traffic of a capture file (pcap or NDJSON)
+    Replay {
+        /// Capture file
+        #[arg(value_name = "FILE")]
+        file: String,
+
+        /// Target (hostname:port), e.g. the multiplexer or a server
+        #[arg(long)]
+        target: String,
+
+        /// Speed-up factor
+        #[arg(long, default_value_t = 1.0)]
+        speed: f64,
+
+        /// Only replay datagrams sent to this UDP port (required for pcap)
+        #[arg(long)]
+        port: Option<u16>,
+
+        /// Only replay datagrams sent to this IP address (pcap only)
+        #[arg(long)]
+        dst_ip: Option<IpAddr>,
+
+        /// Rewrite the Gateway IDs using this prefix (e.g. 0102030400000000/32)
+        #[arg(long)]
+        gateway_id_prefix_rewrite: Option<GatewayIdPrefix>,
+    },
+
+    /// Simulate gateways speaking the Semtech UDP protocol
+    Simulate {
+        /// Target (hostname:port), e.g. the multiplexer
+        #[arg(long)]
+        target: String,
+
+        /// Number of gateways
+        #[arg(long, default_value_t = 1)]
+        gateways: usize,
+
+        /// Gateway ID prefix, the Gateway IDs are the prefix + gateway index
+        #[arg(long, default_value = "0000000000000000/0")]
+        gateway_id_prefix: GatewayIdPrefix,
+
+        /// PULL_DATA keepalive interval (seconds, 0 = disabled)
+        #[arg(long, default_value_t = 10)]
+        keepalive_interval_secs: u64,
+
+        /// Stat report interval (seconds, 0 = disabled)
+        #[arg(long, default_value_t = 30)]
+        stat_interval_secs: u64,
+
+        /// Uplink interval per gateway (milliseconds, 0 = disabled)
+        #[arg(long, default_value_t = 1000)]
+        uplink_interval_ms: u64,
+
+        /// Error reported in the TX_ACK in case of error injection
+        #[arg(long, default_value = "COLLISION_PACKET")]
+        tx_ack_error: String,
+
+        /// Ratio (0.0 - 1.0) of the downlinks that are responded with an error
+        #[arg(long, default_value_t = 0.0)]
+        tx_ack_error_rate: f64,
+
+        /// Duration (seconds, 0 = run until stopped)
+        #[arg(long, default_value_t = 0)]
+        duration_secs: u64,
+    },
 }
 
 #[tokio::main]
 async fn main() {
     let cli = Cli::parse();
-    let config = config::Configuration::get(&cli.config).expect("Read configuration");
+    let config = match config::Configuration::get(&cli.config) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("Read configuration error: {}", e.full());
+            process::exit(1);
+        }
+    };
 
     if let Some(Commands::Configfile {}) = &cli.command {
         cmd::configfile::run(&config);
         return;
     }
 
+    if let Some(Commands::Validate {}) = &cli.command {
+        let problems = config.validate().await;
+        for problem in &problems {
+            eprintln!("{}", problem);
+        }
+        if !problems.is_empty() {
+            process::exit(1);
+        }
+
+        println!("Configuration is valid");
+        return;
+    }
+
     let filter = filter::Targets::new().with_targets(vec![(
         "chirpstack_packet_multiplexer",
-        Level::from_str(&config.logging.level).unwrap(),
+        Level::from_str(&config.logging.level).unwrap_or(Level::INFO),
     )]);
 
     tracing_subscriber::registry()
@@ -43,6 +137,59 @@ async fn main() {
         .with(filter)
         .init();
 
+    if let Some(Commands::Replay {
+        file,
+        target,
+        speed,
+        port,
+        dst_ip,
+        gateway_id_prefix_rewrite,
+    }) = &cli.command
+    {
+        cmd::replay::run(&cmd::replay::Options {
+            file: file.clone(),
+            target: target.clone(),
+            speed: *speed,
+            port: *port,
+            dst_ip: *dst_ip,
+            gateway_id_prefix_rewrite: *gateway_id_prefix_rewrite,
+        })
+        .await
+        .expect("Replay");
+        return;
+    }
+
+    if let Some(Commands::Simulate {
+        target,
+        gateways,
+        gateway_id_prefix,
+        keepalive_interval_secs,
+        stat_interval_secs,
+        uplink_interval_ms,
+        tx_ack_error,
+        tx_ack_error_rate,
+        duration_secs,
+    }) = &cli.command
+    {
+        cmd::simulate::run(&cmd::simulate::Options {
+            target: target.clone(),
+            gateways: *gateways,
+            gateway_id_prefix: *gateway_id_prefix,
+            keepalive_interval: Duration::from_secs(*keepalive_interval_secs),
+            stat_interval: Duration::from_secs(*stat_interval_secs),
+            uplink_interval: Duration::from_millis(*uplink_interval_ms),
+            tx_ack_error: tx_ack_error.clone(),
+            tx_ack_error_rate: *tx_ack_error_rate,
+            duration: match duration_secs {
+                0 => None,
+                v => Some(Duration::from_secs(*v)),
+            },
+        })
+        .await
+        .expect("Simulate");
+        return;
+    }
+
     info!(
         "Starting {} (version: {}, docs: {})",
         env!("CARGO_PKG_DESCRIPTION"),
@@ -50,9 +197,23 @@ async fn main() {
         env!("CARGO_PKG_HOMEPAGE"),
     );
 
-    let (downlink_tx, uplink_rx) = listener::setup(&config.multiplexer.bind)
+    // Syntax errors and unknown keys are fatal (see above), the other checks
+    // are only logged such that e.g. a server that does not resolve at
+    // startup does not prevent the multiplexer from starting.
+    for problem in config.validate().await {
+        warn!(problem = %problem, "Configuration problem");
+    }
+
+    metadata::setup(&config.gateways.metadata_file)
+        .await
+        .expect("Setup gateway metadata");
+    webhooks::setup(&config.webhooks)
+        .await
+        .expect("Setup webhooks");
+    capture::setup(&config.capture)
         .await
-        .expect("Setup listener");
+        .expect("Setup traffic capture");
+    let (downlink_tx, uplink_rx) = listener::setup(&config).await.expect("Setup listener");
     forwarder::setup(downlink_tx, uplink_rx, config.multiplexer.servers.clone())
         .await
         .expect("Setup forwarder");
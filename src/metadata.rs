@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::{OnceCell, RwLock};
+use tracing::info;
+
+use crate::monitoring::set_gateway_info;
+use crate::packets::{GatewayId, PushData};
+
+static METADATA: OnceCell<RwLock<HashMap<GatewayId, Arc<GatewayMetadata>>>> = OnceCell::const_new();
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+struct MetadataFile {
+    #[serde(rename = "gateway")]
+    gateways: Vec<GatewayMetadata>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct GatewayMetadata {
+    pub gateway_id: GatewayId,
+    #[serde(default)]
+    pub name: String,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub altitude: Option<i32>,
+    #[serde(default)]
+    pub tags: HashMap<String, String>,
+}
+
+impl GatewayMetadata {
+    pub fn has_location(&self) -> bool {
+        self.latitude.is_some() && self.longitude.is_some()
+    }
+}
+
+pub async fn setup(file: &str) -> Result<()> {
+    if file.is_empty() {
+        info!("Gateway metadata file is not configured");
+        return Ok(());
+    }
+
+    info!(file = file, "Loading gateway metadata");
+
+    let content = fs::read_to_string(file).context("Read gateway metadata file")?;
+    let metadata: MetadataFile = toml::from_str(&content).context("Parse gateway metadata file")?;
+
+    let gateways = METADATA
+        .get_or_init(|| async { RwLock::new(HashMap::new()) })
+        .await;
+    let mut gateways = gateways.write().await;
+
+    for gw in metadata.gateways {
+        set_gateway_info(gw.gateway_id, &gw.name, &gw.tags).await;
+        gateways.insert(gw.gateway_id, Arc::new(gw));
+    }
+
+    info!(count = gateways.len(), "Gateway metadata loaded");
+
+    Ok(())
+}
+
+pub async fn get(gateway_id: GatewayId) -> Option<Arc<GatewayMetadata>> {
+    let gateways = METADATA
+        .get_or_init(|| async { RwLock::new(HashMap::new()) })
+        .await;
+    let gateways = gateways.read().await;
+    gateways.get(&gateway_id).cloned()
+}
+
+/// Injects the configured location into the stat object of the PUSH_DATA,
+/// in case the stat object does not contain a location. Returns true if the
+/// location was injected.
+pub fn inject_location(push_data: &mut PushData, metadata: &GatewayMetadata) -> bool {
+    let (Some(lati), Some(long)) = (metadata.latitude, metadata.longitude) else {
+        return false;
+    };
+
+    let Some(stat) = push_data.stat_mut() else {
+        return false;
+    };
+
+    if stat.contains_key("lati") && stat.contains_key("long") {
+        return false;
+    }
+
+    stat.insert("lati".into(), Value::from(lati));
+    stat.insert("long".into(), Value::from(long));
+    stat.insert(
+        "alti".into(),
+        Value::from(metadata.altitude.unwrap_or_default()),
+    );
+
+    true
+}
@@ -1,16 +1,20 @@
+use std::collections::HashMap;
+
 use anyhow::Result;
-use axum::{http::StatusCode, routing::get, Router};
+use axum::{http::StatusCode, routing::get, Json, Router};
 use prometheus_client::{
     encoding::text::encode,
     encoding::EncodeLabelSet,
     metrics::counter::Counter,
     metrics::family::Family,
+    metrics::gauge::Gauge,
     registry::{Metric, Registry},
 };
 use tokio::net::TcpListener;
 use tokio::sync::{OnceCell, RwLock};
 use tracing::info;
 
+use crate::listener::{self, GatewayConflict};
 use crate::packets::{GatewayId, PacketType};
 
 static REGISTRY: OnceCell<RwLock<Registry>> = OnceCell::const_new();
@@ -20,6 +24,36 @@ static GATEWAY_UDP_RECEIVED_COUNT: OnceCell<Family<GatewayUdpLabels, Counter>> =
 static SERVER_UDP_SENT_COUNT: OnceCell<Family<ServerUdpLabels, Counter>> = OnceCell::const_new();
 static SERVER_UDP_RECEIVED_COUNT: OnceCell<Family<ServerUdpLabels, Counter>> =
     OnceCell::const_new();
+static GATEWAY_ACK_COUNT: OnceCell<Family<GatewayAckLabels, Counter>> = OnceCell::const_new();
+static GATEWAY_UDP_REJECTED_COUNT: OnceCell<Family<GatewayUdpRejectedLabels, Counter>> =
+    OnceCell::const_new();
+static GATEWAY_ADDRESS_CONFLICT_COUNT: OnceCell<Family<GatewayLabels, Counter>> =
+    OnceCell::const_new();
+static GATEWAY_UDP_DUPLICATE_COUNT: OnceCell<Family<GatewayLabels, Counter>> =
+    OnceCell::const_new();
+static GATEWAY_DOWNLINK_QUEUE_COUNT: OnceCell<Family<GatewayQueueLabels, Counter>> =
+    OnceCell::const_new();
+static GATEWAY_UDP_THROTTLED_COUNT: OnceCell<Family<GatewayUdpLabels, Counter>> =
+    OnceCell::const_new();
+static SERVER_UDP_THROTTLED_COUNT: OnceCell<Family<ServerUdpLabels, Counter>> =
+    OnceCell::const_new();
+static SERVER_UDP_SEND_ERROR_COUNT: OnceCell<Family<ServerUdpLabels, Counter>> =
+    OnceCell::const_new();
+static GATEWAYS_ONLINE: OnceCell<Gauge> = OnceCell::const_new();
+static GATEWAY_LAST_SEEN: OnceCell<Family<GatewayLabels, Gauge>> = OnceCell::const_new();
+static GATEWAY_INFO: OnceCell<Family<Vec<(String, String)>, Gauge>> = OnceCell::const_new();
+static SERVER_SOCKETS: OnceCell<Family<ServerLabels, Gauge>> = OnceCell::const_new();
+static SERVER_BUFFER_PACKETS: OnceCell<Family<ServerLabels, Gauge>> = OnceCell::const_new();
+static SERVER_BUFFER_COUNT: OnceCell<Family<ServerBufferLabels, Counter>> = OnceCell::const_new();
+static SERVER_RETRANSMIT_COUNT: OnceCell<Family<ServerRetransmitLabels, Counter>> =
+    OnceCell::const_new();
+static SERVER_SOCKET_ERROR_COUNT: OnceCell<Family<ServerSocketErrorLabels, Counter>> =
+    OnceCell::const_new();
+
+#[derive(Clone, Hash, PartialEq, Eq, EncodeLabelSet, Debug)]
+struct GatewayLabels {
+    gateway_id: String,
+}
 
 #[derive(Clone, Hash, PartialEq, Eq, EncodeLabelSet, Debug)]
 struct GatewayUdpLabels {
@@ -27,12 +61,56 @@ struct GatewayUdpLabels {
     r#type: String,
 }
 
+#[derive(Clone, Hash, PartialEq, Eq, EncodeLabelSet, Debug)]
+struct GatewayQueueLabels {
+    gateway_id: String,
+    action: String,
+}
+
+#[derive(Clone, Hash, PartialEq, Eq, EncodeLabelSet, Debug)]
+struct GatewayAckLabels {
+    gateway_id: String,
+    r#type: String,
+    result: String,
+}
+
+// The Gateway ID is not used as label as the Gateway ID of rejected packets
+// is not trusted, which could lead to unbounded label cardinality.
+#[derive(Clone, Hash, PartialEq, Eq, EncodeLabelSet, Debug)]
+struct GatewayUdpRejectedLabels {
+    r#type: String,
+    reason: String,
+}
+
 #[derive(Clone, Hash, PartialEq, Eq, EncodeLabelSet, Debug)]
 struct ServerUdpLabels {
     server: String,
     r#type: String,
 }
 
+#[derive(Clone, Hash, PartialEq, Eq, EncodeLabelSet, Debug)]
+struct ServerLabels {
+    server: String,
+}
+
+#[derive(Clone, Hash, PartialEq, Eq, EncodeLabelSet, Debug)]
+struct ServerSocketErrorLabels {
+    server: String,
+    reason: String,
+}
+
+#[derive(Clone, Hash, PartialEq, Eq, EncodeLabelSet, Debug)]
+struct ServerBufferLabels {
+    server: String,
+    action: String,
+}
+
+#[derive(Clone, Hash, PartialEq, Eq, EncodeLabelSet, Debug)]
+struct ServerRetransmitLabels {
+    server: String,
+    result: String,
+}
+
 pub async fn setup(bind: &str) -> Result<()> {
     if bind.is_empty() {
         info!("Monitoring endpoint is not configured");
@@ -41,7 +119,9 @@ pub async fn setup(bind: &str) -> Result<()> {
 
     info!(bind = bind, "Setting up monitoring endpoint");
 
-    let app = Router::new().route("/metrics", get(get_prometheus_metrics));
+    let app = Router::new()
+        .route("/metrics", get(get_prometheus_metrics))
+        .route("/gateways/conflicts", get(get_gateway_conflicts));
 
     let listener = TcpListener::bind(bind).await?;
     tokio::spawn(async {
@@ -65,6 +145,10 @@ async fn get_prometheus_metrics() -> (StatusCode, String) {
     }
 }
 
+async fn get_gateway_conflicts() -> Json<Vec<GatewayConflict>> {
+    Json(listener::get_gateway_conflicts().await)
+}
+
 pub async fn register(name: &str, help: &str, metric: impl Metric) {
     let registry = REGISTRY
         .get_or_init(|| async { RwLock::new(Registry::default()) })
@@ -95,6 +179,29 @@ pub async fn inc_gateway_udp_sent_count(gateway_id: GatewayId, packet_type: Pack
         .inc();
 }
 
+pub async fn inc_gateway_ack_count(gateway_id: GatewayId, packet_type: PacketType, result: &str) {
+    let counter = GATEWAY_ACK_COUNT
+        .get_or_init(|| async {
+            let counter = Family::<GatewayAckLabels, Counter>::default();
+            register(
+                "gateway_ack_count",
+                "Number of ACKs to the gateway in ACK pass-through mode and their result",
+                counter.clone(),
+            )
+            .await;
+            counter
+        })
+        .await;
+
+    counter
+        .get_or_create(&GatewayAckLabels {
+            gateway_id: gateway_id.to_string(),
+            r#type: packet_type.to_string(),
+            result: result.to_string(),
+        })
+        .inc();
+}
+
 pub async fn inc_gateway_udp_received_count(gateway_id: GatewayId, packet_type: PacketType) {
     let counter = GATEWAY_UDP_RECEIVED_COUNT
         .get_or_init(|| async {
@@ -117,6 +224,114 @@ pub async fn inc_gateway_udp_received_count(gateway_id: GatewayId, packet_type:
         .inc();
 }
 
+pub async fn inc_gateway_udp_rejected_count(packet_type: PacketType, reason: &str) {
+    let counter = GATEWAY_UDP_REJECTED_COUNT
+        .get_or_init(|| async {
+            let counter = Family::<GatewayUdpRejectedLabels, Counter>::default();
+            register(
+                "gateway_udp_rejected_count",
+                "Number of UDP datagrams received from the gateway that were rejected",
+                counter.clone(),
+            )
+            .await;
+            counter
+        })
+        .await;
+
+    counter
+        .get_or_create(&GatewayUdpRejectedLabels {
+            r#type: packet_type.to_string(),
+            reason: reason.to_string(),
+        })
+        .inc();
+}
+
+pub async fn inc_gateway_udp_duplicate_count(gateway_id: GatewayId) {
+    let counter = GATEWAY_UDP_DUPLICATE_COUNT
+        .get_or_init(|| async {
+            let counter = Family::<GatewayLabels, Counter>::default();
+            register(
+                "gateway_udp_duplicate_count",
+                "Number of duplicate PUSH_DATA received from the gateway (suppressed)",
+                counter.clone(),
+            )
+            .await;
+            counter
+        })
+        .await;
+
+    counter
+        .get_or_create(&GatewayLabels {
+            gateway_id: gateway_id.to_string(),
+        })
+        .inc();
+}
+
+pub async fn inc_gateway_downlink_queue_count(gateway_id: GatewayId, action: &str) {
+    let counter = GATEWAY_DOWNLINK_QUEUE_COUNT
+        .get_or_init(|| async {
+            let counter = Family::<GatewayQueueLabels, Counter>::default();
+            register(
+                "gateway_downlink_queue_count",
+                "Number of downlinks queued for gateways with unknown address, by action",
+                counter.clone(),
+            )
+            .await;
+            counter
+        })
+        .await;
+
+    counter
+        .get_or_create(&GatewayQueueLabels {
+            gateway_id: gateway_id.to_string(),
+            action: action.to_string(),
+        })
+        .inc();
+}
+
+pub async fn inc_gateway_udp_throttled_count(gateway_id: GatewayId, packet_type: PacketType) {
+    let counter = GATEWAY_UDP_THROTTLED_COUNT
+        .get_or_init(|| async {
+            let counter = Family::<GatewayUdpLabels, Counter>::default();
+            register(
+                "gateway_udp_throttled_count",
+                "Number of UDP datagrams received from the gateway that were dropped by the rate limit",
+                counter.clone(),
+            )
+            .await;
+            counter
+        })
+        .await;
+
+    counter
+        .get_or_create(&GatewayUdpLabels {
+            gateway_id: gateway_id.to_string(),
+            r#type: packet_type.to_string(),
+        })
+        .inc();
+}
+
+pub async fn inc_gateway_address_conflict_count(gateway_id: GatewayId) {
+    let counter = GATEWAY_ADDRESS_CONFLICT_COUNT
+        .get_or_init(|| async {
+            let counter = Family::<GatewayLabels, Counter>::default();
+            register(
+                "gateway_address_conflict_count",
+                "Number of PULL_DATA received from an address other than the gateway address",
+                counter.clone(),
+            )
+            .await;
+            counter
+        })
+        .await;
+
+    counter
+        .get_or_create(&GatewayLabels {
+            gateway_id: gateway_id.to_string(),
+        })
+        .inc();
+}
+
 pub async fn inc_server_udp_sent_count(server: &str, packet_type: PacketType) {
     let counter = SERVER_UDP_SENT_COUNT
         .get_or_init(|| async {
@@ -139,6 +354,50 @@ pub async fn inc_server_udp_sent_count(server: &str, packet_type: PacketType) {
         .inc();
 }
 
+pub async fn inc_server_udp_throttled_count(server: &str, packet_type: PacketType) {
+    let counter = SERVER_UDP_THROTTLED_COUNT
+        .get_or_init(|| async {
+            let counter = Family::<ServerUdpLabels, Counter>::default();
+            register(
+                "server_udp_throttled_count",
+                "Number of UDP datagrams not sent to the server because of the rate limit",
+                counter.clone(),
+            )
+            .await;
+            counter
+        })
+        .await;
+
+    counter
+        .get_or_create(&ServerUdpLabels {
+            server: server.to_string(),
+            r#type: packet_type.to_string(),
+        })
+        .inc();
+}
+
+pub async fn inc_server_udp_send_error_count(server: &str, packet_type: PacketType) {
+    let counter = SERVER_UDP_SEND_ERROR_COUNT
+        .get_or_init(|| async {
+            let counter = Family::<ServerUdpLabels, Counter>::default();
+            register(
+                "server_udp_send_error_count",
+                "Number of UDP datagrams which failed to be sent to the server",
+                counter.clone(),
+            )
+            .await;
+            counter
+        })
+        .await;
+
+    counter
+        .get_or_create(&ServerUdpLabels {
+            server: server.to_string(),
+            r#type: packet_type.to_string(),
+        })
+        .inc();
+}
+
 pub async fn inc_server_udp_received_count(server: &str, packet_type: PacketType) {
     let counter = SERVER_UDP_RECEIVED_COUNT
         .get_or_init(|| async {
@@ -160,3 +419,182 @@ pub async fn inc_server_udp_received_count(server: &str, packet_type: PacketType
         })
         .inc();
 }
+
+pub async fn set_gateways_online(count: usize) {
+    let gauge = GATEWAYS_ONLINE
+        .get_or_init(|| async {
+            let gauge = Gauge::default();
+            register(
+                "gateways_online",
+                "Number of gateways which are online",
+                gauge.clone(),
+            )
+            .await;
+            gauge
+        })
+        .await;
+
+    gauge.set(count as i64);
+}
+
+pub async fn set_gateway_last_seen(gateway_id: GatewayId, timestamp: u64) {
+    let gauge = GATEWAY_LAST_SEEN
+        .get_or_init(|| async {
+            let gauge = Family::<GatewayLabels, Gauge>::default();
+            register(
+                "gateway_last_seen",
+                "UNIX timestamp (seconds) of the last PUSH_DATA or PULL_DATA received from the gateway",
+                gauge.clone(),
+            )
+            .await;
+            gauge
+        })
+        .await;
+
+    gauge
+        .get_or_create(&GatewayLabels {
+            gateway_id: gateway_id.to_string(),
+        })
+        .set(timestamp as i64);
+}
+
+pub async fn set_server_sockets(server: &str, count: usize) {
+    let gauge = SERVER_SOCKETS
+        .get_or_init(|| async {
+            let gauge = Family::<ServerLabels, Gauge>::default();
+            register(
+                "server_sockets",
+                "Number of open UDP sockets to the server",
+                gauge.clone(),
+            )
+            .await;
+            gauge
+        })
+        .await;
+
+    gauge
+        .get_or_create(&ServerLabels {
+            server: server.to_string(),
+        })
+        .set(count as i64);
+}
+
+pub async fn inc_server_socket_error_count(server: &str, reason: &str) {
+    let counter = SERVER_SOCKET_ERROR_COUNT
+        .get_or_init(|| async {
+            let counter = Family::<ServerSocketErrorLabels, Counter>::default();
+            register(
+                "server_socket_error_count",
+                "Number of failed UDP socket creations to the server",
+                counter.clone(),
+            )
+            .await;
+            counter
+        })
+        .await;
+
+    counter
+        .get_or_create(&ServerSocketErrorLabels {
+            server: server.to_string(),
+            reason: reason.to_string(),
+        })
+        .inc();
+}
+
+pub async fn set_server_buffer_packets(server: &str, count: usize) {
+    let gauge = SERVER_BUFFER_PACKETS
+        .get_or_init(|| async {
+            let gauge = Family::<ServerLabels, Gauge>::default();
+            register(
+                "server_buffer_packets",
+                "Number of PUSH_DATA packets in the store-and-forward buffer of the server",
+                gauge.clone(),
+            )
+            .await;
+            gauge
+        })
+        .await;
+
+    gauge
+        .get_or_create(&ServerLabels {
+            server: server.to_string(),
+        })
+        .set(count as i64);
+}
+
+pub async fn inc_server_buffer_count(server: &str, action: &str) {
+    let counter = SERVER_BUFFER_COUNT
+        .get_or_init(|| async {
+            let counter = Family::<ServerBufferLabels, Counter>::default();
+            register(
+                "server_buffer_count",
+                "Number of PUSH_DATA packets buffered, replayed, dropped or expired by the store-and-forward buffer",
+                counter.clone(),
+            )
+            .await;
+            counter
+        })
+        .await;
+
+    counter
+        .get_or_create(&ServerBufferLabels {
+            server: server.to_string(),
+            action: action.to_string(),
+        })
+        .inc();
+}
+
+pub async fn inc_server_retransmit_count(server: &str, result: &str) {
+    let counter = SERVER_RETRANSMIT_COUNT
+        .get_or_init(|| async {
+            let counter = Family::<ServerRetransmitLabels, Counter>::default();
+            register(
+                "server_retransmit_count",
+                "Number of PUSH_DATA retransmissions to the server and their result",
+                counter.clone(),
+            )
+            .await;
+            counter
+        })
+        .await;
+
+    counter
+        .get_or_create(&ServerRetransmitLabels {
+            server: server.to_string(),
+            result: result.to_string(),
+        })
+        .inc();
+}
+
+pub async fn set_gateway_info(gateway_id: GatewayId, name: &str, tags: &HashMap<String, String>) {
+    let gauge = GATEWAY_INFO
+        .get_or_init(|| async {
+            let gauge = Family::<Vec<(String, String)>, Gauge>::default();
+            register(
+                "gateway_info",
+                "Gateway metadata (name and tags) from the gateway metadata file",
+                gauge.clone(),
+            )
+            .await;
+            gauge
+        })
+        .await;
+
+    let mut labels = vec![
+        ("gateway_id".to_string(), gateway_id.to_string()),
+        ("name".to_string(), name.to_string()),
+    ];
+
+    let mut tags: Vec<(&String, &String)> = tags.iter().collect();
+    tags.sort();
+    for (k, v) in tags {
+        // Tag keys are exposed as tag_<key> labels, with invalid label characters replaced.
+        let k: String = k
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        labels.push((format!("tag_{}", k), v.to_string()));
+    }
+
+    gauge.get_or_create(&labels).set(1);
+}
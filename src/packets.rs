@@ -1,8 +1,15 @@
 use std::fmt;
+use std::str::FromStr;
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
+use base64::{engine::general_purpose, Engine as _};
+use serde::{
+    de::{self, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use serde_json::{Map, Value};
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum PacketType {
     PushData,
     PushAck,
@@ -73,15 +80,41 @@ impl TryFrom<&[u8]> for ProtocolVersion {
     }
 }
 
+/// Returns the Semtech UDP packet (protocol version 2). The Gateway ID is
+/// only included for packet-types sent by the gateway.
+pub fn encode(
+    packet_type: PacketType,
+    token: u16,
+    gateway_id: Option<GatewayId>,
+    payload: &[u8],
+) -> Vec<u8> {
+    let mut b = vec![0x02];
+    b.extend_from_slice(&token.to_be_bytes());
+    b.push(packet_type.into());
+    if let Some(gateway_id) = gateway_id {
+        b.extend_from_slice(&gateway_id.as_bytes_be());
+    }
+    b.extend_from_slice(payload);
+    b
+}
+
 #[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
 pub struct GatewayId([u8; 8]);
 
 impl GatewayId {
+    pub fn from_bytes_be(b: [u8; 8]) -> Self {
+        GatewayId(b)
+    }
+
     pub fn as_bytes_le(&self) -> [u8; 8] {
         let mut out = self.0;
         out.reverse(); // BE => LE
         out
     }
+
+    pub fn as_bytes_be(&self) -> [u8; 8] {
+        self.0
+    }
 }
 
 impl TryFrom<&[u8]> for GatewayId {
@@ -104,6 +137,131 @@ impl fmt::Display for GatewayId {
     }
 }
 
+impl FromStr for GatewayId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut b: [u8; 8] = [0; 8];
+        hex::decode_to_slice(s, &mut b).context("Decode Gateway ID")?;
+        Ok(GatewayId(b))
+    }
+}
+
+impl Serialize for GatewayId {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for GatewayId {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(GatewayIdVisitor)
+    }
+}
+
+struct GatewayIdVisitor;
+
+impl<'de> Visitor<'de> for GatewayIdVisitor {
+    type Value = GatewayId;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("A Gateway ID in the format 0000000000000000")
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        GatewayId::from_str(value).map_err(|e| E::custom(format!("{:#}", e)))
+    }
+}
+
+/// Gateway ID prefix, used to rewrite the most significant bits of a Gateway ID.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct GatewayIdPrefix([u8; 8], u32);
+
+impl GatewayIdPrefix {
+    /// Returns the Gateway ID with the most significant bits replaced by the
+    /// prefix.
+    pub fn rewrite(&self, gateway_id: GatewayId) -> GatewayId {
+        if self.1 == 0 {
+            return gateway_id;
+        }
+
+        let mask = u64::MAX << (64 - self.1);
+        let prefix = u64::from_be_bytes(self.0);
+        let id = u64::from_be_bytes(gateway_id.0);
+        GatewayId(((prefix & mask) | (id & !mask)).to_be_bytes())
+    }
+}
+
+impl FromStr for GatewayIdPrefix {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (prefix, size) = s
+            .split_once('/')
+            .ok_or_else(|| anyhow!("Expected format: 0000000000000000/0"))?;
+
+        let mut b: [u8; 8] = [0; 8];
+        hex::decode_to_slice(prefix, &mut b).context("Decode Gateway ID prefix")?;
+
+        let size: u32 = size.parse().context("Parse Gateway ID prefix size")?;
+        if size > 64 {
+            return Err(anyhow!("Gateway ID prefix size must be <= 64"));
+        }
+
+        Ok(GatewayIdPrefix(b, size))
+    }
+}
+
+impl fmt::Display for GatewayIdPrefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", hex::encode(self.0), self.1)
+    }
+}
+
+impl Serialize for GatewayIdPrefix {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for GatewayIdPrefix {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(GatewayIdPrefixVisitor)
+    }
+}
+
+struct GatewayIdPrefixVisitor;
+
+impl<'de> Visitor<'de> for GatewayIdPrefixVisitor {
+    type Value = GatewayIdPrefix;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("A Gateway ID prefix in the format 0000000000000000/0")
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        GatewayIdPrefix::from_str(value).map_err(|e| E::custom(format!("{:#}", e)))
+    }
+}
+
 pub fn get_random_token(v: &[u8]) -> Result<u16> {
     if v.len() < 3 {
         return Err(anyhow!("At least 3 bytes are expected"));
@@ -111,3 +269,166 @@ pub fn get_random_token(v: &[u8]) -> Result<u16> {
 
     Ok(u16::from_be_bytes([v[1], v[2]]))
 }
+
+/// Returns the error reported by the gateway in the TX_ACK, or None in case
+/// the downlink was accepted.
+pub fn get_tx_ack_error(v: &[u8]) -> Option<String> {
+    let payload: Value = serde_json::from_slice(v.get(12..)?).ok()?;
+    let error = payload.get("txpk_ack")?.get("error")?.as_str()?;
+
+    match error {
+        "" | "NONE" => None,
+        _ => Some(error.to_string()),
+    }
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
+pub struct NetId([u8; 3]);
+
+impl NetId {
+    pub fn netid_type(&self) -> u8 {
+        self.0[0] >> 5
+    }
+
+    /// Returns the DevAddr prefix of the NetID.
+    ///
+    /// The prefix consists of the type prefix followed by the NwkID, of which
+    /// the length depends on the NetID type.
+    pub fn dev_addr_prefix(&self) -> lrwn_filters::DevAddrPrefix {
+        let netid_type = self.netid_type() as u32;
+        let nwkid_bits: u32 = match netid_type {
+            0 | 1 => 6,
+            2 => 9,
+            3 => 11,
+            4 => 12,
+            5 => 13,
+            6 => 15,
+            _ => 17,
+        };
+
+        let id = u32::from_be_bytes([0, self.0[0], self.0[1], self.0[2]]);
+        let nwkid = id & ((1 << nwkid_bits) - 1);
+
+        // Type prefix: netid_type 1 bits, followed by a 0 bit.
+        let type_prefix = (0xff_u32 << (8 - netid_type)) & 0xff;
+        let size = netid_type + 1 + nwkid_bits;
+        let prefix = (type_prefix << 24) | (nwkid << (32 - size));
+
+        lrwn_filters::DevAddrPrefix::new(prefix.to_be_bytes(), size)
+    }
+}
+
+impl FromStr for NetId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut b: [u8; 3] = [0; 3];
+        hex::decode_to_slice(s, &mut b).context("Decode NetID")?;
+        Ok(NetId(b))
+    }
+}
+
+impl fmt::Display for NetId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+impl Serialize for NetId {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for NetId {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(NetIdVisitor)
+    }
+}
+
+struct NetIdVisitor;
+
+impl<'de> Visitor<'de> for NetIdVisitor {
+    type Value = NetId;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("A NetID in the format 000000")
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        NetId::from_str(value).map_err(|e| E::custom(format!("{:#}", e)))
+    }
+}
+
+/// PUSH_DATA packet with decoded JSON payload.
+pub struct PushData {
+    header: [u8; 12],
+    pub payload: Map<String, Value>,
+}
+
+impl PushData {
+    pub fn rxpk(&self) -> &[Value] {
+        self.payload
+            .get("rxpk")
+            .and_then(|v| v.as_array())
+            .map(|v| v.as_slice())
+            .unwrap_or_default()
+    }
+
+    pub fn rxpk_mut(&mut self) -> Option<&mut Vec<Value>> {
+        self.payload.get_mut("rxpk").and_then(|v| v.as_array_mut())
+    }
+
+    pub fn has_stat(&self) -> bool {
+        self.payload.contains_key("stat")
+    }
+
+    pub fn stat_mut(&mut self) -> Option<&mut Map<String, Value>> {
+        self.payload.get_mut("stat").and_then(|v| v.as_object_mut())
+    }
+
+    pub fn to_vec(&self) -> Result<Vec<u8>> {
+        let mut out = self.header.to_vec();
+        serde_json::to_writer(&mut out, &self.payload).context("Encode PUSH_DATA JSON")?;
+        Ok(out)
+    }
+}
+
+impl TryFrom<&[u8]> for PushData {
+    type Error = anyhow::Error;
+
+    fn try_from(v: &[u8]) -> Result<PushData> {
+        if v.len() < 12 {
+            return Err(anyhow!("At least 12 bytes are expected"));
+        }
+
+        let mut header: [u8; 12] = [0; 12];
+        header.copy_from_slice(&v[..12]);
+
+        Ok(PushData {
+            header,
+            payload: serde_json::from_slice(&v[12..]).context("Decode PUSH_DATA JSON")?,
+        })
+    }
+}
+
+/// Returns the decoded PHYPayload of the given rxpk object.
+pub fn get_phy_payload(rxpk: &Value) -> Result<Vec<u8>> {
+    let data = rxpk
+        .get("data")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| anyhow!("rxpk does not contain data"))?;
+
+    general_purpose::STANDARD
+        .decode(data)
+        .context("Decode rxpk data")
+}
@@ -0,0 +1,110 @@
+use serde_json::{Map, Value};
+
+use crate::config::Privacy;
+use crate::packets::PushData;
+
+/// Returns true if none of the privacy transformations are set.
+pub fn is_empty(conf: &Privacy) -> bool {
+    !conf.remove_location
+        && conf.location_decimals.is_none()
+        && !conf.remove_tmms
+        && !conf.remove_ftime
+        && !conf.remove_rsig
+        && conf.rssi_step.is_none()
+        && conf.snr_step.is_none()
+}
+
+/// Applies the privacy transformations to the stat and rxpk objects of the
+/// given PUSH_DATA.
+pub fn apply(push_data: &mut PushData, conf: &Privacy) {
+    if let Some(stat) = push_data.stat_mut() {
+        apply_stat(stat, conf);
+    }
+
+    if let Some(rxpk) = push_data.rxpk_mut() {
+        for v in rxpk.iter_mut() {
+            if let Some(v) = v.as_object_mut() {
+                apply_rxpk(v, conf);
+            }
+        }
+    }
+}
+
+fn apply_stat(stat: &mut Map<String, Value>, conf: &Privacy) {
+    if conf.remove_location {
+        stat.remove("lati");
+        stat.remove("long");
+        stat.remove("alti");
+    } else if let Some(decimals) = conf.location_decimals {
+        for k in ["lati", "long"] {
+            round_decimals(stat, k, decimals);
+        }
+    }
+}
+
+fn apply_rxpk(rxpk: &mut Map<String, Value>, conf: &Privacy) {
+    if conf.remove_tmms {
+        rxpk.remove("tmms");
+    }
+
+    if conf.remove_rsig {
+        rxpk.remove("rsig");
+    }
+
+    if conf.remove_ftime {
+        rxpk.remove("ftime");
+        rxpk.remove("etime");
+    }
+
+    if let Some(step) = conf.rssi_step {
+        round_step(rxpk, "rssi", step as f64, true);
+    }
+
+    if let Some(step) = conf.snr_step {
+        round_step(rxpk, "lsnr", step, false);
+    }
+
+    // Protocol v2 gateways report the per-antenna metadata in rsig.
+    if let Some(rsig) = rxpk.get_mut("rsig").and_then(|v| v.as_array_mut()) {
+        for v in rsig.iter_mut() {
+            if let Some(v) = v.as_object_mut() {
+                if conf.remove_ftime {
+                    v.remove("ftime");
+                    v.remove("etime");
+                }
+
+                if let Some(step) = conf.rssi_step {
+                    round_step(v, "rssic", step as f64, true);
+                    round_step(v, "rssis", step as f64, true);
+                }
+
+                if let Some(step) = conf.snr_step {
+                    round_step(v, "lsnr", step, false);
+                }
+            }
+        }
+    }
+}
+
+fn round_decimals(obj: &mut Map<String, Value>, key: &str, decimals: u32) {
+    if let Some(v) = obj.get(key).and_then(|v| v.as_f64()) {
+        let factor = 10_f64.powi(decimals as i32);
+        obj.insert(key.to_string(), Value::from((v * factor).round() / factor));
+    }
+}
+
+fn round_step(obj: &mut Map<String, Value>, key: &str, step: f64, integer: bool) {
+    if step <= 0.0 {
+        return;
+    }
+
+    if let Some(v) = obj.get(key).and_then(|v| v.as_f64()) {
+        let v = (v / step).round() * step;
+        let v = if integer {
+            Value::from(v as i64)
+        } else {
+            Value::from(v)
+        };
+        obj.insert(key.to_string(), v);
+    }
+}
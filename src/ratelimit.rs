@@ -0,0 +1,128 @@
+use std::time::Instant;
+
+use anyhow::{anyhow, Result};
+
+use crate::config;
+
+// Max. size of a UDP datagram.
+const MAX_PACKET_SIZE: u32 = 65535;
+
+/// Returns true if a packets or bytes per second limit is configured.
+pub fn is_enabled(conf: &config::RateLimit) -> bool {
+    conf.packets_per_second != 0 || conf.bytes_per_second != 0
+}
+
+/// Returns an error if the configuration would drop packets regardless of the
+/// rate, i.e. when the configured byte burst is smaller than the max. packet
+/// size.
+pub fn check(conf: &config::RateLimit) -> Result<()> {
+    if conf.bytes_per_second == 0 || conf.byte_burst == 0 {
+        return Ok(());
+    }
+
+    if conf.byte_burst < MAX_PACKET_SIZE {
+        return Err(anyhow!(
+            "byte_burst must be at least {}, got {}",
+            MAX_PACKET_SIZE,
+            conf.byte_burst
+        ));
+    }
+
+    Ok(())
+}
+
+// Without configured byte burst, the bucket holds one second worth of bytes,
+// but at least the max. packet size such that any packet can pass.
+fn byte_burst(conf: &config::RateLimit) -> u32 {
+    if conf.byte_burst == 0 {
+        conf.bytes_per_second.max(MAX_PACKET_SIZE)
+    } else {
+        conf.byte_burst
+    }
+}
+
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: u32, burst: u32) -> Self {
+        // Without configured burst, the bucket holds one second worth of tokens.
+        let capacity = if burst == 0 { rate } else { burst } as f64;
+
+        TokenBucket {
+            rate: rate as f64,
+            capacity,
+            tokens: capacity,
+            updated_at: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.updated_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.updated_at = now;
+    }
+}
+
+/// Packets and bytes per second rate limiter, based on token buckets.
+pub struct RateLimiter {
+    packets: Option<TokenBucket>,
+    bytes: Option<TokenBucket>,
+    last_used: Instant,
+}
+
+impl RateLimiter {
+    /// Returns a new rate limiter, or None if the rate limit is disabled.
+    pub fn new(conf: &config::RateLimit) -> Option<Self> {
+        if !is_enabled(conf) {
+            return None;
+        }
+
+        Some(RateLimiter {
+            packets: (conf.packets_per_second != 0)
+                .then(|| TokenBucket::new(conf.packets_per_second, conf.packet_burst)),
+            bytes: (conf.bytes_per_second != 0)
+                .then(|| TokenBucket::new(conf.bytes_per_second, byte_burst(conf))),
+            last_used: Instant::now(),
+        })
+    }
+
+    /// Returns true if a packet of the given size is allowed. Tokens are only
+    /// consumed if the packet is allowed.
+    pub fn allow(&mut self, size: usize) -> bool {
+        let now = Instant::now();
+        self.last_used = now;
+
+        if let Some(b) = &mut self.packets {
+            b.refill(now);
+            if b.tokens < 1.0 {
+                return false;
+            }
+        }
+
+        if let Some(b) = &mut self.bytes {
+            b.refill(now);
+            if b.tokens < size as f64 {
+                return false;
+            }
+        }
+
+        if let Some(b) = &mut self.packets {
+            b.tokens -= 1.0;
+        }
+
+        if let Some(b) = &mut self.bytes {
+            b.tokens -= size as f64;
+        }
+
+        true
+    }
+
+    pub fn last_used(&self) -> Instant {
+        self.last_used
+    }
+}
@@ -0,0 +1,183 @@
+// Helpers for writing end-to-end tests against the multiplexer, using fake
+// gateways and servers bound to ephemeral ports.
+//
+// Note that the multiplexer uses global state. Starting it again replaces the
+// servers and bound addresses of the previous start, but the sockets of the
+// previous start keep running. Use one test (process) per multiplexer
+// configuration.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use tokio::net::UdpSocket;
+use tokio::time::timeout;
+
+use crate::config;
+use crate::forwarder;
+use crate::listener;
+use crate::packets::{GatewayId, PacketType};
+
+/// Max. time to wait for an expected packet.
+pub const RECV_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Starts the listener and forwarder using the given configuration and
+/// returns the addresses to which the listener is bound. Use
+/// "127.0.0.1:0" as bind for binding to an ephemeral port.
+pub async fn start(conf: &config::Configuration) -> Result<Vec<SocketAddr>> {
+    let (downlink_tx, uplink_rx) = listener::setup(conf).await.context("Setup listener")?;
+    forwarder::setup(downlink_tx, uplink_rx, conf.multiplexer.servers.clone())
+        .await
+        .context("Setup forwarder")?;
+
+    Ok(listener::get_bound_addrs().await)
+}
+
+pub use crate::packets::encode as packet;
+
+/// Fake gateway, connected to the multiplexer.
+pub struct FakeGateway {
+    pub gateway_id: GatewayId,
+    socket: UdpSocket,
+}
+
+impl FakeGateway {
+    pub async fn new(gateway_id: GatewayId, target: SocketAddr) -> Result<Self> {
+        let socket = UdpSocket::bind(SocketAddr::new(target.ip(), 0))
+            .await
+            .context("Bind socket")?;
+        socket.connect(target).await.context("Connect socket")?;
+
+        Ok(FakeGateway { gateway_id, socket })
+    }
+
+    pub fn addr(&self) -> Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    pub async fn send(&self, data: &[u8]) -> Result<()> {
+        self.socket.send(data).await.context("Send")?;
+        Ok(())
+    }
+
+    pub async fn push_data(&self, token: u16, payload: &[u8]) -> Result<()> {
+        self.send(&self.packet(PacketType::PushData, token, payload))
+            .await
+    }
+
+    pub async fn pull_data(&self, token: u16) -> Result<()> {
+        self.send(&self.packet(PacketType::PullData, token, &[]))
+            .await
+    }
+
+    pub async fn tx_ack(&self, token: u16, payload: &[u8]) -> Result<()> {
+        self.send(&self.packet(PacketType::TxAck, token, payload))
+            .await
+    }
+
+    /// Returns the packet as sent by this gateway.
+    pub fn packet(&self, packet_type: PacketType, token: u16, payload: &[u8]) -> Vec<u8> {
+        packet(packet_type, token, Some(self.gateway_id), payload)
+    }
+
+    /// Receives the next packet, returns an error after RECV_TIMEOUT.
+    pub async fn recv(&self) -> Result<Vec<u8>> {
+        let mut buffer = vec![0; 65535];
+        let size = timeout(RECV_TIMEOUT, self.socket.recv(&mut buffer))
+            .await
+            .map_err(|_| anyhow!("Gateway receive timeout"))??;
+        buffer.truncate(size);
+        Ok(buffer)
+    }
+
+    /// Returns an error in case a packet is received within the given
+    /// duration.
+    pub async fn expect_none(&self, duration: Duration) -> Result<()> {
+        let mut buffer = vec![0; 65535];
+        match timeout(duration, self.socket.recv(&mut buffer)).await {
+            Ok(Ok(size)) => Err(anyhow!("Unexpected packet: {:?}", &buffer[..size])),
+            Ok(Err(e)) => Err(e.into()),
+            Err(_) => Ok(()),
+        }
+    }
+}
+
+/// Fake server, receiving the packets forwarded by the multiplexer.
+pub struct FakeServer {
+    socket: UdpSocket,
+}
+
+impl FakeServer {
+    /// Binds the fake server to an ephemeral port on 127.0.0.1.
+    pub async fn new() -> Result<Self> {
+        let socket = UdpSocket::bind("127.0.0.1:0")
+            .await
+            .context("Bind socket")?;
+        Ok(FakeServer { socket })
+    }
+
+    pub fn addr(&self) -> Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// Returns the server configuration pointing to this fake server.
+    pub fn config(&self) -> Result<config::Server> {
+        Ok(config::Server {
+            server: self.addr()?.to_string(),
+            ..Default::default()
+        })
+    }
+
+    pub async fn send_to(&self, data: &[u8], addr: SocketAddr) -> Result<()> {
+        self.socket.send_to(data, addr).await.context("Send")?;
+        Ok(())
+    }
+
+    /// Sends the PUSH_ACK or PULL_ACK for the received PUSH_DATA or
+    /// PULL_DATA.
+    pub async fn ack(&self, data: &[u8], addr: SocketAddr) -> Result<()> {
+        let packet_type = match PacketType::try_from(data)? {
+            PacketType::PushData => PacketType::PushAck,
+            PacketType::PullData => PacketType::PullAck,
+            v => return Err(anyhow!("Unexpected packet-type: {}", v)),
+        };
+
+        self.send_to(
+            &packet(
+                packet_type,
+                u16::from_be_bytes([data[1], data[2]]),
+                None,
+                &[],
+            ),
+            addr,
+        )
+        .await
+    }
+
+    pub async fn pull_resp(&self, token: u16, payload: &[u8], addr: SocketAddr) -> Result<()> {
+        self.send_to(&packet(PacketType::PullResp, token, None, payload), addr)
+            .await
+    }
+
+    /// Receives the next packet and the address of the sending (multiplexer)
+    /// socket, returns an error after RECV_TIMEOUT.
+    pub async fn recv(&self) -> Result<(Vec<u8>, SocketAddr)> {
+        let mut buffer = vec![0; 65535];
+        let (size, addr) = timeout(RECV_TIMEOUT, self.socket.recv_from(&mut buffer))
+            .await
+            .map_err(|_| anyhow!("Server receive timeout"))??;
+        buffer.truncate(size);
+        Ok((buffer, addr))
+    }
+
+    /// Returns an error in case a packet is received within the given
+    /// duration.
+    pub async fn expect_none(&self, duration: Duration) -> Result<()> {
+        let mut buffer = vec![0; 65535];
+        match timeout(duration, self.socket.recv_from(&mut buffer)).await {
+            Ok(Ok((size, _))) => Err(anyhow!("Unexpected packet: {:?}", &buffer[..size])),
+            Ok(Err(e)) => Err(e.into()),
+            Err(_) => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,297 @@
+use std::io;
+use std::net::SocketAddr;
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+
+/// Options which are applied when creating an UDP socket. Options that are
+/// not set (empty / 0) are left at the OS default.
+#[derive(Default, Clone)]
+pub struct SocketOptions {
+    /// Set SO_REUSEPORT.
+    pub reuse_port: bool,
+    /// Interface to bind to (SO_BINDTODEVICE).
+    pub interface: String,
+    /// DSCP value, set as IP_TOS (IPv4) or IPV6_TCLASS (IPv6).
+    pub dscp: u8,
+    /// SO_RCVBUF size.
+    pub recv_buffer_size: usize,
+    /// SO_SNDBUF size.
+    pub send_buffer_size: usize,
+}
+
+/// Creates an UDP socket bound to the given address, with the given options
+/// applied.
+pub fn bind(addr: SocketAddr, opts: &SocketOptions) -> io::Result<UdpSocket> {
+    let sock = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+
+    if opts.reuse_port {
+        sock.set_reuse_port(true)?;
+    }
+
+    if !opts.interface.is_empty() {
+        sock.bind_device(Some(opts.interface.as_bytes()))?;
+    }
+
+    if opts.dscp != 0 {
+        let tos = u32::from(opts.dscp) << 2;
+        if addr.is_ipv4() {
+            sock.set_tos(tos)?;
+        } else {
+            sock.set_tclass_v6(tos)?;
+        }
+    }
+
+    if opts.recv_buffer_size != 0 {
+        sock.set_recv_buffer_size(opts.recv_buffer_size)?;
+    }
+
+    if opts.send_buffer_size != 0 {
+        sock.set_send_buffer_size(opts.send_buffer_size)?;
+    }
+
+    sock.set_nonblocking(true)?;
+    sock.bind(&addr.into())?;
+
+    UdpSocket::from_std(sock.into())
+}
+
+/// Buffers for receiving a batch of datagrams. The buffers are allocated
+/// once and re-used for each receive.
+pub struct RecvBatch {
+    buffers: Vec<Vec<u8>>,
+    // Buffer index, size and source address of each received datagram.
+    received: Vec<(usize, usize, SocketAddr)>,
+    #[cfg(target_os = "linux")]
+    mmsg: linux::MmsgBuffers,
+}
+
+impl RecvBatch {
+    /// Returns buffers for receiving at most batch_size datagrams per call.
+    pub fn new(batch_size: usize) -> Self {
+        let batch_size = batch_size.max(1);
+
+        RecvBatch {
+            buffers: vec![vec![0; 65535]; batch_size],
+            received: Vec::with_capacity(batch_size),
+            #[cfg(target_os = "linux")]
+            mmsg: linux::MmsgBuffers::new(batch_size),
+        }
+    }
+
+    /// Receives one or more datagrams from the socket, at most one per
+    /// buffer.
+    ///
+    /// On Linux, this uses a single recvmmsg syscall in case the batch size
+    /// is greater than one.
+    pub async fn recv(&mut self, socket: &UdpSocket) -> io::Result<()> {
+        self.received.clear();
+
+        #[cfg(target_os = "linux")]
+        if self.buffers.len() > 1 {
+            loop {
+                socket.readable().await?;
+
+                match socket.try_io(tokio::io::Interest::READABLE, || {
+                    self.mmsg
+                        .recvmmsg(socket, &mut self.buffers, &mut self.received)
+                }) {
+                    Ok(()) => return Ok(()),
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        let (size, addr) = socket.recv_from(&mut self.buffers[0]).await?;
+        self.received.push((0, size, addr));
+
+        Ok(())
+    }
+
+    /// Returns the data and source address of the datagrams received by the
+    /// last recv call.
+    pub fn received(&self) -> impl Iterator<Item = (&[u8], SocketAddr)> {
+        self.received
+            .iter()
+            .map(|(i, size, addr)| (&self.buffers[*i][..*size], *addr))
+    }
+}
+
+/// Sends the given datagrams using the socket. A datagram which can not be
+/// sent does not prevent the remaining datagrams from being sent, the index
+/// and error of each failed datagram is returned.
+///
+/// On Linux, this uses sendmmsg in case more than one datagram is given.
+pub async fn send_batch(
+    socket: &UdpSocket,
+    packets: &[(&[u8], SocketAddr)],
+) -> Vec<(usize, io::Error)> {
+    let mut errors = Vec::new();
+
+    #[cfg(target_os = "linux")]
+    if packets.len() > 1 {
+        let mut sent = 0;
+        while sent < packets.len() {
+            if let Err(e) = socket.writable().await {
+                errors.push((sent, e));
+                sent += 1;
+                continue;
+            }
+
+            match socket.try_io(tokio::io::Interest::WRITABLE, || {
+                linux::sendmmsg(socket, &packets[sent..])
+            }) {
+                Ok(n) => sent += n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                // sendmmsg only returns an error if the first datagram could
+                // not be sent, skip it and continue with the next one.
+                Err(e) => {
+                    errors.push((sent, e));
+                    sent += 1;
+                }
+            }
+        }
+
+        return errors;
+    }
+
+    for (i, (data, addr)) in packets.iter().enumerate() {
+        if let Err(e) = socket.send_to(data, addr).await {
+            errors.push((i, e));
+        }
+    }
+
+    errors
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::io;
+    use std::mem;
+    use std::net::SocketAddr;
+    use std::os::fd::AsRawFd;
+    use std::ptr;
+
+    use socket2::SockAddr;
+    use tokio::net::UdpSocket;
+
+    // Message headers and addresses used by recvmmsg. The pointers in the
+    // headers are set on each call, as the buffers might have moved.
+    pub struct MmsgBuffers {
+        addrs: Vec<libc::sockaddr_storage>,
+        iovecs: Vec<libc::iovec>,
+        msgs: Vec<libc::mmsghdr>,
+    }
+
+    // SAFETY: the raw pointers in iovecs and msgs are only written and read
+    // within recvmmsg, they are never dereferenced outside of that call.
+    unsafe impl Send for MmsgBuffers {}
+    unsafe impl Sync for MmsgBuffers {}
+
+    impl MmsgBuffers {
+        pub fn new(batch_size: usize) -> Self {
+            // SAFETY: sockaddr_storage, iovec and mmsghdr are plain C structs
+            // for which all-zero is a valid value.
+            MmsgBuffers {
+                addrs: (0..batch_size).map(|_| unsafe { mem::zeroed() }).collect(),
+                iovecs: (0..batch_size).map(|_| unsafe { mem::zeroed() }).collect(),
+                msgs: (0..batch_size).map(|_| unsafe { mem::zeroed() }).collect(),
+            }
+        }
+
+        pub fn recvmmsg(
+            &mut self,
+            socket: &UdpSocket,
+            buffers: &mut [Vec<u8>],
+            out: &mut Vec<(usize, usize, SocketAddr)>,
+        ) -> io::Result<()> {
+            let count = buffers.len().min(self.msgs.len());
+
+            for (i, buffer) in buffers.iter_mut().take(count).enumerate() {
+                self.iovecs[i] = libc::iovec {
+                    iov_base: buffer.as_mut_ptr() as *mut libc::c_void,
+                    iov_len: buffer.len(),
+                };
+
+                let msg = &mut self.msgs[i];
+                msg.msg_len = 0;
+                msg.msg_hdr.msg_name = &mut self.addrs[i] as *mut _ as *mut libc::c_void;
+                msg.msg_hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as _;
+                msg.msg_hdr.msg_iov = &mut self.iovecs[i];
+                msg.msg_hdr.msg_iovlen = 1;
+            }
+
+            // SAFETY: all pointers in msgs point to buffers that outlive the
+            // call.
+            let n = unsafe {
+                libc::recvmmsg(
+                    socket.as_raw_fd(),
+                    self.msgs.as_mut_ptr(),
+                    count as _,
+                    libc::MSG_DONTWAIT as _,
+                    ptr::null_mut(),
+                )
+            };
+            if n < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            for (i, (msg, addr)) in self
+                .msgs
+                .iter()
+                .zip(&self.addrs)
+                .take(n as usize)
+                .enumerate()
+            {
+                // SAFETY: the address was written by the kernel, with its
+                // length set in msg_namelen.
+                let addr = unsafe { SockAddr::new(*addr, msg.msg_hdr.msg_namelen) };
+                if let Some(addr) = addr.as_socket() {
+                    out.push((i, msg.msg_len as usize, addr));
+                }
+            }
+
+            Ok(())
+        }
+    }
+
+    pub fn sendmmsg(socket: &UdpSocket, packets: &[(&[u8], SocketAddr)]) -> io::Result<usize> {
+        let addrs: Vec<SockAddr> = packets.iter().map(|(_, a)| SockAddr::from(*a)).collect();
+        let mut iovecs: Vec<libc::iovec> = packets
+            .iter()
+            .map(|(b, _)| libc::iovec {
+                iov_base: b.as_ptr() as *mut libc::c_void,
+                iov_len: b.len(),
+            })
+            .collect();
+        // SAFETY: mmsghdr is a plain C struct for which all-zero is a valid
+        // value.
+        let mut msgs: Vec<libc::mmsghdr> = (0..packets.len())
+            .map(|_| unsafe { mem::zeroed() })
+            .collect();
+
+        for (i, msg) in msgs.iter_mut().enumerate() {
+            msg.msg_hdr.msg_name = addrs[i].as_ptr() as *mut libc::c_void;
+            msg.msg_hdr.msg_namelen = addrs[i].len();
+            msg.msg_hdr.msg_iov = &mut iovecs[i];
+            msg.msg_hdr.msg_iovlen = 1;
+        }
+
+        // SAFETY: all pointers in msgs point to buffers that outlive the call,
+        // the kernel does not write to the iovec buffers on send.
+        let n = unsafe {
+            libc::sendmmsg(
+                socket.as_raw_fd(),
+                msgs.as_mut_ptr(),
+                msgs.len() as _,
+                libc::MSG_DONTWAIT as _,
+            )
+        };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(n as usize)
+    }
+}
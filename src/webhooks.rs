@@ -0,0 +1,136 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use bytes::Bytes;
+use hmac::{Hmac, Mac};
+use http_body_util::Full;
+use hyper::{header, Method, Request, Uri};
+use hyper_util::client::legacy::{connect::HttpConnector, Client};
+use hyper_util::rt::TokioExecutor;
+use sha2::Sha256;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{debug, error, info, warn};
+
+use crate::config;
+use crate::events::{self, Event};
+use crate::traits::PrintFullError;
+
+type HttpClient = Client<HttpConnector, Full<Bytes>>;
+
+/// Header containing the HMAC-SHA256 signature of the request body, in case a
+/// secret is configured.
+pub const SIGNATURE_HEADER: &str = "X-Signature-256";
+
+pub async fn setup(conf: &[config::Webhook]) -> Result<()> {
+    for webhook in conf {
+        let uri: Uri = webhook
+            .url
+            .parse()
+            .with_context(|| format!("Parse webhook url: {}", webhook.url))?;
+        if uri.scheme_str() != Some("http") {
+            return Err(anyhow!(
+                "Unsupported webhook url: {}, only http:// is supported",
+                webhook.url
+            ));
+        }
+
+        info!(url = %webhook.url, events = ?webhook.events, "Setting up webhook");
+
+        let events_rx = events::subscribe().await;
+        tokio::spawn(handle_webhook(webhook.clone(), events_rx));
+    }
+
+    Ok(())
+}
+
+async fn handle_webhook(conf: config::Webhook, mut events_rx: broadcast::Receiver<Event>) {
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build_http();
+
+    loop {
+        let event = match events_rx.recv().await {
+            Ok(v) => v,
+            Err(RecvError::Lagged(skipped)) => {
+                warn!(url = %conf.url, skipped = skipped, "Webhook is lagging, skipping events");
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+
+        if !conf.events.is_empty() && !conf.events.iter().any(|v| v == event.name()) {
+            continue;
+        }
+
+        if let Err(e) = send_event(&client, &conf, &event).await {
+            error!(url = %conf.url, event = event.name(), error = %e.full(), "Send webhook error");
+        }
+    }
+}
+
+// Sends the event, with retries in case of an error or a non 2xx response.
+async fn send_event(client: &HttpClient, conf: &config::Webhook, event: &Event) -> Result<()> {
+    let body = Bytes::from(serde_json::to_vec(event).context("Encode event")?);
+    let signature = if conf.secret.is_empty() {
+        None
+    } else {
+        Some(sign(&conf.secret, &body)?)
+    };
+
+    let mut attempt = 0;
+    loop {
+        match post(client, conf, body.clone(), signature.as_deref()).await {
+            Ok(_) => {
+                debug!(url = %conf.url, event = event.name(), "Webhook sent");
+                return Ok(());
+            }
+            Err(e) if attempt < conf.max_retries => {
+                attempt += 1;
+                warn!(url = %conf.url, event = event.name(), attempt = attempt, error = %e.full(), "Webhook failed, retrying");
+                tokio::time::sleep(Duration::from_millis(conf.retry_interval_ms)).await;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+async fn post(
+    client: &HttpClient,
+    conf: &config::Webhook,
+    body: Bytes,
+    signature: Option<&str>,
+) -> Result<()> {
+    let mut req = Request::builder()
+        .method(Method::POST)
+        .uri(&conf.url)
+        .header(header::CONTENT_TYPE, "application/json");
+
+    for (k, v) in &conf.headers {
+        req = req.header(k, v);
+    }
+
+    if let Some(signature) = signature {
+        req = req.header(SIGNATURE_HEADER, signature);
+    }
+
+    let req = req.body(Full::new(body)).context("Build request")?;
+    let resp = tokio::time::timeout(Duration::from_millis(conf.timeout_ms), client.request(req))
+        .await
+        .map_err(|_| anyhow!("Request timeout"))?
+        .context("Request")?;
+
+    if !resp.status().is_success() {
+        return Err(anyhow!("Unexpected response status: {}", resp.status()));
+    }
+
+    Ok(())
+}
+
+/// Returns the signature of the body, in the format sha256=<hex encoded
+/// HMAC-SHA256>.
+pub fn sign(secret: &str, body: &[u8]) -> Result<String> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).context("Setup HMAC")?;
+    mac.update(body);
+    Ok(format!(
+        "sha256={}",
+        hex::encode(mac.finalize().into_bytes())
+    ))
+}
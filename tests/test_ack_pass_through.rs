@@ -0,0 +1,112 @@
+use std::time::Duration;
+
+use tracing_subscriber::prelude::*;
+
+use chirpstack_packet_multiplexer::config;
+use chirpstack_packet_multiplexer::packets::PacketType;
+use chirpstack_packet_multiplexer::testing::{self, FakeGateway, FakeServer};
+
+#[tokio::test]
+async fn test() {
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    // Servers.
+    let server1 = FakeServer::new().await.unwrap();
+    let server2 = FakeServer::new().await.unwrap();
+
+    let conf = config::Configuration {
+        multiplexer: config::Multiplexer {
+            bind: "127.0.0.1:0".into(),
+            servers: vec![
+                config::Server {
+                    primary: true,
+                    ..server1.config().unwrap()
+                },
+                server2.config().unwrap(),
+            ],
+            ..Default::default()
+        },
+        gateways: config::Gateways {
+            ack: config::Ack {
+                mode: config::AckMode::Primary,
+                timeout_ms: 200,
+                send_on_timeout: false,
+            },
+            dedup_window_ms: 1000,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    // The primary ACK mode requires a primary server.
+    let invalid_conf = config::Configuration {
+        multiplexer: config::Multiplexer {
+            servers: vec![server1.config().unwrap()],
+            ..Default::default()
+        },
+        gateways: config::Gateways {
+            ack: conf.gateways.ack.clone(),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    assert!(testing::start(&invalid_conf).await.is_err());
+
+    let addrs = testing::start(&conf).await.unwrap();
+
+    // Gateway.
+    let gw = FakeGateway::new("0102030405060708".parse().unwrap(), addrs[0])
+        .await
+        .unwrap();
+
+    // Send PUSH_DATA, expect PUSH_DATA at both servers.
+    gw.push_data(0x0102, b"{}").await.unwrap();
+    let push_data = gw.packet(PacketType::PushData, 0x0102, b"{}");
+    let (data, server1_addr) = server1.recv().await.unwrap();
+    assert_eq!(push_data, data);
+    let (data, server2_addr) = server2.recv().await.unwrap();
+    assert_eq!(push_data, data);
+
+    // Expect no PUSH_ACK at the gateway, as the primary server did not ack.
+    server2.ack(&data, server2_addr).await.unwrap();
+    gw.expect_none(Duration::from_millis(100)).await.unwrap();
+
+    // Send PUSH_ACK from primary server, expect PUSH_ACK at gateway.
+    server1.ack(&data, server1_addr).await.unwrap();
+    assert_eq!(vec![0x02, 0x01, 0x02, 0x01], gw.recv().await.unwrap());
+
+    // Send the PUSH_DATA again (e.g. the PUSH_ACK was lost). As the ACK is no
+    // longer pending, expect the PUSH_ACK directly at the gateway and the
+    // duplicate not to be forwarded.
+    gw.push_data(0x0102, b"{}").await.unwrap();
+    assert_eq!(vec![0x02, 0x01, 0x02, 0x01], gw.recv().await.unwrap());
+    server1
+        .expect_none(Duration::from_millis(100))
+        .await
+        .unwrap();
+
+    // Send PULL_DATA and PULL_ACK from primary server, expect PULL_ACK at
+    // gateway.
+    gw.pull_data(0x0103).await.unwrap();
+    let (data, _) = server1.recv().await.unwrap();
+    assert_eq!(gw.packet(PacketType::PullData, 0x0103, &[]), data);
+    server1.ack(&data, server1_addr).await.unwrap();
+    assert_eq!(vec![0x02, 0x01, 0x03, 0x04], gw.recv().await.unwrap());
+
+    // Send PUSH_DATA which is not acknowledged by the primary server, expect
+    // no PUSH_ACK at the gateway, also not after the timeout.
+    gw.push_data(0x0104, b"{}").await.unwrap();
+    gw.expect_none(Duration::from_millis(400)).await.unwrap();
+
+    // A late PUSH_ACK from the primary server is not forwarded.
+    server1
+        .ack(
+            &gw.packet(PacketType::PushData, 0x0104, b"{}"),
+            server1_addr,
+        )
+        .await
+        .unwrap();
+    gw.expect_none(Duration::from_millis(100)).await.unwrap();
+}
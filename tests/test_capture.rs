@@ -0,0 +1,95 @@
+use std::env;
+use std::fs;
+use std::time::Duration;
+
+use tokio::time::sleep;
+use tracing_subscriber::prelude::*;
+
+use chirpstack_packet_multiplexer::packets::PacketType;
+use chirpstack_packet_multiplexer::testing::{self, FakeGateway, FakeServer};
+use chirpstack_packet_multiplexer::{capture, config};
+
+#[tokio::test]
+async fn test() {
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    let file = env::temp_dir().join("chirpstack-packet-multiplexer-test.pcap");
+
+    // Server.
+    let server = FakeServer::new().await.unwrap();
+
+    let conf = config::Configuration {
+        multiplexer: config::Multiplexer {
+            bind: "127.0.0.1:0".into(),
+            servers: vec![server.config().unwrap()],
+            ..Default::default()
+        },
+        capture: config::Capture {
+            file: file.to_string_lossy().to_string(),
+            gateway_id_prefixes: vec!["0102030405060708/64".parse().unwrap()],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    capture::setup(&conf.capture).await.unwrap();
+    let addrs = testing::start(&conf).await.unwrap();
+
+    // Gateways, only the first matches the capture prefix.
+    let gw1 = FakeGateway::new("0102030405060708".parse().unwrap(), addrs[0])
+        .await
+        .unwrap();
+    let gw2 = FakeGateway::new("0202030405060708".parse().unwrap(), addrs[0])
+        .await
+        .unwrap();
+
+    for gw in [&gw1, &gw2] {
+        gw.push_data(0x0102, b"{}").await.unwrap();
+        assert_eq!(vec![0x02, 0x01, 0x02, 0x01], gw.recv().await.unwrap());
+        let (data, _) = server.recv().await.unwrap();
+        assert_eq!(gw.packet(PacketType::PushData, 0x0102, b"{}"), data);
+    }
+
+    // Wait for the records to be written.
+    sleep(Duration::from_millis(200)).await;
+
+    let b = fs::read(&file).unwrap();
+    assert_eq!(&0xa1b23c4du32.to_le_bytes(), &b[..4]);
+    assert_eq!(&101u32.to_le_bytes(), &b[20..24]);
+
+    // Parse the records, returning the UDP payloads with the source and
+    // destination ports.
+    let mut records = Vec::new();
+    let mut b = &b[24..];
+    while !b.is_empty() {
+        let incl_len = u32::from_le_bytes([b[8], b[9], b[10], b[11]]) as usize;
+        let packet = &b[16..16 + incl_len];
+        assert_eq!(0x45, packet[0]);
+        assert_eq!(17, packet[9]);
+
+        let udp = &packet[20..];
+        records.push((
+            u16::from_be_bytes([udp[0], udp[1]]),
+            u16::from_be_bytes([udp[2], udp[3]]),
+            udp[8..].to_vec(),
+        ));
+        b = &b[16 + incl_len..];
+    }
+
+    // Expect the PUSH_DATA received from gw1, the PUSH_ACK sent to gw1 and
+    // the PUSH_DATA sent to the server. Nothing is expected for gw2.
+    let push_data_gw1 = gw1.packet(PacketType::PushData, 0x0102, b"{}");
+    let gw1_port = gw1.addr().unwrap().port();
+    let port = addrs[0].port();
+    let server_port = server.addr().unwrap().port();
+    assert_eq!(3, records.len());
+    assert!(records.contains(&(gw1_port, port, push_data_gw1.clone())));
+    assert!(records.contains(&(port, gw1_port, vec![0x02, 0x01, 0x02, 0x01])));
+    assert!(records
+        .iter()
+        .any(|(_, dst, data)| *dst == server_port && data == &push_data_gw1));
+
+    fs::remove_file(&file).unwrap();
+}
@@ -0,0 +1,90 @@
+use std::env;
+use std::fs;
+use std::process::Command;
+
+use chirpstack_packet_multiplexer::config;
+
+#[tokio::test]
+async fn test() {
+    let dir = env::temp_dir();
+    let file_1 = dir.join("chirpstack-packet-multiplexer-test-validation-1.toml");
+    let file_2 = dir.join("chirpstack-packet-multiplexer-test-validation-2.toml");
+    let file_names = vec![
+        file_1.to_string_lossy().to_string(),
+        file_2.to_string_lossy().to_string(),
+    ];
+
+    // Unknown key, expect the error to point to the second file.
+    fs::write(
+        &file_1,
+        "[multiplexer]\nbind=\"127.0.0.1:1700\"\n\n[[multiplexer.server]]\nserver=\"127.0.0.1:1701\"\n",
+    )
+    .unwrap();
+    fs::write(&file_2, "[logging]\nlevel=\"info\"\nlevle=\"debug\"\n").unwrap();
+
+    let err = config::Configuration::get(&file_names).err().unwrap();
+    assert_eq!(
+        format!(
+            "{}:3:1: unknown field `levle`, expected `level`",
+            file_names[1]
+        ),
+        err.to_string()
+    );
+
+    // Valid configuration.
+    fs::write(&file_2, "[logging]\nlevel=\"info\"\n").unwrap();
+    let conf = config::Configuration::get(&file_names).unwrap();
+    assert!(conf.validate().await.is_empty());
+
+    // Duplicate servers and spill files, empty and overlapping prefixes.
+    fs::write(
+        &file_2,
+        r#"
+[[multiplexer.server]]
+server="127.0.0.1:1701"
+gateway_id_prefixes=["0102000000000000/16", "0102030000000000/24"]
+
+[[multiplexer.server]]
+server="127.0.0.1:1702"
+join_eui_prefixes=["0000000000000000/0"]
+
+[multiplexer.server.buffer]
+spill_file="/tmp/chirpstack-packet-multiplexer.spill"
+
+[[multiplexer.server]]
+server="invalid"
+
+[multiplexer.server.buffer]
+spill_file="/tmp/chirpstack-packet-multiplexer.spill"
+"#,
+    )
+    .unwrap();
+    let conf = config::Configuration::get(&file_names).unwrap();
+    let problems = conf.validate().await;
+    assert_eq!(5, problems.len(), "{:?}", problems);
+    assert_eq!("Duplicate server: 127.0.0.1:1701", problems[0]);
+    assert_eq!(
+        "multiplexer.server 127.0.0.1:1701: gateway_id_prefixes: prefix 0102000000000000/16 overlaps with 0102030000000000/24",
+        problems[1]
+    );
+    assert_eq!(
+        "multiplexer.server 127.0.0.1:1702: join_eui_prefixes: empty prefix 0000000000000000/0 matches everything, use an empty list instead",
+        problems[2]
+    );
+    assert!(problems[3].starts_with("multiplexer.server invalid: Unable to resolve invalid"));
+    assert_eq!(
+        "multiplexer.server invalid: buffer.spill_file: /tmp/chirpstack-packet-multiplexer.spill is already used by another server",
+        problems[4]
+    );
+
+    // Expect the validate subcommand to exit with a non-zero exit code.
+    let output = Command::new(env!("CARGO_BIN_EXE_chirpstack-packet-multiplexer"))
+        .args(["-c", &file_names[0], "-c", &file_names[1], "validate"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Duplicate server: 127.0.0.1:1701"));
+
+    fs::remove_file(&file_1).unwrap();
+    fs::remove_file(&file_2).unwrap();
+}
@@ -0,0 +1,76 @@
+use std::time::Duration;
+
+use tokio::time::sleep;
+use tracing_subscriber::prelude::*;
+
+use chirpstack_packet_multiplexer::config;
+use chirpstack_packet_multiplexer::testing::{self, FakeGateway, FakeServer};
+
+#[tokio::test]
+async fn test() {
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    // Server.
+    let server = FakeServer::new().await.unwrap();
+
+    let conf = config::Configuration {
+        multiplexer: config::Multiplexer {
+            bind: "127.0.0.1:0".into(),
+            servers: vec![server.config().unwrap()],
+            ..Default::default()
+        },
+        gateways: config::Gateways {
+            downlink_queue: config::DownlinkQueue {
+                max_packets: 2,
+                max_age_ms: 200,
+            },
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let addrs = testing::start(&conf).await.unwrap();
+
+    // Gateways.
+    let gw1 = FakeGateway::new("0102030405060708".parse().unwrap(), addrs[0])
+        .await
+        .unwrap();
+    let gw2 = FakeGateway::new("0202030405060708".parse().unwrap(), addrs[0])
+        .await
+        .unwrap();
+
+    // Send PUSH_DATA from both gateways, these do not set the gateway address
+    // used for downlinks.
+    let mut server_addrs = Vec::new();
+    for gw in [&gw1, &gw2] {
+        gw.push_data(0x0102, b"{}").await.unwrap();
+        assert_eq!(vec![0x02, 0x01, 0x02, 0x01], gw.recv().await.unwrap());
+
+        let (_, addr) = server.recv().await.unwrap();
+        server_addrs.push(addr);
+    }
+
+    // Send PULL_RESP for both gateways, these are queued.
+    for addr in server_addrs {
+        server.pull_resp(0x0103, b"{}", addr).await.unwrap();
+    }
+    sleep(Duration::from_millis(100)).await;
+
+    // Send PULL_DATA from gateway 1, expect PULL_ACK and the queued PULL_RESP.
+    gw1.pull_data(0x0104).await.unwrap();
+    let mut received = Vec::new();
+    for _ in 0..2 {
+        received.push(gw1.recv().await.unwrap());
+    }
+    assert!(received.contains(&vec![0x02, 0x01, 0x04, 0x04]));
+    assert!(received.contains(&vec![0x02, 0x01, 0x03, 0x03, 0x7b, 0x7d]));
+
+    // Send PULL_DATA from gateway 2 after the max. age, expect only PULL_ACK
+    // as the queued PULL_RESP has expired.
+    sleep(Duration::from_millis(200)).await;
+    gw2.pull_data(0x0104).await.unwrap();
+    assert_eq!(vec![0x02, 0x01, 0x04, 0x04], gw2.recv().await.unwrap());
+    gw2.expect_none(Duration::from_millis(100)).await.unwrap();
+}
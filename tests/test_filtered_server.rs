@@ -1,11 +1,11 @@
 use std::str::FromStr;
 use std::time::Duration;
 
-use tokio::net::UdpSocket;
-use tokio::time::timeout;
 use tracing_subscriber::prelude::*;
 
-use chirpstack_packet_multiplexer::{config, forwarder, listener};
+use chirpstack_packet_multiplexer::config;
+use chirpstack_packet_multiplexer::packets::PacketType;
+use chirpstack_packet_multiplexer::testing::{self, FakeGateway, FakeServer};
 use lrwn_filters::EuiPrefix;
 
 #[tokio::test]
@@ -14,54 +14,42 @@ async fn test() {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
+    // Servers.
+    let server1 = FakeServer::new().await.unwrap();
+    let server2 = FakeServer::new().await.unwrap();
+
     let conf = config::Configuration {
         multiplexer: config::Multiplexer {
-            bind: "0.0.0.0:1710".into(),
+            bind: "127.0.0.1:0".into(),
             servers: vec![
+                server1.config().unwrap(),
                 config::Server {
-                    server: "localhost:1711".into(),
-                    ..Default::default()
-                },
-                config::Server {
-                    server: "localhost:1712".into(),
                     gateway_id_prefixes: vec![EuiPrefix::from_str("0101000000000000/16").unwrap()],
-                    ..Default::default()
+                    ..server2.config().unwrap()
                 },
             ],
+            ..Default::default()
         },
         ..Default::default()
     };
 
-    let (downlink_tx, uplink_rx) = listener::setup(&conf.multiplexer.bind).await.unwrap();
-    forwarder::setup(downlink_tx, uplink_rx, conf.multiplexer.servers.clone())
+    let addrs = testing::start(&conf).await.unwrap();
+
+    // Gateway.
+    let gw = FakeGateway::new("0102030405060708".parse().unwrap(), addrs[0])
         .await
         .unwrap();
-    let mut buffer: [u8; 65535] = [0; 65535];
-
-    // Server sockets.
-    let server1_sock = UdpSocket::bind("0.0.0.0:1711").await.unwrap();
-    let server2_sock = UdpSocket::bind("0.0.0.0:1701").await.unwrap();
-
-    // Gateway socket.
-    let gw_sock = UdpSocket::bind("0.0.0.0:0").await.unwrap();
-    gw_sock.connect("localhost:1710").await.unwrap();
 
     // Send PUSH_DATA.
-    gw_sock
-        .send(&[
-            0x02, 0x01, 0x02, 0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x7b, 0x7d,
-        ])
-        .await
-        .unwrap();
+    gw.push_data(0x0102, b"{}").await.unwrap();
 
     // Expect PUSH_DATA forwarded to server 1.
-    let size = server1_sock.recv(&mut buffer).await.unwrap();
-    assert_eq!(
-        &[0x02, 0x01, 0x02, 0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x7b, 0x7d,],
-        &buffer[..size]
-    );
+    let (data, _) = server1.recv().await.unwrap();
+    assert_eq!(gw.packet(PacketType::PushData, 0x0102, b"{}"), data);
 
     // Expect PUSH_DATA not forwarded to server 2.
-    let resp = timeout(Duration::from_millis(100), server2_sock.recv(&mut buffer)).await;
-    assert!(resp.is_err());
+    server2
+        .expect_none(Duration::from_millis(100))
+        .await
+        .unwrap();
 }
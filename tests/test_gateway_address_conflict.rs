@@ -0,0 +1,119 @@
+use std::time::Duration;
+
+use serde_json::Value;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing_subscriber::prelude::*;
+
+use chirpstack_packet_multiplexer::packets::PacketType;
+use chirpstack_packet_multiplexer::testing::{self, FakeGateway, FakeServer};
+use chirpstack_packet_multiplexer::{config, monitoring};
+
+#[tokio::test]
+async fn test() {
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    // Server.
+    let server = FakeServer::new().await.unwrap();
+
+    let conf = config::Configuration {
+        multiplexer: config::Multiplexer {
+            bind: "127.0.0.1:0".into(),
+            servers: vec![server.config().unwrap()],
+            ..Default::default()
+        },
+        gateways: config::Gateways {
+            address_change_policy: config::AddressChangePolicy::RequireConsecutive,
+            address_change_keepalives: 2,
+            ..Default::default()
+        },
+        monitoring: config::Monitoring {
+            bind: "127.0.0.1:1720".into(),
+        },
+        ..Default::default()
+    };
+
+    let addrs = testing::start(&conf).await.unwrap();
+    monitoring::setup(&conf.monitoring.bind).await.unwrap();
+
+    // Gateways, using the same Gateway ID.
+    let gw1 = FakeGateway::new("0102030405060708".parse().unwrap(), addrs[0])
+        .await
+        .unwrap();
+    let gw2 = FakeGateway::new("0102030405060708".parse().unwrap(), addrs[0])
+        .await
+        .unwrap();
+
+    // Send PULL_DATA from gateway 1.
+    gw1.pull_data(0x0102).await.unwrap();
+
+    // Expect PULL_ACK at gateway 1.
+    assert_eq!(vec![0x02, 0x01, 0x02, 0x04], gw1.recv().await.unwrap());
+
+    // Expect PULL_DATA forwarded to server.
+    let (data, addr) = server.recv().await.unwrap();
+    assert_eq!(gw1.packet(PacketType::PullData, 0x0102, &[]), data);
+
+    // Send PULL_DATA from gateway 2.
+    gw2.pull_data(0x0102).await.unwrap();
+
+    // Expect no PULL_ACK at gateway 2, as it is not yet accepted.
+    gw2.expect_none(Duration::from_millis(100)).await.unwrap();
+
+    // Expect PULL_DATA not forwarded to server.
+    server
+        .expect_none(Duration::from_millis(100))
+        .await
+        .unwrap();
+
+    // Send PULL_RESP from server, expect it at gateway 1.
+    server.pull_resp(0x0103, b"{}", addr).await.unwrap();
+    assert_eq!(
+        vec![0x02, 0x01, 0x03, 0x03, 0x7b, 0x7d],
+        gw1.recv().await.unwrap()
+    );
+
+    // Send second consecutive PULL_DATA from gateway 2.
+    gw2.pull_data(0x0102).await.unwrap();
+
+    // Expect PULL_ACK at gateway 2.
+    assert_eq!(vec![0x02, 0x01, 0x02, 0x04], gw2.recv().await.unwrap());
+
+    // Expect PULL_DATA forwarded to server.
+    let (data, _) = server.recv().await.unwrap();
+    assert_eq!(gw2.packet(PacketType::PullData, 0x0102, &[]), data);
+
+    // Send PULL_RESP from server, expect it at gateway 2.
+    server.pull_resp(0x0104, b"{}", addr).await.unwrap();
+    assert_eq!(
+        vec![0x02, 0x01, 0x04, 0x03, 0x7b, 0x7d],
+        gw2.recv().await.unwrap()
+    );
+
+    // Expect the conflict to be exposed by the monitoring endpoint.
+    let mut stream = TcpStream::connect("127.0.0.1:1720").await.unwrap();
+    stream
+        .write_all(b"GET /gateways/conflicts HTTP/1.0\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let mut resp = String::new();
+    stream.read_to_string(&mut resp).await.unwrap();
+    let (_, body) = resp.split_once("\r\n\r\n").unwrap();
+    let conflicts: Value = serde_json::from_str(body).unwrap();
+
+    assert_eq!("0102030405060708", conflicts[0]["gateway_id"]);
+    assert_eq!(2, conflicts[0]["conflict_count"]);
+    assert_eq!(
+        gw2.addr().unwrap().port(),
+        conflicts[0]["addr"]
+            .as_str()
+            .unwrap()
+            .rsplit_once(':')
+            .unwrap()
+            .1
+            .parse::<u16>()
+            .unwrap()
+    );
+}
@@ -0,0 +1,81 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use tracing_subscriber::prelude::*;
+
+use chirpstack_packet_multiplexer::config;
+use chirpstack_packet_multiplexer::packets::PacketType;
+use chirpstack_packet_multiplexer::testing::{self, FakeGateway, FakeServer};
+use lrwn_filters::EuiPrefix;
+
+#[tokio::test]
+async fn test() {
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    // Server.
+    let server = FakeServer::new().await.unwrap();
+
+    let conf = config::Configuration {
+        multiplexer: config::Multiplexer {
+            bind: "127.0.0.1:0".into(),
+            servers: vec![server.config().unwrap()],
+            ..Default::default()
+        },
+        gateways: config::Gateways {
+            allowed: vec![
+                config::GatewayAllow {
+                    gateway_id_prefixes: vec![EuiPrefix::from_str("0102030405060708/64").unwrap()],
+                    source_cidrs: vec!["127.0.0.0/8".parse().unwrap()],
+                },
+                config::GatewayAllow {
+                    gateway_id_prefixes: vec![EuiPrefix::from_str("0202030405060708/64").unwrap()],
+                    source_cidrs: vec!["10.0.0.0/8".parse().unwrap()],
+                },
+            ],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let addrs = testing::start(&conf).await.unwrap();
+
+    // Gateways, the first is allowed, the second is not allowed from this
+    // source and the third is not allowed at all.
+    let gw1 = FakeGateway::new("0102030405060708".parse().unwrap(), addrs[0])
+        .await
+        .unwrap();
+    let gw2 = FakeGateway::new("0202030405060708".parse().unwrap(), addrs[0])
+        .await
+        .unwrap();
+    let gw3 = FakeGateway::new("0302030405060708".parse().unwrap(), addrs[0])
+        .await
+        .unwrap();
+
+    // Send PUSH_DATA from allowed gateway.
+    gw1.push_data(0x0102, b"{}").await.unwrap();
+
+    // Expect PUSH_ACK.
+    assert_eq!(vec![0x02, 0x01, 0x02, 0x01], gw1.recv().await.unwrap());
+
+    // Expect PUSH_DATA forwarded to server.
+    let (data, _) = server.recv().await.unwrap();
+    assert_eq!(gw1.packet(PacketType::PushData, 0x0102, b"{}"), data);
+
+    // Send PULL_DATA from gateway which is not allowed from this source.
+    gw2.pull_data(0x0103).await.unwrap();
+
+    // Send PUSH_DATA from gateway which is not allowed.
+    gw3.push_data(0x0104, b"{}").await.unwrap();
+
+    // Expect no ACKs at the gateways.
+    gw2.expect_none(Duration::from_millis(100)).await.unwrap();
+    gw3.expect_none(Duration::from_millis(100)).await.unwrap();
+
+    // Expect nothing forwarded to the server.
+    server
+        .expect_none(Duration::from_millis(100))
+        .await
+        .unwrap();
+}
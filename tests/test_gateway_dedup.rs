@@ -0,0 +1,86 @@
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::sleep;
+use tracing_subscriber::prelude::*;
+
+use chirpstack_packet_multiplexer::packets::PacketType;
+use chirpstack_packet_multiplexer::testing::{self, FakeGateway, FakeServer};
+use chirpstack_packet_multiplexer::{config, monitoring};
+
+#[tokio::test]
+async fn test() {
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    // Server.
+    let server = FakeServer::new().await.unwrap();
+
+    let conf = config::Configuration {
+        multiplexer: config::Multiplexer {
+            bind: "127.0.0.1:0".into(),
+            servers: vec![server.config().unwrap()],
+            ..Default::default()
+        },
+        gateways: config::Gateways {
+            dedup_window_ms: 300,
+            ..Default::default()
+        },
+        monitoring: config::Monitoring {
+            bind: "127.0.0.1:1720".into(),
+        },
+        ..Default::default()
+    };
+
+    let addrs = testing::start(&conf).await.unwrap();
+    monitoring::setup(&conf.monitoring.bind).await.unwrap();
+
+    // Gateways using the same Gateway ID, the second simulating a NAT
+    // re-binding.
+    let gw1 = FakeGateway::new("0102030405060708".parse().unwrap(), addrs[0])
+        .await
+        .unwrap();
+    let gw2 = FakeGateway::new("0102030405060708".parse().unwrap(), addrs[0])
+        .await
+        .unwrap();
+
+    // Send the same PUSH_DATA twice, expect both to be acknowledged.
+    for gw in [&gw1, &gw2] {
+        gw.push_data(0x0102, b"{}").await.unwrap();
+        assert_eq!(vec![0x02, 0x01, 0x02, 0x01], gw.recv().await.unwrap());
+    }
+
+    // Expect the PUSH_DATA only once at the server.
+    let push_data = gw1.packet(PacketType::PushData, 0x0102, b"{}");
+    let (data, _) = server.recv().await.unwrap();
+    assert_eq!(push_data, data);
+    server
+        .expect_none(Duration::from_millis(100))
+        .await
+        .unwrap();
+
+    // Send PUSH_DATA with the same token but different payload, expect it to
+    // be forwarded.
+    gw1.push_data(0x0102, b"{ }").await.unwrap();
+    let (data, _) = server.recv().await.unwrap();
+    assert_eq!(gw1.packet(PacketType::PushData, 0x0102, b"{ }"), data);
+
+    // Send the first PUSH_DATA again after the window, expect it to be
+    // forwarded.
+    sleep(Duration::from_millis(400)).await;
+    gw1.push_data(0x0102, b"{}").await.unwrap();
+    let (data, _) = server.recv().await.unwrap();
+    assert_eq!(push_data, data);
+
+    // Expect the suppressed duplicate to be counted.
+    let mut stream = TcpStream::connect("127.0.0.1:1720").await.unwrap();
+    stream
+        .write_all(b"GET /metrics HTTP/1.0\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let mut resp = String::new();
+    stream.read_to_string(&mut resp).await.unwrap();
+    assert!(resp.contains("gateway_udp_duplicate_count_total{gateway_id=\"0102030405060708\"} 1\n"));
+}
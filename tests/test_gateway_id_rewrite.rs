@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use tracing_subscriber::prelude::*;
+
+use chirpstack_packet_multiplexer::config;
+use chirpstack_packet_multiplexer::packets::{GatewayId, GatewayIdPrefix, PacketType};
+use chirpstack_packet_multiplexer::testing::{self, FakeGateway, FakeServer};
+
+#[tokio::test]
+async fn test() {
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    // Servers.
+    let server1 = FakeServer::new().await.unwrap();
+    let server2 = FakeServer::new().await.unwrap();
+
+    let conf = config::Configuration {
+        multiplexer: config::Multiplexer {
+            bind: "127.0.0.1:0".into(),
+            servers: vec![
+                config::Server {
+                    gateway_id_mapping: HashMap::from([(
+                        GatewayId::from_str("0102030405060708").unwrap(),
+                        GatewayId::from_str("1112131415161718").unwrap(),
+                    )]),
+                    ..server1.config().unwrap()
+                },
+                config::Server {
+                    gateway_id_prefix_rewrite: Some(
+                        GatewayIdPrefix::from_str("aabb000000000000/16").unwrap(),
+                    ),
+                    ..server2.config().unwrap()
+                },
+            ],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let addrs = testing::start(&conf).await.unwrap();
+
+    // Gateway.
+    let gw = FakeGateway::new("0102030405060708".parse().unwrap(), addrs[0])
+        .await
+        .unwrap();
+
+    // Gateway IDs as expected at server 1 (mapped) and server 2 (prefix
+    // rewritten).
+    let mapped_id = GatewayId::from_str("1112131415161718").unwrap();
+    let rewritten_id = GatewayId::from_str("aabb030405060708").unwrap();
+
+    // Send PUSH_DATA.
+    gw.push_data(0x0102, b"{}").await.unwrap();
+
+    // Expect PUSH_ACK.
+    assert_eq!(vec![0x02, 0x01, 0x02, 0x01], gw.recv().await.unwrap());
+
+    // Expect PUSH_DATA with mapped Gateway ID at server 1.
+    let (data, _) = server1.recv().await.unwrap();
+    assert_eq!(
+        testing::packet(PacketType::PushData, 0x0102, Some(mapped_id), b"{}"),
+        data
+    );
+
+    // Expect PUSH_DATA with rewritten Gateway ID prefix at server 2.
+    let (data, _) = server2.recv().await.unwrap();
+    assert_eq!(
+        testing::packet(PacketType::PushData, 0x0102, Some(rewritten_id), b"{}"),
+        data
+    );
+
+    // Send PULL_DATA.
+    gw.pull_data(0x0103).await.unwrap();
+
+    // Expect PULL_ACK.
+    assert_eq!(vec![0x02, 0x01, 0x03, 0x04], gw.recv().await.unwrap());
+
+    // Expect PULL_DATA with mapped Gateway ID at server 1.
+    let (data, addr) = server1.recv().await.unwrap();
+    assert_eq!(
+        testing::packet(PacketType::PullData, 0x0103, Some(mapped_id), &[]),
+        data
+    );
+
+    // Expect PULL_DATA with rewritten Gateway ID prefix at server 2.
+    let (data, _) = server2.recv().await.unwrap();
+    assert_eq!(
+        testing::packet(PacketType::PullData, 0x0103, Some(rewritten_id), &[]),
+        data
+    );
+
+    // Send PULL_RESP from server 1.
+    server1.pull_resp(0x0104, b"{}", addr).await.unwrap();
+
+    // Expect PULL_RESP at the gateway.
+    assert_eq!(
+        vec![0x02, 0x01, 0x04, 0x03, 0x7b, 0x7d],
+        gw.recv().await.unwrap()
+    );
+
+    // Send TX_ACK from gateway.
+    gw.tx_ack(0x0104, &[]).await.unwrap();
+
+    // Expect TX_ACK with mapped Gateway ID at server 1.
+    let (data, _) = server1.recv().await.unwrap();
+    assert_eq!(
+        testing::packet(PacketType::TxAck, 0x0104, Some(mapped_id), &[]),
+        data
+    );
+}
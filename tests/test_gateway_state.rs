@@ -0,0 +1,98 @@
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tracing_subscriber::prelude::*;
+
+use chirpstack_packet_multiplexer::events::{self, Event};
+use chirpstack_packet_multiplexer::testing::{self, FakeGateway, FakeServer};
+use chirpstack_packet_multiplexer::{config, monitoring};
+
+async fn get_metrics() -> String {
+    let mut stream = TcpStream::connect("127.0.0.1:1720").await.unwrap();
+    stream
+        .write_all(b"GET /metrics HTTP/1.0\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let mut resp = String::new();
+    stream.read_to_string(&mut resp).await.unwrap();
+    resp
+}
+
+#[tokio::test]
+async fn test() {
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    // Server.
+    let server = FakeServer::new().await.unwrap();
+
+    let conf = config::Configuration {
+        multiplexer: config::Multiplexer {
+            bind: "127.0.0.1:0".into(),
+            servers: vec![server.config().unwrap()],
+            ..Default::default()
+        },
+        gateways: config::Gateways {
+            keepalive_interval_secs: 1,
+            offline_after_keepalives: 1,
+            ..Default::default()
+        },
+        monitoring: config::Monitoring {
+            bind: "127.0.0.1:1720".into(),
+        },
+        ..Default::default()
+    };
+
+    let mut events_rx = events::subscribe().await;
+    let addrs = testing::start(&conf).await.unwrap();
+    monitoring::setup(&conf.monitoring.bind).await.unwrap();
+
+    // Gateway.
+    let gw = FakeGateway::new("0102030405060708".parse().unwrap(), addrs[0])
+        .await
+        .unwrap();
+    let gw_addr = gw.addr().unwrap();
+
+    // Send PULL_DATA and expect PULL_ACK.
+    gw.pull_data(0x0102).await.unwrap();
+    assert_eq!(vec![0x02, 0x01, 0x02, 0x04], gw.recv().await.unwrap());
+
+    // Expect first seen and online events.
+    let event = events_rx.recv().await.unwrap();
+    assert!(matches!(
+        event,
+        Event::GatewayFirstSeen { addr, .. } if addr == gw_addr
+    ));
+    assert_eq!(Some(gw.gateway_id), event.gateway_id());
+    let event = events_rx.recv().await.unwrap();
+    assert!(matches!(event, Event::GatewayOnline { addr, .. } if addr == gw_addr));
+
+    let metrics = get_metrics().await;
+    assert!(metrics.contains("gateways_online 1\n"));
+    assert!(metrics.contains("gateway_last_seen{gateway_id=\"0102030405060708\"} "));
+
+    // Send PULL_DATA again, expect no event as the gateway is online.
+    gw.pull_data(0x0102).await.unwrap();
+    gw.recv().await.unwrap();
+    let resp = timeout(Duration::from_millis(100), events_rx.recv()).await;
+    assert!(resp.is_err());
+
+    // Expect offline event after the keepalive interval.
+    let event = timeout(Duration::from_secs(3), events_rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!("gateway_offline", event.name());
+    assert!(get_metrics().await.contains("gateways_online 0\n"));
+
+    // Send PULL_DATA, expect only online event.
+    gw.pull_data(0x0102).await.unwrap();
+    gw.recv().await.unwrap();
+    let event = events_rx.recv().await.unwrap();
+    assert_eq!("gateway_online", event.name());
+    let resp = timeout(Duration::from_millis(100), events_rx.recv()).await;
+    assert!(resp.is_err());
+}
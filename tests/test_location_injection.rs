@@ -0,0 +1,100 @@
+use std::fs;
+
+use serde_json::{json, Value};
+use tracing_subscriber::prelude::*;
+
+use chirpstack_packet_multiplexer::packets::PacketType;
+use chirpstack_packet_multiplexer::testing::{self, FakeGateway, FakeServer};
+use chirpstack_packet_multiplexer::{config, metadata};
+
+#[tokio::test]
+async fn test() {
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    let metadata_file = std::env::temp_dir().join("test_location_injection.toml");
+    fs::write(
+        &metadata_file,
+        r#"
+        [[gateway]]
+          gateway_id="0102030405060708"
+          name="office"
+          latitude=52.374
+          longitude=4.8897
+          altitude=12
+
+          [gateway.tags]
+            site="amsterdam"
+        "#,
+    )
+    .unwrap();
+
+    // Servers.
+    let server1 = FakeServer::new().await.unwrap();
+    let server2 = FakeServer::new().await.unwrap();
+
+    let conf = config::Configuration {
+        multiplexer: config::Multiplexer {
+            bind: "127.0.0.1:0".into(),
+            servers: vec![
+                server1.config().unwrap(),
+                config::Server {
+                    inject_location: true,
+                    ..server2.config().unwrap()
+                },
+            ],
+            ..Default::default()
+        },
+        gateways: config::Gateways {
+            metadata_file: metadata_file.to_str().unwrap().into(),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    metadata::setup(&conf.gateways.metadata_file).await.unwrap();
+    let addrs = testing::start(&conf).await.unwrap();
+
+    // Gateway.
+    let gw = FakeGateway::new("0102030405060708".parse().unwrap(), addrs[0])
+        .await
+        .unwrap();
+
+    // Send PUSH_DATA without location.
+    let payload = serde_json::to_vec(&json!({"stat": {"rxnb": 1}})).unwrap();
+    gw.push_data(0x0101, &payload).await.unwrap();
+
+    // Expect PUSH_ACK.
+    assert_eq!(vec![0x02, 0x01, 0x01, 0x01], gw.recv().await.unwrap());
+
+    // Expect PUSH_DATA forwarded as-is to server 1.
+    let (data, _) = server1.recv().await.unwrap();
+    assert_eq!(gw.packet(PacketType::PushData, 0x0101, &payload), data);
+
+    // Expect injected location at server 2.
+    let (data, _) = server2.recv().await.unwrap();
+    let received: Value = serde_json::from_slice(&data[12..]).unwrap();
+    assert_eq!(
+        json!({"stat": {"rxnb": 1, "lati": 52.374, "long": 4.8897, "alti": 12}}),
+        received
+    );
+
+    // Send PUSH_DATA with location.
+    let payload =
+        serde_json::to_vec(&json!({"stat": {"rxnb": 1, "lati": 1.0, "long": 2.0, "alti": 3}}))
+            .unwrap();
+    gw.push_data(0x0102, &payload).await.unwrap();
+
+    // Expect PUSH_ACK.
+    assert_eq!(vec![0x02, 0x01, 0x02, 0x01], gw.recv().await.unwrap());
+
+    // Expect the reported location is not overwritten.
+    let push_data = gw.packet(PacketType::PushData, 0x0102, &payload);
+    let (data, _) = server1.recv().await.unwrap();
+    assert_eq!(push_data, data);
+    let (data, _) = server2.recv().await.unwrap();
+    assert_eq!(push_data, data);
+
+    fs::remove_file(&metadata_file).unwrap();
+}
@@ -0,0 +1,59 @@
+use tracing_subscriber::prelude::*;
+
+use chirpstack_packet_multiplexer::config;
+use chirpstack_packet_multiplexer::packets::PacketType;
+use chirpstack_packet_multiplexer::testing::{self, FakeGateway, FakeServer};
+
+#[tokio::test]
+async fn test() {
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    // Server.
+    let server = FakeServer::new().await.unwrap();
+
+    let conf = config::Configuration {
+        multiplexer: config::Multiplexer {
+            bind: "127.0.0.1:0".into(),
+            binds: vec!["127.0.0.1:0".into()],
+            sockets_per_bind: 4,
+            servers: vec![server.config().unwrap()],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let addrs = testing::start(&conf).await.unwrap();
+
+    // Gateways, each using a different bind.
+    let gw1 = FakeGateway::new("0102030405060708".parse().unwrap(), addrs[0])
+        .await
+        .unwrap();
+    let gw2 = FakeGateway::new("0202030405060708".parse().unwrap(), addrs[1])
+        .await
+        .unwrap();
+
+    for gw in [&gw1, &gw2] {
+        // Send PULL_DATA.
+        gw.pull_data(0x0102).await.unwrap();
+
+        // Expect PULL_ACK.
+        assert_eq!(vec![0x02, 0x01, 0x02, 0x04], gw.recv().await.unwrap());
+
+        // Expect PULL_DATA forwarded to server.
+        let (data, addr) = server.recv().await.unwrap();
+        assert_eq!(gw.packet(PacketType::PullData, 0x0102, &[]), data);
+
+        // Send PULL_RESP from server.
+        server.pull_resp(0x0103, b"{}", addr).await.unwrap();
+
+        // Expect PULL_RESP at gateway, sent from the bind to which the gateway
+        // sent its PULL_DATA (the socket is connected, it would not receive
+        // packets from other addresses).
+        assert_eq!(
+            vec![0x02, 0x01, 0x03, 0x03, 0x7b, 0x7d],
+            gw.recv().await.unwrap()
+        );
+    }
+}
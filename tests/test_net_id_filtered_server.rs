@@ -0,0 +1,122 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use base64::{engine::general_purpose, Engine as _};
+use serde_json::{json, Value};
+use tracing_subscriber::prelude::*;
+
+use chirpstack_packet_multiplexer::config;
+use chirpstack_packet_multiplexer::packets::{NetId, PacketType};
+use chirpstack_packet_multiplexer::testing::{self, FakeGateway, FakeServer};
+use lrwn_filters::EuiPrefix;
+
+fn rxpk(phy_payload: &[u8]) -> Value {
+    json!({
+        "freq": 868.1,
+        "data": general_purpose::STANDARD.encode(phy_payload),
+    })
+}
+
+#[tokio::test]
+async fn test() {
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    // Servers.
+    let server1 = FakeServer::new().await.unwrap();
+    let server2 = FakeServer::new().await.unwrap();
+
+    let conf = config::Configuration {
+        multiplexer: config::Multiplexer {
+            bind: "127.0.0.1:0".into(),
+            servers: vec![
+                config::Server {
+                    net_ids: vec![NetId::from_str("000013").unwrap()],
+                    join_eui_prefixes: vec![EuiPrefix::from_str("0102030405060708/64").unwrap()],
+                    ..server1.config().unwrap()
+                },
+                config::Server {
+                    net_ids: vec![NetId::from_str("600001").unwrap()],
+                    ..server2.config().unwrap()
+                },
+            ],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let addrs = testing::start(&conf).await.unwrap();
+
+    // Gateway.
+    let gw = FakeGateway::new("0102030405060708".parse().unwrap(), addrs[0])
+        .await
+        .unwrap();
+
+    // DevAddr 26000013 (NetID 000013).
+    let rxpk_net_id_1 = rxpk(&[
+        0x40, 0x13, 0x00, 0x00, 0x26, 0x00, 0x01, 0x00, 0x01, 0x02, 0x03, 0x04,
+    ]);
+    // DevAddr e0020001 (NetID 600001).
+    let rxpk_net_id_2 = rxpk(&[
+        0x40, 0x01, 0x00, 0x02, 0xe0, 0x00, 0x01, 0x00, 0x01, 0x02, 0x03, 0x04,
+    ]);
+    // Join-request with JoinEUI 0102030405060708.
+    let rxpk_join = rxpk(&[
+        0x00, 0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+        0x01, 0x01, 0x01, 0x02, 0x01, 0x02, 0x03, 0x04,
+    ]);
+    let stat = json!({"rxnb": 3});
+
+    // Send PUSH_DATA.
+    let payload = json!({
+        "rxpk": [rxpk_net_id_1, rxpk_net_id_2, rxpk_join],
+        "stat": stat,
+    });
+    gw.push_data(0x0101, &serde_json::to_vec(&payload).unwrap())
+        .await
+        .unwrap();
+
+    // Expect PUSH_ACK.
+    assert_eq!(vec![0x02, 0x01, 0x01, 0x01], gw.recv().await.unwrap());
+
+    // Expect only the NetID 000013 uplink and join-request at server 1.
+    let (data, _) = server1.recv().await.unwrap();
+    assert_eq!(gw.packet(PacketType::PushData, 0x0101, &[]), &data[..12]);
+    let payload: Value = serde_json::from_slice(&data[12..]).unwrap();
+    assert_eq!(
+        json!({
+            "rxpk": [rxpk_net_id_1, rxpk_join],
+            "stat": stat,
+        }),
+        payload
+    );
+
+    // Expect only the NetID 600001 uplink and join-request at server 2.
+    let (data, _) = server2.recv().await.unwrap();
+    let payload: Value = serde_json::from_slice(&data[12..]).unwrap();
+    assert_eq!(
+        json!({
+            "rxpk": [rxpk_net_id_2, rxpk_join],
+            "stat": stat,
+        }),
+        payload
+    );
+
+    // Send PUSH_DATA without stat, only containing the NetID 600001 uplink.
+    let payload = serde_json::to_vec(&json!({"rxpk": [rxpk_net_id_2]})).unwrap();
+    gw.push_data(0x0102, &payload).await.unwrap();
+
+    // Expect PUSH_ACK.
+    assert_eq!(vec![0x02, 0x01, 0x02, 0x01], gw.recv().await.unwrap());
+
+    // Expect PUSH_DATA forwarded as-is to server 2.
+    let (data, _) = server2.recv().await.unwrap();
+    assert_eq!(gw.packet(PacketType::PushData, 0x0102, &payload), data);
+
+    // Expect nothing forwarded to server 1.
+    server1
+        .expect_none(Duration::from_millis(100))
+        .await
+        .unwrap();
+}
@@ -1,7 +1,7 @@
-use tokio::net::UdpSocket;
 use tracing_subscriber::prelude::*;
 
-use chirpstack_packet_multiplexer::{config, forwarder, listener};
+use chirpstack_packet_multiplexer::config;
+use chirpstack_packet_multiplexer::testing::{self, FakeGateway};
 
 #[tokio::test]
 async fn test() {
@@ -11,41 +11,26 @@ async fn test() {
 
     let conf = config::Configuration {
         multiplexer: config::Multiplexer {
-            bind: "0.0.0.0:1710".into(),
+            bind: "127.0.0.1:0".into(),
             ..Default::default()
         },
         ..Default::default()
     };
-    let (downlink_tx, uplink_rx) = listener::setup(&conf.multiplexer.bind).await.unwrap();
-    forwarder::setup(downlink_tx, uplink_rx, conf.multiplexer.servers.clone())
+    let addrs = testing::start(&conf).await.unwrap();
+
+    let gw = FakeGateway::new("0102030405060708".parse().unwrap(), addrs[0])
         .await
         .unwrap();
 
-    let gw_sock = UdpSocket::bind("0.0.0.0:0").await.unwrap();
-    gw_sock.connect("localhost:1710").await.unwrap();
-    let mut buffer: [u8; 65535] = [0; 65535];
-
     // Send PUSH_DATA.
-    gw_sock
-        .send(&[
-            0x02, 0x01, 0x02, 0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x7b, 0x7d,
-        ])
-        .await
-        .unwrap();
+    gw.push_data(0x0102, b"{}").await.unwrap();
 
     // Expect PUSH_ACK.
-    let size = gw_sock.recv(&mut buffer).await.unwrap();
-    assert_eq!(&[0x02, 0x01, 0x02, 0x01], &buffer[..size]);
+    assert_eq!(vec![0x02, 0x01, 0x02, 0x01], gw.recv().await.unwrap());
 
     // Send PULL_DATA.
-    gw_sock
-        .send(&[
-            0x02, 0x01, 0x02, 0x02, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
-        ])
-        .await
-        .unwrap();
+    gw.pull_data(0x0102).await.unwrap();
 
     // Expect PULL_ACK.
-    let size = gw_sock.recv(&mut buffer).await.unwrap();
-    assert_eq!(&[0x02, 0x01, 0x02, 0x04], &buffer[..size]);
+    assert_eq!(vec![0x02, 0x01, 0x02, 0x04], gw.recv().await.unwrap());
 }
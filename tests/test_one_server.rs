@@ -1,10 +1,10 @@
 use std::time::Duration;
 
-use tokio::net::UdpSocket;
-use tokio::time::timeout;
 use tracing_subscriber::prelude::*;
 
-use chirpstack_packet_multiplexer::{config, forwarder, listener};
+use chirpstack_packet_multiplexer::config;
+use chirpstack_packet_multiplexer::packets::PacketType;
+use chirpstack_packet_multiplexer::testing::{self, FakeGateway, FakeServer};
 
 #[tokio::test]
 async fn test() {
@@ -12,105 +12,69 @@ async fn test() {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
+    // Server.
+    let server = FakeServer::new().await.unwrap();
+
     let conf = config::Configuration {
         multiplexer: config::Multiplexer {
-            bind: "0.0.0.0:1710".into(),
-            servers: vec![config::Server {
-                server: "localhost:1711".into(),
-                ..Default::default()
-            }],
+            bind: "127.0.0.1:0".into(),
+            servers: vec![server.config().unwrap()],
+            ..Default::default()
         },
         ..Default::default()
     };
 
-    let (downlink_tx, uplink_rx) = listener::setup(&conf.multiplexer.bind).await.unwrap();
-    forwarder::setup(downlink_tx, uplink_rx, conf.multiplexer.servers.clone())
+    let addrs = testing::start(&conf).await.unwrap();
+
+    // Gateway.
+    let gw = FakeGateway::new("0102030405060708".parse().unwrap(), addrs[0])
         .await
         .unwrap();
-    let mut buffer: [u8; 65535] = [0; 65535];
-
-    // Server socket.
-    let server_sock = UdpSocket::bind("0.0.0.0:1711").await.unwrap();
-
-    // Gateway socket.
-    let gw_sock = UdpSocket::bind("0.0.0.0:0").await.unwrap();
-    gw_sock.connect("localhost:1710").await.unwrap();
 
     // Send PUSH_DATA.
-    gw_sock
-        .send(&[
-            0x02, 0x01, 0x02, 0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x7b, 0x7d,
-        ])
-        .await
-        .unwrap();
+    gw.push_data(0x0102, b"{}").await.unwrap();
 
     // Expect PUSH_ACK.
-    let size = gw_sock.recv(&mut buffer).await.unwrap();
-    assert_eq!(&[0x02, 0x01, 0x02, 0x01], &buffer[..size]);
+    assert_eq!(vec![0x02, 0x01, 0x02, 0x01], gw.recv().await.unwrap());
 
     // Expect PUSH_DATA forwarded to server.
-    let size = server_sock.recv(&mut buffer).await.unwrap();
-    assert_eq!(
-        &[0x02, 0x01, 0x02, 0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x7b, 0x7d,],
-        &buffer[..size]
-    );
+    let (data, _) = server.recv().await.unwrap();
+    assert_eq!(gw.packet(PacketType::PushData, 0x0102, b"{}"), data);
 
     // Send PULL_DATA.
-    gw_sock
-        .send(&[
-            0x02, 0x01, 0x02, 0x02, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
-        ])
-        .await
-        .unwrap();
+    gw.pull_data(0x0102).await.unwrap();
 
     // Expect PULL_ACK.
-    let size = gw_sock.recv(&mut buffer).await.unwrap();
-    assert_eq!(&[0x02, 0x01, 0x02, 0x04], &buffer[..size]);
+    assert_eq!(vec![0x02, 0x01, 0x02, 0x04], gw.recv().await.unwrap());
 
-    // Expect PULL_ACK forwarded to server.
-    let (size, addr) = server_sock.recv_from(&mut buffer).await.unwrap();
-    assert_eq!(
-        &[0x02, 0x01, 0x02, 0x02, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,],
-        &buffer[..size]
-    );
+    // Expect PULL_DATA forwarded to server.
+    let (data, addr) = server.recv().await.unwrap();
+    assert_eq!(gw.packet(PacketType::PullData, 0x0102, &[]), data);
 
     // Send TX_ACK from gateway.
     // We want to test here that if the token is not associated with the server
     // socket, it is not forwarded to the server.
-    // Send TX_ACK from gateway.
-    gw_sock
-        .send(&[
-            0x02, 0x01, 0x02, 0x05, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
-        ])
-        .await
-        .unwrap();
+    gw.tx_ack(0x0102, &[]).await.unwrap();
 
     // Expect receiving at server to timeout.
-    let resp = timeout(Duration::from_millis(100), server_sock.recv(&mut buffer)).await;
-    assert!(resp.is_err());
-
-    // Send PULL_RESP from server.
-    server_sock
-        .send_to(&[0x02, 0x01, 0x02, 0x03, 0x7b, 0x7d], addr)
+    server
+        .expect_none(Duration::from_millis(100))
         .await
         .unwrap();
 
+    // Send PULL_RESP from server.
+    server.pull_resp(0x0102, b"{}", addr).await.unwrap();
+
     // Expect PULL_RESP at gateway.
-    let size = gw_sock.recv(&mut buffer).await.unwrap();
-    assert_eq!(&[0x02, 0x01, 0x02, 0x03, 0x7b, 0x7d,], &buffer[..size]);
+    assert_eq!(
+        vec![0x02, 0x01, 0x02, 0x03, 0x7b, 0x7d],
+        gw.recv().await.unwrap()
+    );
 
     // Send TX_ACK from gateway.
-    gw_sock
-        .send(&[
-            0x02, 0x01, 0x02, 0x05, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
-        ])
-        .await
-        .unwrap();
+    gw.tx_ack(0x0102, &[]).await.unwrap();
 
     // Expect TX_ACK at server.
-    let size = server_sock.recv(&mut buffer).await.unwrap();
-    assert_eq!(
-        &[0x02, 0x01, 0x02, 0x05, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08],
-        &buffer[..size]
-    );
+    let (data, _) = server.recv().await.unwrap();
+    assert_eq!(gw.packet(PacketType::TxAck, 0x0102, &[]), data);
 }
@@ -0,0 +1,116 @@
+use serde_json::{json, Value};
+use tracing_subscriber::prelude::*;
+
+use chirpstack_packet_multiplexer::config;
+use chirpstack_packet_multiplexer::packets::PacketType;
+use chirpstack_packet_multiplexer::testing::{self, FakeGateway, FakeServer};
+
+#[tokio::test]
+async fn test() {
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    // Servers.
+    let server1 = FakeServer::new().await.unwrap();
+    let server2 = FakeServer::new().await.unwrap();
+    let server3 = FakeServer::new().await.unwrap();
+
+    let conf = config::Configuration {
+        multiplexer: config::Multiplexer {
+            bind: "127.0.0.1:0".into(),
+            servers: vec![
+                server1.config().unwrap(),
+                config::Server {
+                    privacy: config::Privacy {
+                        location_decimals: Some(2),
+                        remove_tmms: true,
+                        remove_ftime: true,
+                        rssi_step: Some(5),
+                        snr_step: Some(2.5),
+                        ..Default::default()
+                    },
+                    ..server2.config().unwrap()
+                },
+                config::Server {
+                    privacy: config::Privacy {
+                        remove_location: true,
+                        remove_rsig: true,
+                        ..Default::default()
+                    },
+                    ..server3.config().unwrap()
+                },
+            ],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let addrs = testing::start(&conf).await.unwrap();
+
+    // Gateway.
+    let gw = FakeGateway::new("0102030405060708".parse().unwrap(), addrs[0])
+        .await
+        .unwrap();
+
+    let payload = json!({
+        "rxpk": [{
+            "tmst": 1000,
+            "tmms": 1234567890,
+            "ftime": 12345,
+            "etime": "AAECAwQFBgc=",
+            "rssi": -82,
+            "lsnr": 6.3,
+            "rsig": [{"ant": 0, "chan": 1, "rssic": -83, "lsnr": 6.3, "ftime": 12345, "etime": "AAECAwQFBgc="}],
+            "data": "",
+        }],
+        "stat": {"lati": 52.37403, "long": 4.88969, "alti": 12, "rxnb": 1},
+    });
+
+    // Send PUSH_DATA.
+    let payload = serde_json::to_vec(&payload).unwrap();
+    gw.push_data(0x0101, &payload).await.unwrap();
+
+    // Expect PUSH_ACK.
+    assert_eq!(vec![0x02, 0x01, 0x01, 0x01], gw.recv().await.unwrap());
+
+    // Expect PUSH_DATA forwarded as-is to server 1.
+    let (data, _) = server1.recv().await.unwrap();
+    assert_eq!(gw.packet(PacketType::PushData, 0x0101, &payload), data);
+
+    // Expect coarsened metadata at server 2.
+    let (data, _) = server2.recv().await.unwrap();
+    let received: Value = serde_json::from_slice(&data[12..]).unwrap();
+    assert_eq!(
+        json!({
+            "rxpk": [{
+                "tmst": 1000,
+                "rssi": -80,
+                "lsnr": 7.5,
+                "rsig": [{"ant": 0, "chan": 1, "rssic": -85, "lsnr": 7.5}],
+                "data": "",
+            }],
+            "stat": {"lati": 52.37, "long": 4.89, "alti": 12, "rxnb": 1},
+        }),
+        received
+    );
+
+    // Expect removed location and rsig at server 3.
+    let (data, _) = server3.recv().await.unwrap();
+    let received: Value = serde_json::from_slice(&data[12..]).unwrap();
+    assert_eq!(
+        json!({
+            "rxpk": [{
+                "tmst": 1000,
+                "tmms": 1234567890,
+                "ftime": 12345,
+                "etime": "AAECAwQFBgc=",
+                "rssi": -82,
+                "lsnr": 6.3,
+                "data": "",
+            }],
+            "stat": {"rxnb": 1},
+        }),
+        received
+    );
+}
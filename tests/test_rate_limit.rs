@@ -0,0 +1,116 @@
+use std::time::Duration;
+
+use tracing_subscriber::prelude::*;
+
+use chirpstack_packet_multiplexer::packets::PacketType;
+use chirpstack_packet_multiplexer::testing::{self, FakeGateway, FakeServer};
+use chirpstack_packet_multiplexer::{config, ratelimit};
+
+#[tokio::test]
+async fn test() {
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    // Servers.
+    let server1 = FakeServer::new().await.unwrap();
+    let server2 = FakeServer::new().await.unwrap();
+
+    let conf = config::Configuration {
+        multiplexer: config::Multiplexer {
+            bind: "127.0.0.1:0".into(),
+            servers: vec![
+                server1.config().unwrap(),
+                config::Server {
+                    rate_limit: config::RateLimit {
+                        packets_per_second: 1,
+                        ..Default::default()
+                    },
+                    ..server2.config().unwrap()
+                },
+            ],
+            ..Default::default()
+        },
+        gateways: config::Gateways {
+            rate_limit: config::RateLimit {
+                packets_per_second: 1,
+                packet_burst: 3,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let addrs = testing::start(&conf).await.unwrap();
+
+    // Gateway.
+    let gw = FakeGateway::new("0102030405060708".parse().unwrap(), addrs[0])
+        .await
+        .unwrap();
+
+    // Send 4 PUSH_DATA, of which the last exceeds the gateway burst.
+    for token in 0x0101..=0x0104 {
+        gw.push_data(token, b"{}").await.unwrap();
+    }
+
+    // Expect 3 PUSH_ACKs.
+    for token in 0x01..=0x03 {
+        assert_eq!(vec![0x02, 0x01, token, 0x01], gw.recv().await.unwrap());
+    }
+    gw.expect_none(Duration::from_millis(100)).await.unwrap();
+
+    // PULL_DATA is not rate limited, expect PULL_ACK.
+    gw.pull_data(0x0105).await.unwrap();
+    assert_eq!(vec![0x02, 0x01, 0x05, 0x04], gw.recv().await.unwrap());
+
+    // Expect 3 PUSH_DATA and the PULL_DATA at server 1.
+    for token in 0x0101..=0x0103 {
+        let (data, _) = server1.recv().await.unwrap();
+        assert_eq!(gw.packet(PacketType::PushData, token, b"{}"), data);
+    }
+    let (data, _) = server1.recv().await.unwrap();
+    assert_eq!(gw.packet(PacketType::PullData, 0x0105, &[]), data);
+    server1
+        .expect_none(Duration::from_millis(100))
+        .await
+        .unwrap();
+
+    // Expect only the first PUSH_DATA and the PULL_DATA at server 2.
+    let (data, _) = server2.recv().await.unwrap();
+    assert_eq!(gw.packet(PacketType::PushData, 0x0101, b"{}"), data);
+    let (data, _) = server2.recv().await.unwrap();
+    assert_eq!(gw.packet(PacketType::PullData, 0x0105, &[]), data);
+    server2
+        .expect_none(Duration::from_millis(100))
+        .await
+        .unwrap();
+
+    // Without byte burst, it defaults to at least the max. packet size.
+    assert!(ratelimit::check(&config::RateLimit {
+        bytes_per_second: 1000,
+        ..Default::default()
+    })
+    .is_ok());
+    let mut limiter = ratelimit::RateLimiter::new(&config::RateLimit {
+        bytes_per_second: 1000,
+        ..Default::default()
+    })
+    .unwrap();
+    assert!(limiter.allow(65535));
+    assert!(!limiter.allow(1000));
+
+    // A configured byte burst smaller than the max. packet size is rejected.
+    assert!(ratelimit::check(&config::RateLimit {
+        bytes_per_second: 1000,
+        byte_burst: 1000,
+        ..Default::default()
+    })
+    .is_err());
+    assert!(ratelimit::check(&config::RateLimit {
+        bytes_per_second: 1000,
+        byte_burst: 65535,
+        ..Default::default()
+    })
+    .is_ok());
+}
@@ -0,0 +1,133 @@
+use std::env;
+use std::fs;
+use std::time::Duration;
+
+use tokio::time::Instant;
+use tracing_subscriber::prelude::*;
+
+use chirpstack_packet_multiplexer::cmd::replay;
+use chirpstack_packet_multiplexer::packets::{GatewayId, PacketType};
+use chirpstack_packet_multiplexer::testing::{self, FakeServer};
+
+// Returns a pcap record (LINKTYPE_RAW) containing the datagram with IPv4 and
+// UDP headers.
+fn pcap_record(time_us: u32, dst_port: u16, data: &[u8]) -> Vec<u8> {
+    let udp_len = 8 + data.len() as u16;
+    let mut packet = vec![0x45, 0x00];
+    packet.extend_from_slice(&(20 + udp_len).to_be_bytes());
+    packet.extend_from_slice(&[0x00, 0x00, 0x40, 0x00, 64, 17, 0x00, 0x00]);
+    packet.extend_from_slice(&[127, 0, 0, 1, 127, 0, 0, 1]);
+    packet.extend_from_slice(&1234u16.to_be_bytes());
+    packet.extend_from_slice(&dst_port.to_be_bytes());
+    packet.extend_from_slice(&udp_len.to_be_bytes());
+    packet.extend_from_slice(&[0x00, 0x00]);
+    packet.extend_from_slice(data);
+
+    let mut b = Vec::new();
+    b.extend_from_slice(&1700000000u32.to_le_bytes());
+    b.extend_from_slice(&time_us.to_le_bytes());
+    b.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+    b.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+    b.extend_from_slice(&packet);
+    b
+}
+
+#[tokio::test]
+async fn test() {
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    // Server.
+    let server = FakeServer::new().await.unwrap();
+
+    let gateway_id: GatewayId = "0102030405060708".parse().unwrap();
+    let rewritten_id: GatewayId = "0a0b030405060708".parse().unwrap();
+    let push_data_1 = testing::packet(PacketType::PushData, 0x0102, Some(gateway_id), b"{}");
+    let push_data_2 = testing::packet(PacketType::PushData, 0x0103, Some(gateway_id), b"{}");
+
+    // Capture containing two PUSH_DATA sent to port 1700 (gateway side),
+    // 200ms apart, and a copy of the first sent to port 1800 (server side).
+    let mut pcap = Vec::new();
+    pcap.extend_from_slice(&0xa1b2c3d4u32.to_le_bytes());
+    pcap.extend_from_slice(&[0x02, 0x00, 0x04, 0x00]);
+    pcap.extend_from_slice(&[0; 8]);
+    pcap.extend_from_slice(&65535u32.to_le_bytes());
+    pcap.extend_from_slice(&101u32.to_le_bytes());
+    pcap.extend(pcap_record(0, 1700, &push_data_1));
+    pcap.extend(pcap_record(1000, 1800, &push_data_1));
+    pcap.extend(pcap_record(200000, 1700, &push_data_2));
+
+    let file = env::temp_dir().join("chirpstack-packet-multiplexer-test-replay.pcap");
+    fs::write(&file, &pcap).unwrap();
+
+    // Without port filter, the server side copy would be replayed as well.
+    let opts = replay::Options {
+        file: file.to_string_lossy().to_string(),
+        target: server.addr().unwrap().to_string(),
+        speed: 2.0,
+        port: None,
+        dst_ip: None,
+        gateway_id_prefix_rewrite: None,
+    };
+    assert!(replay::run(&opts).await.is_err());
+
+    let opts = replay::Options {
+        file: file.to_string_lossy().to_string(),
+        target: server.addr().unwrap().to_string(),
+        speed: 2.0,
+        port: Some(1700),
+        dst_ip: None,
+        gateway_id_prefix_rewrite: Some("0a0b000000000000/16".parse().unwrap()),
+    };
+    let replay_task = tokio::spawn(async move { replay::run(&opts).await.unwrap() });
+
+    // Expect the first PUSH_DATA with the rewritten Gateway ID, respond with
+    // a PUSH_ACK.
+    let (data, addr) = server.recv().await.unwrap();
+    let received_at = Instant::now();
+    assert_eq!(
+        testing::packet(PacketType::PushData, 0x0102, Some(rewritten_id), b"{}"),
+        data
+    );
+    server.ack(&data, addr).await.unwrap();
+
+    // Expect the second PUSH_DATA after ~100ms (speed-up factor 2), from the
+    // same address.
+    let (data, addr_2) = server.recv().await.unwrap();
+    assert_eq!(
+        testing::packet(PacketType::PushData, 0x0103, Some(rewritten_id), b"{}"),
+        data
+    );
+    assert_eq!(addr, addr_2);
+    let elapsed = received_at.elapsed();
+    assert!(elapsed >= Duration::from_millis(80) && elapsed < Duration::from_millis(180));
+
+    let summary = replay_task.await.unwrap();
+    assert_eq!(
+        replay::Summary {
+            gateways: 1,
+            sent: 2,
+            received: 1,
+        },
+        summary
+    );
+
+    // NDJSON capture.
+    let ndjson = "{\"time\":1700000000.0,\"data\":\"AgECAAECAwQFBgcIe30=\"}\n";
+    fs::write(&file, ndjson).unwrap();
+    let opts = replay::Options {
+        file: file.to_string_lossy().to_string(),
+        target: server.addr().unwrap().to_string(),
+        speed: 1.0,
+        port: None,
+        dst_ip: None,
+        gateway_id_prefix_rewrite: None,
+    };
+    let replay_task = tokio::spawn(async move { replay::run(&opts).await.unwrap() });
+    let (data, _) = server.recv().await.unwrap();
+    assert_eq!(push_data_1, data);
+    assert_eq!(1, replay_task.await.unwrap().sent);
+
+    fs::remove_file(&file).unwrap();
+}
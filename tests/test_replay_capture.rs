@@ -0,0 +1,85 @@
+use std::env;
+use std::time::Duration;
+
+use tokio::net::UdpSocket;
+use tokio::time::{sleep, timeout};
+use tracing_subscriber::prelude::*;
+
+use chirpstack_packet_multiplexer::cmd::replay;
+use chirpstack_packet_multiplexer::packets::PacketType;
+use chirpstack_packet_multiplexer::testing::{self, FakeGateway};
+use chirpstack_packet_multiplexer::{capture, config};
+
+#[tokio::test]
+async fn test() {
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    let file = env::temp_dir().join("chirpstack-packet-multiplexer-test-replay-capture.pcap");
+    let mut buffer: [u8; 65535] = [0; 65535];
+
+    // Server, the multiplexer is bound to the same port on a different
+    // address, such that the gateway and server side traffic in the capture
+    // only differ in the destination address.
+    let server_sock = UdpSocket::bind("127.0.0.2:0").await.unwrap();
+    let port = server_sock.local_addr().unwrap().port();
+
+    let conf = config::Configuration {
+        multiplexer: config::Multiplexer {
+            bind: format!("127.0.0.1:{}", port),
+            servers: vec![config::Server {
+                server: server_sock.local_addr().unwrap().to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        },
+        capture: config::Capture {
+            file: file.to_string_lossy().to_string(),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    capture::setup(&conf.capture).await.unwrap();
+    let addrs = testing::start(&conf).await.unwrap();
+
+    // Send PUSH_DATA, expect PUSH_ACK and PUSH_DATA at server.
+    let gw = FakeGateway::new("0102030405060708".parse().unwrap(), addrs[0])
+        .await
+        .unwrap();
+    gw.push_data(0x0102, b"{}").await.unwrap();
+    assert_eq!(vec![0x02, 0x01, 0x02, 0x01], gw.recv().await.unwrap());
+    let size = timeout(testing::RECV_TIMEOUT, server_sock.recv(&mut buffer))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        gw.packet(PacketType::PushData, 0x0102, b"{}"),
+        &buffer[..size]
+    );
+
+    // Wait for the capture to be written.
+    sleep(Duration::from_millis(200)).await;
+
+    // The capture contains the PUSH_DATA twice (gateway and server side),
+    // filtering on the port only replays both.
+    let target = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let opts = replay::Options {
+        file: file.to_string_lossy().to_string(),
+        target: target.local_addr().unwrap().to_string(),
+        speed: 1.0,
+        port: Some(port),
+        dst_ip: None,
+        gateway_id_prefix_rewrite: None,
+    };
+    assert_eq!(2, replay::run(&opts).await.unwrap().sent);
+
+    // Filtering on the multiplexer address only replays the gateway side
+    // PUSH_DATA.
+    let opts = replay::Options {
+        dst_ip: Some(addrs[0].ip()),
+        ..opts
+    };
+    assert_eq!(1, replay::run(&opts).await.unwrap().sent);
+}
@@ -0,0 +1,97 @@
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use tracing_subscriber::prelude::*;
+
+use chirpstack_packet_multiplexer::config;
+use chirpstack_packet_multiplexer::packets::PacketType;
+use chirpstack_packet_multiplexer::testing::{self, FakeGateway, FakeServer};
+
+#[tokio::test]
+async fn test() {
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    // Servers.
+    let server1 = FakeServer::new().await.unwrap();
+    let server2 = FakeServer::new().await.unwrap();
+
+    let conf = config::Configuration {
+        multiplexer: config::Multiplexer {
+            bind: "127.0.0.1:0".into(),
+            servers: vec![
+                server1.config().unwrap(),
+                config::Server {
+                    rxpk_filters: config::RxpkFilters {
+                        crc_status: vec![1],
+                        min_rssi: Some(-120),
+                        frequency_ranges: vec![(902300000, 903700000)],
+                        spreading_factors: vec![7, 8, 9, 10],
+                        drop_stat_only: true,
+                        ..Default::default()
+                    },
+                    ..server2.config().unwrap()
+                },
+            ],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let addrs = testing::start(&conf).await.unwrap();
+
+    // Gateway.
+    let gw = FakeGateway::new("0102030405060708".parse().unwrap(), addrs[0])
+        .await
+        .unwrap();
+
+    let rxpk_ok = json!({"stat": 1, "freq": 902.3, "rssi": -80, "datr": "SF7BW125", "data": ""});
+    let rxpk_crc_error =
+        json!({"stat": -1, "freq": 902.3, "rssi": -80, "datr": "SF7BW125", "data": ""});
+    let rxpk_low_rssi =
+        json!({"stat": 1, "freq": 902.3, "rssi": -121, "datr": "SF7BW125", "data": ""});
+    let rxpk_other_freq =
+        json!({"stat": 1, "freq": 904.3, "rssi": -80, "datr": "SF7BW125", "data": ""});
+    let rxpk_other_sf =
+        json!({"stat": 1, "freq": 903.7, "rssi": -80, "datr": "SF12BW125", "data": ""});
+    let stat = json!({"rxnb": 5});
+
+    let payload = json!({
+        "rxpk": [rxpk_ok, rxpk_crc_error, rxpk_low_rssi, rxpk_other_freq, rxpk_other_sf],
+        "stat": stat,
+    });
+
+    // Send PUSH_DATA.
+    let payload = serde_json::to_vec(&payload).unwrap();
+    gw.push_data(0x0101, &payload).await.unwrap();
+
+    // Expect PUSH_ACK.
+    assert_eq!(vec![0x02, 0x01, 0x01, 0x01], gw.recv().await.unwrap());
+
+    // Expect PUSH_DATA forwarded as-is to server 1.
+    let (data, _) = server1.recv().await.unwrap();
+    assert_eq!(gw.packet(PacketType::PushData, 0x0101, &payload), data);
+
+    // Expect only the matching rxpk at server 2.
+    let (data, _) = server2.recv().await.unwrap();
+    let received: Value = serde_json::from_slice(&data[12..]).unwrap();
+    assert_eq!(json!({"rxpk": [rxpk_ok], "stat": stat}), received);
+
+    // Send stat-only PUSH_DATA.
+    let payload = serde_json::to_vec(&json!({"stat": stat})).unwrap();
+    gw.push_data(0x0102, &payload).await.unwrap();
+
+    // Expect PUSH_ACK.
+    assert_eq!(vec![0x02, 0x01, 0x02, 0x01], gw.recv().await.unwrap());
+
+    // Expect PUSH_DATA forwarded to server 1.
+    let (data, _) = server1.recv().await.unwrap();
+    assert_eq!(gw.packet(PacketType::PushData, 0x0102, &payload), data);
+
+    // Expect nothing forwarded to server 2.
+    server2
+        .expect_none(Duration::from_millis(100))
+        .await
+        .unwrap();
+}
@@ -0,0 +1,82 @@
+use tracing_subscriber::prelude::*;
+
+use chirpstack_packet_multiplexer::config;
+use chirpstack_packet_multiplexer::packets::PacketType;
+use chirpstack_packet_multiplexer::testing::{self, FakeGateway, FakeServer};
+
+#[tokio::test]
+async fn test() {
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    // Servers, for the uplink and downlink port.
+    let server_up = FakeServer::new().await.unwrap();
+    let server_down = FakeServer::new().await.unwrap();
+
+    let conf = config::Configuration {
+        multiplexer: config::Multiplexer {
+            bind: "127.0.0.1:0".into(),
+            bind_down: "127.0.0.1:0".into(),
+            servers: vec![config::Server {
+                server: "127.0.0.1:1700".into(),
+                port_up: server_up.addr().unwrap().port(),
+                port_down: server_down.addr().unwrap().port(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let addrs = testing::start(&conf).await.unwrap();
+
+    // Gateway, using separate sockets for the uplink and downlink bind.
+    let gw_up = FakeGateway::new("0102030405060708".parse().unwrap(), addrs[0])
+        .await
+        .unwrap();
+    let gw_down = FakeGateway::new("0102030405060708".parse().unwrap(), addrs[1])
+        .await
+        .unwrap();
+
+    // Send PUSH_DATA.
+    gw_up.push_data(0x0102, b"{}").await.unwrap();
+
+    // Expect PUSH_ACK.
+    assert_eq!(vec![0x02, 0x01, 0x02, 0x01], gw_up.recv().await.unwrap());
+
+    // Expect PUSH_DATA forwarded to server uplink port.
+    let (data, up_addr) = server_up.recv().await.unwrap();
+    assert_eq!(gw_up.packet(PacketType::PushData, 0x0102, b"{}"), data);
+
+    // Send PULL_DATA.
+    gw_down.pull_data(0x0103).await.unwrap();
+
+    // Expect PULL_ACK.
+    assert_eq!(vec![0x02, 0x01, 0x03, 0x04], gw_down.recv().await.unwrap());
+
+    // Expect PULL_DATA forwarded to server downlink port, using a different
+    // socket than for uplink.
+    let (data, down_addr) = server_down.recv().await.unwrap();
+    assert_eq!(gw_down.packet(PacketType::PullData, 0x0103, &[]), data);
+    assert_ne!(up_addr, down_addr);
+
+    // Send PULL_RESP from server.
+    server_down
+        .pull_resp(0x0104, b"{}", down_addr)
+        .await
+        .unwrap();
+
+    // Expect PULL_RESP at gateway downlink socket.
+    assert_eq!(
+        vec![0x02, 0x01, 0x04, 0x03, 0x7b, 0x7d],
+        gw_down.recv().await.unwrap()
+    );
+
+    // Send TX_ACK from gateway.
+    gw_down.tx_ack(0x0104, &[]).await.unwrap();
+
+    // Expect TX_ACK at server downlink port.
+    let (data, _) = server_down.recv().await.unwrap();
+    assert_eq!(gw_down.packet(PacketType::TxAck, 0x0104, &[]), data);
+}
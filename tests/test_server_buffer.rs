@@ -0,0 +1,100 @@
+use std::fs;
+use std::time::Duration;
+
+use tokio::time::sleep;
+use tracing_subscriber::prelude::*;
+
+use chirpstack_packet_multiplexer::config;
+use chirpstack_packet_multiplexer::packets::PacketType;
+use chirpstack_packet_multiplexer::testing::{self, FakeGateway, FakeServer};
+
+#[tokio::test]
+async fn test() {
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    let spill_file = std::env::temp_dir().join("test_server_buffer.bin");
+    let _ = fs::remove_file(&spill_file);
+
+    // Server.
+    let server = FakeServer::new().await.unwrap();
+
+    let conf = config::Configuration {
+        multiplexer: config::Multiplexer {
+            bind: "127.0.0.1:0".into(),
+            servers: vec![config::Server {
+                buffer: config::Buffer {
+                    max_packets: 10,
+                    max_memory_packets: 1,
+                    spill_file: spill_file.to_str().unwrap().into(),
+                    ack_timeout_ms: 100,
+                    replay_packets_per_second: 100,
+                    ..Default::default()
+                },
+                ..server.config().unwrap()
+            }],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let addrs = testing::start(&conf).await.unwrap();
+
+    // Gateway.
+    let gw = FakeGateway::new("0102030405060708".parse().unwrap(), addrs[0])
+        .await
+        .unwrap();
+
+    // Send 3 PUSH_DATA.
+    for token in 1..=3 {
+        gw.push_data(token, b"{}").await.unwrap();
+
+        // Expect PUSH_ACK.
+        assert_eq!(
+            vec![0x02, 0x00, token as u8, 0x01],
+            gw.recv().await.unwrap()
+        );
+    }
+
+    // Expect PUSH_DATA at server, which is not acknowledged.
+    let mut addr = None;
+    for token in 1..=3 {
+        let (data, a) = server.recv().await.unwrap();
+        assert_eq!(gw.packet(PacketType::PushData, token, b"{}"), data);
+        addr = Some(a);
+    }
+    let addr = addr.unwrap();
+
+    // Expect the PUSH_DATA to be buffered (and spilled to disk) after the ACK
+    // timeout, without being replayed.
+    sleep(Duration::from_millis(300)).await;
+    assert!(fs::metadata(&spill_file).unwrap().len() > 0);
+    server
+        .expect_none(Duration::from_millis(100))
+        .await
+        .unwrap();
+
+    // Send PUSH_ACK from server.
+    server
+        .ack(&gw.packet(PacketType::PushData, 3, b"{}"), addr)
+        .await
+        .unwrap();
+
+    // Expect buffered PUSH_DATA to be replayed in order.
+    for token in 1..=3 {
+        let (data, _) = server.recv().await.unwrap();
+        assert_eq!(gw.packet(PacketType::PushData, token, b"{}"), data);
+
+        server.ack(&data, addr).await.unwrap();
+    }
+
+    // Expect nothing else as the replayed PUSH_DATA are acknowledged.
+    server
+        .expect_none(Duration::from_millis(300))
+        .await
+        .unwrap();
+    assert_eq!(0, fs::metadata(&spill_file).unwrap().len());
+
+    fs::remove_file(&spill_file).unwrap();
+}
@@ -0,0 +1,78 @@
+use std::time::Duration;
+
+use tokio::net::UdpSocket;
+use tokio::time::{sleep, timeout};
+use tracing_subscriber::prelude::*;
+
+use chirpstack_packet_multiplexer::config;
+use chirpstack_packet_multiplexer::packets::PacketType;
+use chirpstack_packet_multiplexer::testing::{self, FakeGateway, FakeServer};
+
+#[tokio::test]
+async fn test() {
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    // Server.
+    let server = FakeServer::new().await.unwrap();
+    let server_addr = server.addr().unwrap();
+
+    let conf = config::Configuration {
+        multiplexer: config::Multiplexer {
+            bind: "127.0.0.1:0".into(),
+            servers: vec![server.config().unwrap()],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let addrs = testing::start(&conf).await.unwrap();
+
+    // Gateway.
+    let gw = FakeGateway::new("0102030405060708".parse().unwrap(), addrs[0])
+        .await
+        .unwrap();
+
+    // Send PULL_DATA, expect PULL_ACK and PULL_DATA at server.
+    gw.pull_data(0x0101).await.unwrap();
+    assert_eq!(vec![0x02, 0x01, 0x01, 0x04], gw.recv().await.unwrap());
+    let (data, addr) = server.recv().await.unwrap();
+    assert_eq!(gw.packet(PacketType::PullData, 0x0101, &[]), data);
+
+    // Close the server port and send PUSH_DATA. This results in a pending
+    // connection refused error (ICMP port unreachable) on the forwarder
+    // socket.
+    drop(server);
+    gw.push_data(0x0102, b"{}").await.unwrap();
+    assert_eq!(vec![0x02, 0x01, 0x02, 0x01], gw.recv().await.unwrap());
+    sleep(Duration::from_millis(100)).await;
+
+    // Re-bind the server port.
+    let server_sock = UdpSocket::bind(server_addr).await.unwrap();
+    let mut buffer: [u8; 65535] = [0; 65535];
+
+    // Send PULL_RESP from server, the forwarder socket first receives the
+    // pending error. Expect the PULL_RESP at the gateway, as the forwarder
+    // socket keeps receiving.
+    server_sock
+        .send_to(&[0x02, 0x01, 0x03, 0x03, 0x7b, 0x7d], addr)
+        .await
+        .unwrap();
+    assert_eq!(
+        vec![0x02, 0x01, 0x03, 0x03, 0x7b, 0x7d],
+        gw.recv().await.unwrap()
+    );
+
+    // Send PUSH_DATA, expect it at the server.
+    gw.push_data(0x0104, b"{}").await.unwrap();
+    assert_eq!(vec![0x02, 0x01, 0x04, 0x01], gw.recv().await.unwrap());
+    let size = timeout(testing::RECV_TIMEOUT, server_sock.recv(&mut buffer))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        gw.packet(PacketType::PushData, 0x0104, b"{}"),
+        &buffer[..size]
+    );
+}
@@ -0,0 +1,77 @@
+use std::time::Duration;
+
+use tracing_subscriber::prelude::*;
+
+use chirpstack_packet_multiplexer::config;
+use chirpstack_packet_multiplexer::packets::PacketType;
+use chirpstack_packet_multiplexer::testing::{self, FakeGateway, FakeServer};
+
+#[tokio::test]
+async fn test() {
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    // Server.
+    let server = FakeServer::new().await.unwrap();
+
+    let conf = config::Configuration {
+        multiplexer: config::Multiplexer {
+            bind: "127.0.0.1:0".into(),
+            servers: vec![config::Server {
+                retransmit: config::Retransmit {
+                    max_retries: 2,
+                    timeout_ms: 100,
+                },
+                ..server.config().unwrap()
+            }],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let addrs = testing::start(&conf).await.unwrap();
+
+    // Gateway.
+    let gw = FakeGateway::new("0102030405060708".parse().unwrap(), addrs[0])
+        .await
+        .unwrap();
+    let push_data = gw.packet(PacketType::PushData, 0x0102, b"{}");
+
+    // Send PUSH_DATA.
+    gw.push_data(0x0102, b"{}").await.unwrap();
+
+    // Expect PUSH_ACK.
+    assert_eq!(vec![0x02, 0x01, 0x02, 0x01], gw.recv().await.unwrap());
+
+    // Expect PUSH_DATA at server, which is not acknowledged.
+    let (data, _) = server.recv().await.unwrap();
+    assert_eq!(push_data, data);
+
+    // Expect the PUSH_DATA to be retransmitted with the same token.
+    let (data, addr) = server.recv().await.unwrap();
+    assert_eq!(push_data, data);
+
+    // Send PUSH_ACK from server.
+    server.ack(&data, addr).await.unwrap();
+
+    // Expect no further retransmissions.
+    server
+        .expect_none(Duration::from_millis(300))
+        .await
+        .unwrap();
+
+    // Send another PUSH_DATA which is never acknowledged.
+    gw.push_data(0x0102, b"{}").await.unwrap();
+    assert_eq!(vec![0x02, 0x01, 0x02, 0x01], gw.recv().await.unwrap());
+
+    // Expect the initial PUSH_DATA and 2 retries, nothing after that.
+    for _ in 0..3 {
+        let (data, _) = server.recv().await.unwrap();
+        assert_eq!(push_data, data);
+    }
+    server
+        .expect_none(Duration::from_millis(300))
+        .await
+        .unwrap();
+}
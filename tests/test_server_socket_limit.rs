@@ -0,0 +1,71 @@
+use std::time::Duration;
+
+use tracing_subscriber::prelude::*;
+
+use chirpstack_packet_multiplexer::config;
+use chirpstack_packet_multiplexer::packets::PacketType;
+use chirpstack_packet_multiplexer::testing::{self, FakeGateway, FakeServer};
+
+#[tokio::test]
+async fn test() {
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    // Servers.
+    let server1 = FakeServer::new().await.unwrap();
+    let server2 = FakeServer::new().await.unwrap();
+
+    let conf = config::Configuration {
+        multiplexer: config::Multiplexer {
+            bind: "127.0.0.1:0".into(),
+            servers: vec![
+                config::Server {
+                    max_sockets: 1,
+                    local_port_range: Some((1714, 1715)),
+                    ..server1.config().unwrap()
+                },
+                server2.config().unwrap(),
+            ],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let addrs = testing::start(&conf).await.unwrap();
+
+    // Gateways.
+    let gws = [
+        FakeGateway::new("0102030405060708".parse().unwrap(), addrs[0])
+            .await
+            .unwrap(),
+        FakeGateway::new("0202030405060708".parse().unwrap(), addrs[0])
+            .await
+            .unwrap(),
+    ];
+
+    // Send PUSH_DATA from both gateways.
+    for gw in &gws {
+        gw.push_data(0x0102, b"{}").await.unwrap();
+
+        // Expect PUSH_ACK.
+        assert_eq!(vec![0x02, 0x01, 0x02, 0x01], gw.recv().await.unwrap());
+    }
+
+    // Expect only the PUSH_DATA of the first gateway at server 1, sent from
+    // the first port of the local port range.
+    let (data, addr) = server1.recv().await.unwrap();
+    assert_eq!(gws[0].packet(PacketType::PushData, 0x0102, b"{}"), data);
+    assert_eq!(1714, addr.port());
+    server1
+        .expect_none(Duration::from_millis(100))
+        .await
+        .unwrap();
+
+    // Expect the PUSH_DATA of both gateways at server 2, as it has no socket
+    // limit.
+    for gw in &gws {
+        let (data, _) = server2.recv().await.unwrap();
+        assert_eq!(gw.packet(PacketType::PushData, 0x0102, b"{}"), data);
+    }
+}
@@ -0,0 +1,53 @@
+use std::net::{IpAddr, Ipv4Addr};
+
+use tracing_subscriber::prelude::*;
+
+use chirpstack_packet_multiplexer::config;
+use chirpstack_packet_multiplexer::packets::PacketType;
+use chirpstack_packet_multiplexer::testing::{self, FakeGateway, FakeServer};
+
+#[tokio::test]
+async fn test() {
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    // Server.
+    let server = FakeServer::new().await.unwrap();
+
+    let conf = config::Configuration {
+        multiplexer: config::Multiplexer {
+            bind: "127.0.0.1:0".into(),
+            recv_buffer_size: 1024 * 1024,
+            send_buffer_size: 1024 * 1024,
+            servers: vec![config::Server {
+                local_address: Some(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2))),
+                interface: "lo".into(),
+                dscp: 46,
+                recv_buffer_size: 256 * 1024,
+                send_buffer_size: 256 * 1024,
+                ..server.config().unwrap()
+            }],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let addrs = testing::start(&conf).await.unwrap();
+
+    // Gateway.
+    let gw = FakeGateway::new("0102030405060708".parse().unwrap(), addrs[0])
+        .await
+        .unwrap();
+
+    // Send PUSH_DATA.
+    gw.push_data(0x0102, b"{}").await.unwrap();
+
+    // Expect PUSH_ACK.
+    assert_eq!(vec![0x02, 0x01, 0x02, 0x01], gw.recv().await.unwrap());
+
+    // Expect PUSH_DATA forwarded to server, from the configured local address.
+    let (data, addr) = server.recv().await.unwrap();
+    assert_eq!(gw.packet(PacketType::PushData, 0x0102, b"{}"), data);
+    assert_eq!(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)), addr.ip());
+}
@@ -0,0 +1,105 @@
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use tracing_subscriber::prelude::*;
+
+use chirpstack_packet_multiplexer::cmd::simulate;
+use chirpstack_packet_multiplexer::config;
+use chirpstack_packet_multiplexer::packets::PacketType;
+use chirpstack_packet_multiplexer::testing::{self, FakeServer};
+
+#[tokio::test]
+async fn test() {
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    // Server.
+    let server = FakeServer::new().await.unwrap();
+
+    let conf = config::Configuration {
+        multiplexer: config::Multiplexer {
+            bind: "127.0.0.1:0".into(),
+            servers: vec![server.config().unwrap()],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let addrs = testing::start(&conf).await.unwrap();
+
+    let opts = simulate::Options {
+        target: addrs[0].to_string(),
+        gateways: 2,
+        gateway_id_prefix: "0102030400000000/32".parse().unwrap(),
+        keepalive_interval: Duration::from_millis(100),
+        stat_interval: Duration::from_millis(250),
+        uplink_interval: Duration::from_millis(100),
+        tx_ack_error: "TOO_LATE".into(),
+        tx_ack_error_rate: 1.0,
+        duration: Some(Duration::from_millis(450)),
+    };
+    let simulate_task = tokio::spawn(async move { simulate::run(&opts).await.unwrap() });
+
+    // Expect PULL_DATA, stat and rxpk PUSH_DATA for both gateways. Respond to
+    // the first PULL_DATA with a PULL_RESP and expect the TX_ACK with the
+    // injected error.
+    let mut gateway_ids = Vec::new();
+    let (mut stat, mut rxpk, mut pull_resp_sent) = (false, false, false);
+    let mut tx_ack = None;
+
+    while tx_ack.is_none() || !stat || !rxpk || gateway_ids.len() < 2 {
+        let (b, addr) = server.recv().await.unwrap();
+
+        if !gateway_ids.contains(&b[4..12].to_vec()) {
+            gateway_ids.push(b[4..12].to_vec());
+        }
+
+        match PacketType::try_from(b.as_slice()).unwrap() {
+            PacketType::PushData => {
+                let payload: serde_json::Value = serde_json::from_slice(&b[12..]).unwrap();
+                stat |= payload.get("stat").is_some();
+                rxpk |= payload.get("rxpk").is_some();
+            }
+            PacketType::PullData if !pull_resp_sent => {
+                server
+                    .pull_resp(0x0007, b"{\"txpk\":{}}", addr)
+                    .await
+                    .unwrap();
+                pull_resp_sent = true;
+            }
+            PacketType::TxAck => tx_ack = Some(b),
+            _ => {}
+        }
+    }
+
+    let tx_ack = tx_ack.unwrap();
+    assert_eq!(&[0x02, 0x00, 0x07, 0x05], &tx_ack[..4]);
+    assert_eq!(b"{\"txpk_ack\":{\"error\":\"TOO_LATE\"}}", &tx_ack[12..]);
+
+    gateway_ids.sort();
+    assert_eq!(
+        vec![
+            vec![0x01, 0x02, 0x03, 0x04, 0x00, 0x00, 0x00, 0x00],
+            vec![0x01, 0x02, 0x03, 0x04, 0x00, 0x00, 0x00, 0x01],
+        ],
+        gateway_ids
+    );
+
+    // All packets are acknowledged by the multiplexer, except for packets
+    // still in flight at the end of the simulation.
+    let summary = simulate_task.await.unwrap();
+    let push_data_sent = summary.push_data_sent.load(Ordering::Relaxed);
+    let pull_data_sent = summary.pull_data_sent.load(Ordering::Relaxed);
+    let push_ack_received = summary.push_ack_received.load(Ordering::Relaxed);
+    let pull_ack_received = summary.pull_ack_received.load(Ordering::Relaxed);
+    assert!(push_data_sent >= 10);
+    assert!(pull_data_sent >= 8);
+    assert!(push_ack_received <= push_data_sent && push_ack_received + 4 >= push_data_sent);
+    assert!(pull_ack_received <= pull_data_sent && pull_ack_received + 2 >= pull_data_sent);
+    assert_eq!(0, summary.missing_acks.load(Ordering::Relaxed));
+    assert_eq!(0, summary.invalid_acks.load(Ordering::Relaxed));
+    assert_eq!(1, summary.pull_resp_received.load(Ordering::Relaxed));
+    assert_eq!(1, summary.tx_ack_sent.load(Ordering::Relaxed));
+    assert_eq!(1, summary.tx_ack_errors.load(Ordering::Relaxed));
+}
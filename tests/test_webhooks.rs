@@ -0,0 +1,156 @@
+use std::time::Duration;
+
+use serde_json::Value;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+use tracing_subscriber::prelude::*;
+
+use chirpstack_packet_multiplexer::testing::{self, FakeGateway, FakeServer};
+use chirpstack_packet_multiplexer::{config, webhooks};
+
+// Accepts a single HTTP request, responds with the given status and returns
+// the signature header and the body of the request.
+async fn handle_request(http: &TcpListener, status: u16) -> (String, Vec<u8>) {
+    let (mut stream, _) = timeout(Duration::from_secs(5), http.accept())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let mut req = Vec::new();
+    let mut buffer = [0; 1024];
+    let header_end = loop {
+        let size = stream.read(&mut buffer).await.unwrap();
+        req.extend_from_slice(&buffer[..size]);
+        if let Some(i) = req.windows(4).position(|v| v == b"\r\n\r\n") {
+            break i + 4;
+        }
+    };
+
+    let headers = String::from_utf8(req[..header_end].to_vec()).unwrap();
+    assert!(headers.starts_with("POST /events HTTP/1.1\r\n"));
+
+    let mut content_length = 0;
+    let mut signature = String::new();
+    for line in headers.lines() {
+        if let Some((k, v)) = line.split_once(": ") {
+            match k.to_lowercase().as_str() {
+                "content-length" => content_length = v.parse().unwrap(),
+                "x-signature-256" => signature = v.to_string(),
+                _ => {}
+            }
+        }
+    }
+
+    while req.len() < header_end + content_length {
+        let size = stream.read(&mut buffer).await.unwrap();
+        req.extend_from_slice(&buffer[..size]);
+    }
+
+    stream
+        .write_all(
+            format!(
+                "HTTP/1.1 {} Status\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                status
+            )
+            .as_bytes(),
+        )
+        .await
+        .unwrap();
+
+    (signature, req[header_end..].to_vec())
+}
+
+#[tokio::test]
+async fn test() {
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    // HTTP stand-in for the webhook endpoint.
+    let http = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+    // Server.
+    let server = FakeServer::new().await.unwrap();
+
+    let conf = config::Configuration {
+        multiplexer: config::Multiplexer {
+            bind: "127.0.0.1:0".into(),
+            servers: vec![config::Server {
+                unreachable_after_secs: 1,
+                ..server.config().unwrap()
+            }],
+            ..Default::default()
+        },
+        webhooks: vec![config::Webhook {
+            url: format!("http://{}/events", http.local_addr().unwrap()),
+            events: vec![
+                "gateway_first_seen".into(),
+                "server_unreachable".into(),
+                "server_recovered".into(),
+                "downlink_rejected".into(),
+            ],
+            secret: "secret".into(),
+            max_retries: 1,
+            retry_interval_ms: 100,
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+
+    webhooks::setup(&conf.webhooks).await.unwrap();
+    let addrs = testing::start(&conf).await.unwrap();
+
+    // Gateway.
+    let gw = FakeGateway::new("0102030405060708".parse().unwrap(), addrs[0])
+        .await
+        .unwrap();
+
+    // Send PULL_DATA.
+    gw.pull_data(0x0102).await.unwrap();
+    let (pull_data, server_addr) = server.recv().await.unwrap();
+
+    // Expect the gateway_first_seen event, which fails the first time and is
+    // retried.
+    let (signature1, body1) = handle_request(&http, 500).await;
+    let (signature2, body2) = handle_request(&http, 200).await;
+    assert_eq!(body1, body2);
+    assert_eq!(signature1, signature2);
+    assert_eq!(webhooks::sign("secret", &body1).unwrap(), signature1);
+
+    let event: Value = serde_json::from_slice(&body1).unwrap();
+    assert_eq!("gateway_first_seen", event["event"]);
+    assert_eq!("0102030405060708", event["gateway_id"]);
+
+    // Expect the server_unreachable event, as the server did not send the
+    // PULL_ACK.
+    let (_, body) = handle_request(&http, 200).await;
+    let event: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!("server_unreachable", event["event"]);
+    assert_eq!(server.addr().unwrap().to_string(), event["server"]);
+
+    // Send PULL_ACK, expect the server_recovered event.
+    server.ack(&pull_data, server_addr).await.unwrap();
+    let (_, body) = handle_request(&http, 200).await;
+    let event: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!("server_recovered", event["event"]);
+
+    // Send PULL_RESP and TX_ACK with error, expect the downlink_rejected
+    // event.
+    server.pull_resp(0x0103, b"{}", server_addr).await.unwrap();
+    loop {
+        let data = gw.recv().await.unwrap();
+        if data[3] == 0x03 {
+            assert_eq!(vec![0x02, 0x01, 0x03, 0x03, 0x7b, 0x7d], data);
+            break;
+        }
+    }
+    gw.tx_ack(0x0103, br#"{"txpk_ack":{"error":"TOO_LATE"}}"#)
+        .await
+        .unwrap();
+
+    let (_, body) = handle_request(&http, 200).await;
+    let event: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!("downlink_rejected", event["event"]);
+    assert_eq!("TOO_LATE", event["error"]);
+}